@@ -7,10 +7,11 @@ use std::{
 
 use mime::Mime;
 use reqwest::header::CONTENT_TYPE;
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::{
     api::{BoardPosts, GlowficError, PostInBoard, Replies},
+    gen::epub::Since,
     types::{Continuity, Icon, Thread},
     utils::{
         extension_to_image_mime, guess_image_mime, http_client, mime_to_image_extension, url_hash,
@@ -178,6 +179,39 @@ impl Thread {
     }
 }
 
+/// What the last epub of a post had, so that the next delta epub can pick up from there.
+#[derive(Debug, Serialize, Deserialize)]
+struct GenerationState {
+    /// [None] if the post had no replies yet.
+    last_reply_id: Option<u64>,
+}
+
+impl Thread {
+    fn state_key(id: u64) -> PathBuf {
+        format!("{CACHE_ROOT}/state/posts/{id}.json").into()
+    }
+
+    /// Where a delta epub of the post starts to only have what is new since [Thread::record_generation],
+    /// or [None] if no epub was generated for it.
+    pub fn since_last_generation(id: u64) -> Option<Since> {
+        let data = std::fs::read(Self::state_key(id)).ok()?;
+        let state: GenerationState = serde_json::from_slice(&data).ok()?;
+        // Reply ids start at 1.
+        Some(Since::Reply(state.last_reply_id.unwrap_or(0)))
+    }
+
+    /// Remembers the replies that an epub was just generated with, for [Thread::since_last_generation].
+    pub fn record_generation(&self) -> std::io::Result<()> {
+        let state = GenerationState {
+            last_reply_id: self.replies.last().map(|reply| reply.id),
+        };
+
+        let path = Self::state_key(self.post.id);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        write_if_changed(path, serde_json::to_vec_pretty(&state).unwrap())
+    }
+}
+
 impl Continuity {
     pub async fn get_cached(
         id: u64,
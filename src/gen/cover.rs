@@ -1,11 +1,19 @@
-use std::fmt::Write;
+use std::{fmt::Write, sync::OnceLock};
 
 use crate::types::User;
 
 pub fn image(subject: &str, authors: &[User]) -> Vec<u8> {
     let svg = svg(subject, authors);
 
-    render_svg(&svg)
+    render_svg(&svg, WIDTH, HEIGHT)
+}
+
+/// A square avatar with the initials of `name` on a background color derived from `seed`.
+/// Used in place of missing icons.
+pub fn placeholder_icon(name: &str, seed: u64) -> Vec<u8> {
+    let svg = placeholder_svg(name, seed);
+
+    render_svg(&svg, ICON_SIZE, ICON_SIZE)
 }
 
 const WIDTH: u32 = 1600;
@@ -62,28 +70,66 @@ fn svg(subject: &str, authors: &[User]) -> String {
     )
 }
 
-fn render_svg(svg: &str) -> Vec<u8> {
-    let db = {
-        let mut db = fontdb::Database::new();
-        for font in FONTS {
-            db.load_font_data(font.to_vec());
-        }
-        db.set_serif_family("Cinzel");
-        db
+const ICON_SIZE: u32 = 200;
+const HALF_ICON_SIZE: u32 = ICON_SIZE / 2;
+const INITIALS_TEXT_SIZE: u32 = 80;
+
+fn placeholder_svg(name: &str, seed: u64) -> String {
+    let initials: String = name
+        .split_whitespace()
+        .filter_map(|word| word.chars().find(|c| c.is_alphanumeric()))
+        .take(2)
+        .flat_map(char::to_uppercase)
+        .collect();
+    let initials = super::transform::escape_html(&initials);
+
+    let background = placeholder_color(seed);
+    let baseline = HALF_ICON_SIZE + INITIALS_TEXT_SIZE * 7 / 20;
+
+    format!(
+        r##"<svg viewBox="0 0 {ICON_SIZE} {ICON_SIZE}" xmlns="http://www.w3.org/2000/svg">
+        <rect width="100%" height="100%" fill="{background}"/>
+        <text x="{HALF_ICON_SIZE}" y="{baseline}" font-size="{INITIALS_TEXT_SIZE}px" fill="#F5F5DF" {STYLE} class="initials">{initials}</text>
+      </svg>
+      "##
+    )
+}
+
+/// Spreads the hues using the golden angle, so that consecutive ids get distinct colors.
+/// Saturation and lightness are fixed to keep the light text readable.
+fn placeholder_color(seed: u64) -> String {
+    let hue = (seed as f64 * 137.508) % 360.0;
+    let (saturation, lightness) = (0.45, 0.4);
+
+    let chroma = (1.0 - (2.0 * lightness - 1.0_f64).abs()) * saturation;
+    let x = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let m = lightness - chroma / 2.0;
+    let (r, g, b) = match hue as u32 / 60 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
     };
+    let [r, g, b] = [r, g, b].map(|c| ((c + m) * 255.0).round() as u8);
 
+    format!("#{r:02X}{g:02X}{b:02X}")
+}
+
+fn render_svg(svg: &str, width: u32, height: u32) -> Vec<u8> {
     let tree = usvg::Tree::from_str(
         svg,
         &usvg::Options {
             font_family: "Cinzel".to_string(),
             ..Default::default()
         },
-        &db,
+        font_db(),
     )
     .unwrap();
 
     let pixmap = {
-        let mut pixmap = tiny_skia::Pixmap::new(WIDTH, HEIGHT).unwrap();
+        let mut pixmap = tiny_skia::Pixmap::new(width, height).unwrap();
         resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
         pixmap
     };
@@ -91,4 +137,16 @@ fn render_svg(svg: &str) -> Vec<u8> {
     pixmap.encode_png().unwrap()
 }
 
+fn font_db() -> &'static fontdb::Database {
+    static DB: OnceLock<fontdb::Database> = OnceLock::new();
+    DB.get_or_init(|| {
+        let mut db = fontdb::Database::new();
+        for font in FONTS {
+            db.load_font_data(font.to_vec());
+        }
+        db.set_serif_family("Cinzel");
+        db
+    })
+}
+
 const FONTS: &[&[u8]] = &[include_bytes!("../../fonts/Cinzel-VariableFont_wght.ttf")];
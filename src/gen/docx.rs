@@ -0,0 +1,882 @@
+//! Word documents (Office Open XML), which most word processors can open and edit.
+//!
+//! The document is a single `word/document.xml` of paragraphs and tables, with the images
+//! in `word/media`, the links as external relationships, and a small stylesheet.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    io::{Cursor, Write},
+};
+
+use image::ImageReader;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    intern_images::{placeholder_url, process_images, InternedImage},
+    types::{Continuity, Section, User},
+    Thread,
+};
+
+use super::{
+    plain_author_names, process_content,
+    transform::{self, ParagraphKind, RichBlock, Run},
+    Block, ImageFormat, LanguageTag, Options, SECTIONLESS_NAME,
+};
+
+/// English Metric Units per pixel, at 96 pixels per inch.
+const EMU_PER_PIXEL: u64 = 9525;
+/// One inch.
+const ICON_SIZE: u64 = 914400;
+/// Six inches, within the margins of both letter and A4 pages.
+const MAX_IMAGE_WIDTH: u64 = 6 * 914400;
+/// In twentieths of a point, per blockquote or list level.
+const INDENT: usize = 720;
+
+impl Thread {
+    /// The thread as a Word document, with the images embedded.
+    pub async fn to_docx(&self, options: Options) -> Result<Vec<u8>, Box<dyn Error>> {
+        let options = docx_options(options);
+        let images = process_images(self.images_to_intern(options).await?, options).await?;
+        let mut document = Document::new(images);
+
+        self.docx_content(&mut document, 0, options);
+
+        let post = &self.post;
+        document.finish(&CoreProperties {
+            title: &post.subject,
+            authors: &post.authors,
+            created: &post.created_at.to_rfc3339(),
+            identifier: &self.uuid(options).to_string(),
+            language: options.language,
+        })
+    }
+
+    /// The title, status, description, and all the posts and replies.
+    /// Level 0 is the title of the document, the others are headings that start a new page.
+    fn docx_content(&self, document: &mut Document, level: usize, options: Options) {
+        let post = &self.post;
+
+        document.heading(&post.subject, level);
+        document.styled_paragraph(
+            &format!("by {}", plain_author_names(&post.authors)),
+            "Subtitle",
+        );
+        document.paragraph(&[
+            Run::plain("[Status: "),
+            Run {
+                link: Some(format!("https://glowfic.com/posts/{}", post.id)),
+                ..Run::plain(&post.status)
+            },
+            Run::plain(&format!("] [{} replies]", self.replies.len())),
+        ]);
+        if let Some(description) = &post.description {
+            document.content(description, options);
+        }
+
+        for block in self.blocks() {
+            document.rule();
+            document.block(&block, options);
+        }
+    }
+}
+
+impl Continuity {
+    /// The entire board as a Word document, with the images embedded,
+    /// and a heading for each board section and thread.
+    pub async fn to_docx(&self, options: Options) -> Result<Vec<u8>, Box<dyn Error>> {
+        let options = docx_options(options);
+        let authors = self.authors();
+        let images = process_images(self.images_to_intern(options).await?, options).await?;
+        let mut document = Document::new(images);
+
+        let name = &self.board.name;
+        document.heading(name, 0);
+        document.styled_paragraph(&format!("by {}", plain_author_names(&authors)), "Subtitle");
+
+        let (sections, sectionless_threads) = self.sections();
+        let section_iter = sections
+            .iter()
+            .map(|(section, threads)| (Some(section), threads))
+            .chain((!sectionless_threads.is_empty()).then_some((None, &sectionless_threads)));
+        for (section, threads) in section_iter {
+            let level = if sections.is_empty() {
+                1
+            } else {
+                let name: &str = match section {
+                    Some(Section { name, .. }) => name,
+                    None => SECTIONLESS_NAME,
+                };
+                document.heading(name, 1);
+                2
+            };
+            for thread in threads {
+                thread.docx_content(&mut document, level, options);
+            }
+        }
+
+        document.finish(&CoreProperties {
+            title: name,
+            authors: &authors,
+            created: &self
+                .created_at()
+                .map(|date| date.to_rfc3339())
+                .unwrap_or_default(),
+            identifier: &self.uuid(options).to_string(),
+            language: options.language,
+        })
+    }
+}
+
+/// Word can't show WebP and AVIF images.
+fn docx_options(options: Options) -> Options {
+    Options {
+        image_format: ImageFormat::Compatible,
+        ..options
+    }
+}
+
+impl Run {
+    fn plain(text: &str) -> Self {
+        Run {
+            text: text.to_string(),
+            style: Default::default(),
+            link: None,
+        }
+    }
+}
+
+struct CoreProperties<'a> {
+    title: &'a str,
+    authors: &'a [User],
+    /// RFC 3339.
+    created: &'a str,
+    identifier: &'a str,
+    language: LanguageTag,
+}
+
+struct Media {
+    name: String,
+    data: Vec<u8>,
+    mime: mime::Mime,
+    relationship: String,
+    /// In pixels.
+    dimensions: (u32, u32),
+}
+
+/// The document as it is being written.
+struct Document {
+    body: Vec<String>,
+    /// The urls of the images Word supports, and their names.
+    url_map: HashMap<String, String>,
+    /// The images by name.
+    media: BTreeMap<String, Media>,
+    /// The targets of the links, and the id of their relationship.
+    links: BTreeMap<String, String>,
+    /// For the `id` of each drawing.
+    drawings: usize,
+}
+impl Document {
+    fn new(images: HashMap<String, InternedImage>) -> Self {
+        let mut media = BTreeMap::new();
+        let mut url_map = HashMap::new();
+        for (url, image) in images {
+            if !matches!(
+                (image.mime.type_(), image.mime.subtype()),
+                (mime::IMAGE, mime::BMP | mime::GIF | mime::JPEG | mime::PNG)
+            ) {
+                continue;
+            }
+            let name = image.name();
+            url_map.insert(url, name.clone());
+
+            // Deduplicated images are shared by several urls, but should only be added once.
+            if media.contains_key(&name) {
+                continue;
+            }
+            let dimensions = ImageReader::new(Cursor::new(&image.data))
+                .with_guessed_format()
+                .ok()
+                .and_then(|reader| reader.into_dimensions().ok());
+            let Some(dimensions) = dimensions else {
+                log::warn!("Could not read the dimensions of {}", image.original_url);
+                url_map.retain(|_, n| *n != name);
+                continue;
+            };
+            let relationship = format!("rIdImage{}", media.len() + 1);
+            media.insert(
+                name.clone(),
+                Media {
+                    name,
+                    data: image.data,
+                    mime: image.mime,
+                    relationship,
+                    dimensions,
+                },
+            );
+        }
+
+        Self {
+            body: vec![],
+            url_map,
+            media,
+            links: BTreeMap::new(),
+            drawings: 0,
+        }
+    }
+
+    /// Level 0 is the title, and the other levels start a new page.
+    fn heading(&mut self, text: &str, level: usize) {
+        let properties = match level {
+            0 => r#"<w:pStyle w:val="Title"/>"#.to_string(),
+            level => format!(r#"<w:pStyle w:val="Heading{level}"/><w:pageBreakBefore/>"#),
+        };
+        self.body.push(format!(
+            "<w:p><w:pPr>{properties}</w:pPr>{}</w:p>",
+            text_run(text, "")
+        ));
+    }
+
+    fn styled_paragraph(&mut self, text: &str, style: &str) {
+        self.body.push(format!(
+            r#"<w:p><w:pPr><w:pStyle w:val="{style}"/></w:pPr>{}</w:p>"#,
+            text_run(text, "")
+        ));
+    }
+
+    fn paragraph(&mut self, runs: &[Run]) {
+        let runs = self.runs(runs, "");
+        self.body.push(format!("<w:p>{runs}</w:p>"));
+    }
+
+    /// A horizontal line, between posts and replies.
+    fn rule(&mut self) {
+        self.body.push(
+            r#"<w:p><w:pPr><w:pBdr><w:bottom w:val="single" w:sz="6" w:space="1" w:color="auto"/></w:pBdr></w:pPr></w:p>"#
+                .to_string(),
+        );
+    }
+
+    /// The icon, the speaker, and the content.
+    fn block(&mut self, block: &Block, options: Options) {
+        let icon_url = block.icon.and_then(|icon| icon.url.clone()).or_else(|| {
+            options
+                .placeholder_icons
+                .then_some(placeholder_url(block.character_id?))
+        });
+        let icon = icon_url
+            .and_then(|url| self.url_map.get(&url).cloned())
+            .and_then(|name| self.drawing(&name, block.character_name, Some(ICON_SIZE)));
+        if let Some(icon) = icon {
+            self.body
+                .push(format!(r#"<w:p><w:pPr><w:keepNext/></w:pPr>{icon}</w:p>"#));
+        }
+
+        if let Some(speaker) = block.character_name.or(block.author) {
+            let mut runs = text_run(speaker, "");
+            if let (Some(_), Some(author)) = (block.character_name, block.author) {
+                runs.push_str(&text_run(
+                    &format!(" ({author})"),
+                    "<w:b w:val=\"0\"/><w:i/>",
+                ));
+            }
+            self.body.push(format!(
+                r#"<w:p><w:pPr><w:pStyle w:val="Speaker"/></w:pPr>{runs}</w:p>"#
+            ));
+        }
+
+        self.content(block.content, options);
+    }
+
+    fn content(&mut self, content: &str, options: Options) {
+        let content = process_content(content, options, &self.url_map);
+
+        for block in transform::html_to_rich_text(&content) {
+            match block {
+                RichBlock::Paragraph {
+                    runs,
+                    kind,
+                    marker,
+                    quote_depth,
+                    list_depth,
+                } => {
+                    let mut properties = match kind {
+                        ParagraphKind::Heading(_) => {
+                            r#"<w:pStyle w:val="Subheading"/>"#.to_string()
+                        }
+                        ParagraphKind::Label => r#"<w:keepNext/>"#.to_string(),
+                        ParagraphKind::Normal => String::new(),
+                    };
+                    let indent = INDENT * (quote_depth + list_depth);
+                    if indent > 0 {
+                        // The marker hangs to the left of the text.
+                        let hanging = if marker.is_some() { INDENT / 2 } else { 0 };
+                        properties.push_str(&format!(
+                            r#"<w:ind w:left="{indent}" w:hanging="{hanging}"/>"#
+                        ));
+                    }
+                    let extra = match kind {
+                        ParagraphKind::Label => "<w:b/>",
+                        _ => "",
+                    };
+                    let marker = marker
+                        .map(|marker| text_run(&format!("{marker}\t"), extra))
+                        .unwrap_or_default();
+                    let runs = self.runs(&runs, extra);
+                    self.body.push(format!(
+                        "<w:p>{}{marker}{runs}</w:p>",
+                        paragraph_properties(&properties)
+                    ));
+                }
+                RichBlock::Image {
+                    src,
+                    alt,
+                    quote_depth,
+                } => {
+                    let properties = match quote_depth {
+                        0 => String::new(),
+                        depth => format!(r#"<w:ind w:left="{}"/>"#, INDENT * depth),
+                    };
+                    let image = self.drawing(&src, alt.as_deref(), None).unwrap_or_else(|| {
+                        // Unsupported or remote images become links.
+                        let alt = format!("[{}]", alt.as_deref().unwrap_or("image"));
+                        self.runs(
+                            &[Run {
+                                link: Some(src),
+                                ..Run::plain(&alt)
+                            }],
+                            "",
+                        )
+                    });
+                    self.body.push(format!(
+                        "<w:p>{}{image}</w:p>",
+                        paragraph_properties(&properties)
+                    ));
+                }
+                RichBlock::Rule => self.rule(),
+                RichBlock::Table(rows) => {
+                    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+                    if columns == 0 {
+                        continue;
+                    }
+                    let grid = "<w:gridCol/>".repeat(columns);
+                    let mut table = vec![format!(
+                        r#"<w:tbl><w:tblPr><w:tblW w:w="0" w:type="auto"/><w:tblBorders>{}</w:tblBorders></w:tblPr><w:tblGrid>{grid}</w:tblGrid>"#,
+                        ["top", "left", "bottom", "right", "insideH", "insideV"]
+                            .map(|side| format!(
+                                r#"<w:{side} w:val="single" w:sz="4" w:space="0" w:color="auto"/>"#
+                            ))
+                            .join("")
+                    )];
+                    for cells in rows {
+                        table.push("<w:tr>".to_string());
+                        for i in 0..columns {
+                            let runs = cells
+                                .get(i)
+                                .map(|cell| self.runs(cell, ""))
+                                .unwrap_or_default();
+                            // Cells need at least one paragraph.
+                            table.push(format!("<w:tc><w:p>{runs}</w:p></w:tc>"));
+                        }
+                        table.push("</w:tr>".to_string());
+                    }
+                    table.push("</w:tbl>".to_string());
+                    self.body.push(table.join(""));
+                }
+            }
+        }
+    }
+
+    /// The runs, with the links as hyperlinks and the line breaks as `<w:br/>`.
+    /// `extra` is added to the properties of all the runs.
+    fn runs(&mut self, runs: &[Run], extra: &str) -> String {
+        let mut xml = String::new();
+        for Run { text, style, link } in runs {
+            let mut properties = extra.to_string();
+            if link.is_some() {
+                properties.push_str(r#"<w:rStyle w:val="Hyperlink"/>"#);
+            }
+            for (styled, property) in [
+                (style.strong, "<w:b/>"),
+                (style.emphasis, "<w:i/>"),
+                (style.strikethrough, "<w:strike/>"),
+                (
+                    style.code,
+                    r#"<w:rFonts w:ascii="Courier New" w:hAnsi="Courier New" w:cs="Courier New"/>"#,
+                ),
+                (style.superscript, r#"<w:vertAlign w:val="superscript"/>"#),
+                (style.subscript, r#"<w:vertAlign w:val="subscript"/>"#),
+            ] {
+                if styled {
+                    properties.push_str(property);
+                }
+            }
+            let run = text_run(text, &properties);
+
+            match link.as_ref().filter(|link| !link.starts_with('#')) {
+                Some(link) => {
+                    let count = self.links.len();
+                    let id = self
+                        .links
+                        .entry(link.clone())
+                        .or_insert_with(|| format!("rIdLink{}", count + 1));
+                    xml.push_str(&format!(r#"<w:hyperlink r:id="{id}">{run}</w:hyperlink>"#));
+                }
+                None => xml.push_str(&run),
+            }
+        }
+        xml
+    }
+
+    /// An inline image, at its size (up to [MAX_IMAGE_WIDTH]), or fit in a square of `size`.
+    fn drawing(
+        &mut self,
+        name: &str,
+        description: Option<&str>,
+        size: Option<u64>,
+    ) -> Option<String> {
+        let Media {
+            relationship,
+            dimensions: (width, height),
+            ..
+        } = self.media.get(name)?;
+        let (width, height) = (
+            (*width).max(1) as u64 * EMU_PER_PIXEL,
+            (*height).max(1) as u64 * EMU_PER_PIXEL,
+        );
+        let (cx, cy) = match size {
+            Some(size) if width >= height => (size, size * height / width),
+            Some(size) => (size * width / height, size),
+            None if width > MAX_IMAGE_WIDTH => (MAX_IMAGE_WIDTH, MAX_IMAGE_WIDTH * height / width),
+            None => (width, height),
+        };
+
+        self.drawings += 1;
+        let id = self.drawings;
+        let name = transform::escape_html(name);
+        let description = transform::escape_html(description.unwrap_or_default());
+
+        Some(format!(
+            r#"<w:r><w:drawing><wp:inline distT="0" distB="0" distL="0" distR="0"><wp:extent cx="{cx}" cy="{cy}"/><wp:docPr id="{id}" name="{name}" descr="{description}"/><a:graphic xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main"><a:graphicData uri="http://schemas.openxmlformats.org/drawingml/2006/picture"><pic:pic xmlns:pic="http://schemas.openxmlformats.org/drawingml/2006/picture"><pic:nvPicPr><pic:cNvPr id="{id}" name="{name}"/><pic:cNvPicPr/></pic:nvPicPr><pic:blipFill><a:blip r:embed="{relationship}"/><a:stretch><a:fillRect/></a:stretch></pic:blipFill><pic:spPr><a:xfrm><a:off x="0" y="0"/><a:ext cx="{cx}" cy="{cy}"/></a:xfrm><a:prstGeom prst="rect"><a:avLst/></a:prstGeom></pic:spPr></pic:pic></a:graphicData></a:graphic></wp:inline></w:drawing></w:r>"#
+        ))
+    }
+
+    /// The zipped package.
+    fn finish(self, properties: &CoreProperties) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let file_options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        let mut add = |path: &str, data: &[u8]| -> Result<(), Box<dyn Error>> {
+            zip.start_file(path, file_options)?;
+            zip.write_all(data)?;
+            Ok(())
+        };
+
+        add("[Content_Types].xml", self.content_types().as_bytes())?;
+        add("_rels/.rels", ROOT_RELATIONSHIPS.as_bytes())?;
+        add("docProps/core.xml", core_properties(properties).as_bytes())?;
+        add(
+            "word/styles.xml",
+            STYLES
+                .replace("{language}", properties.language.as_str())
+                .as_bytes(),
+        )?;
+        add(
+            "word/_rels/document.xml.rels",
+            self.relationships().as_bytes(),
+        )?;
+        add(
+            "word/document.xml",
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships" xmlns:wp="http://schemas.openxmlformats.org/drawingml/2006/wordprocessingDrawing"><w:body>
+{}
+<w:sectPr><w:pgSz w:w="12240" w:h="15840"/><w:pgMar w:top="1440" w:right="1440" w:bottom="1440" w:left="1440" w:header="720" w:footer="720" w:gutter="0"/></w:sectPr></w:body></w:document>
+"#,
+                self.body.join("\n")
+            )
+            .as_bytes(),
+        )?;
+        for Media { name, data, .. } in self.media.values() {
+            add(&format!("word/media/{name}"), data)?;
+        }
+
+        Ok(zip.finish()?.into_inner())
+    }
+
+    fn content_types(&self) -> String {
+        let extensions: BTreeMap<&str, String> = self
+            .media
+            .values()
+            .filter_map(|Media { name, mime, .. }| {
+                let (_, extension) = name.rsplit_once('.')?;
+                Some((extension, mime.to_string()))
+            })
+            .collect();
+        let defaults: Vec<String> = extensions
+            .into_iter()
+            .map(|(extension, mime)| {
+                format!(r#"<Default Extension="{extension}" ContentType="{mime}"/>"#)
+            })
+            .collect();
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+<Default Extension="xml" ContentType="application/xml"/>
+{}
+<Override PartName="/word/document.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml"/>
+<Override PartName="/word/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.wordprocessingml.styles+xml"/>
+<Override PartName="/docProps/core.xml" ContentType="application/vnd.openxmlformats-package.core-properties+xml"/>
+</Types>
+"#,
+            defaults.join("\n")
+        )
+    }
+
+    fn relationships(&self) -> String {
+        let mut relationships = vec![
+            r#"<Relationship Id="rIdStyles" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>"#.to_string(),
+        ];
+        for Media {
+            name, relationship, ..
+        } in self.media.values()
+        {
+            relationships.push(format!(
+                r#"<Relationship Id="{relationship}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/image" Target="media/{name}"/>"#
+            ));
+        }
+        for (link, id) in &self.links {
+            let link = transform::escape_html(link);
+            relationships.push(format!(
+                r#"<Relationship Id="{id}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink" Target="{link}" TargetMode="External"/>"#
+            ));
+        }
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+{}
+</Relationships>
+"#,
+            relationships.join("\n")
+        )
+    }
+}
+
+fn paragraph_properties(properties: &str) -> String {
+    match properties {
+        "" => String::new(),
+        properties => format!("<w:pPr>{properties}</w:pPr>"),
+    }
+}
+
+/// A run of the text, with the line breaks as `<w:br/>`.
+fn text_run(text: &str, properties: &str) -> String {
+    let lines: Vec<String> = text
+        .split('\n')
+        .map(|line| {
+            let line = transform::escape_html(line)
+                .replace('\t', "</w:t><w:tab/><w:t xml:space=\"preserve\">");
+            format!(r#"<w:t xml:space="preserve">{line}</w:t>"#)
+        })
+        .collect();
+    let properties = match properties {
+        "" => String::new(),
+        properties => format!("<w:rPr>{properties}</w:rPr>"),
+    };
+    format!("<w:r>{properties}{}</w:r>", lines.join("<w:br/>"))
+}
+
+fn core_properties(
+    CoreProperties {
+        title,
+        authors,
+        created,
+        identifier,
+        language,
+    }: &CoreProperties,
+) -> String {
+    let title = transform::escape_html(title);
+    let creator = transform::escape_html(
+        &authors
+            .iter()
+            .map(|User { username, .. }| username.as_str())
+            .collect::<Vec<_>>()
+            .join("; "),
+    );
+    let created = match created {
+        &"" => String::new(),
+        created => {
+            format!(r#"<dcterms:created xsi:type="dcterms:W3CDTF">{created}</dcterms:created>"#)
+        }
+    };
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<cp:coreProperties xmlns:cp="http://schemas.openxmlformats.org/package/2006/metadata/core-properties" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:dcterms="http://purl.org/dc/terms/" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
+<dc:title>{title}</dc:title>
+<dc:creator>{creator}</dc:creator>
+<dc:identifier>urn:uuid:{identifier}</dc:identifier>
+<dc:language>{language}</dc:language>
+{created}
+</cp:coreProperties>
+"#
+    )
+}
+
+const ROOT_RELATIONSHIPS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/>
+<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties" Target="docProps/core.xml"/>
+</Relationships>
+"#;
+
+const STYLES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:styles xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+<w:docDefaults>
+<w:rPrDefault><w:rPr><w:rFonts w:ascii="Georgia" w:hAnsi="Georgia" w:cs="Georgia"/><w:sz w:val="22"/><w:lang w:val="{language}"/></w:rPr></w:rPrDefault>
+<w:pPrDefault><w:pPr><w:spacing w:after="160" w:line="276" w:lineRule="auto"/></w:pPr></w:pPrDefault>
+</w:docDefaults>
+<w:style w:type="paragraph" w:default="1" w:styleId="Normal"><w:name w:val="Normal"/><w:qFormat/></w:style>
+<w:style w:type="paragraph" w:styleId="Title"><w:name w:val="Title"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:pPr><w:jc w:val="center"/><w:spacing w:before="2400" w:after="240"/></w:pPr><w:rPr><w:sz w:val="56"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Subtitle"><w:name w:val="Subtitle"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:pPr><w:jc w:val="center"/></w:pPr><w:rPr><w:i/><w:sz w:val="26"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Heading1"><w:name w:val="heading 1"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:pPr><w:keepNext/><w:spacing w:before="480" w:after="240"/><w:jc w:val="center"/><w:outlineLvl w:val="0"/></w:pPr><w:rPr><w:b/><w:sz w:val="40"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Heading2"><w:name w:val="heading 2"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:pPr><w:keepNext/><w:spacing w:before="360" w:after="240"/><w:jc w:val="center"/><w:outlineLvl w:val="1"/></w:pPr><w:rPr><w:b/><w:sz w:val="32"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Speaker"><w:name w:val="Speaker"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:pPr><w:keepNext/><w:spacing w:after="80"/></w:pPr><w:rPr><w:b/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Subheading"><w:name w:val="Subheading"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:pPr><w:keepNext/></w:pPr><w:rPr><w:b/><w:sz w:val="26"/></w:rPr></w:style>
+<w:style w:type="character" w:styleId="Hyperlink"><w:name w:val="Hyperlink"/><w:rPr><w:color w:val="0563C1"/><w:u w:val="single"/></w:rPr></w:style>
+</w:styles>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use chrono::DateTime;
+    use image::{ImageFormat as Format, RgbImage};
+    use zip::ZipArchive;
+
+    use crate::types::{Board, BoardInPost, Character, Icon, Post, Reply};
+
+    use super::*;
+
+    fn thread(id: u64, section: Option<u64>) -> Thread {
+        let user = User {
+            id: 1,
+            username: "Alice & <Bob>".to_string(),
+        };
+        Thread {
+            post: Post {
+                id,
+                authors: vec![user.clone()],
+                board: BoardInPost {
+                    id: 1,
+                    name: "Board".to_string(),
+                },
+                character: None,
+                content: "<p>Post</p>".to_string(),
+                created_at: DateTime::default(),
+                description: None,
+                icon: None,
+                num_replies: 1,
+                section: section.map(|id| Section {
+                    id,
+                    name: format!("Section {id}"),
+                    order: id,
+                }),
+                section_order: id,
+                status: "active".to_string(),
+                subject: format!(r#"Thread {id}: "Cats" & <Dogs>"#),
+                tagged_at: DateTime::default(),
+            },
+            replies: vec![Reply {
+                id: id * 1000,
+                character: Some(Character {
+                    id: 7,
+                    name: "Eve's \"twin\"".to_string(),
+                    screenname: None,
+                }),
+                character_name: None,
+                content: r#"<p>1 &lt; 2 &amp;&amp; "yes"</p><details><summary>More</summary><p>Hidden</p></details>"#.to_string(),
+                created_at: DateTime::default(),
+                icon: Some(Icon {
+                    id: 3,
+                    keyword: None,
+                    url: Some("https://example.com/icon.png".to_string()),
+                }),
+                updated_at: DateTime::default(),
+                user,
+            }],
+        }
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Cursor::new(vec![]);
+        RgbImage::new(width, height)
+            .write_to(&mut data, Format::Png)
+            .unwrap();
+        data.into_inner()
+    }
+
+    fn icon() -> InternedImage {
+        InternedImage {
+            id: Some(3),
+            original_url: "https://example.com/icon.png".to_string(),
+            mime: mime::IMAGE_PNG,
+            data: png(200, 100),
+        }
+    }
+
+    fn document_with(thread: &Thread, images: HashMap<String, InternedImage>) -> Document {
+        let mut document = Document::new(images);
+        thread.docx_content(&mut document, 0, Options::default());
+        document
+    }
+
+    #[test]
+    fn titles_names_and_content_are_escaped() {
+        let body = document_with(&thread(1, None), HashMap::new())
+            .body
+            .join("\n");
+
+        assert!(body.contains(
+            r#"<w:pStyle w:val="Title"/></w:pPr><w:r><w:t xml:space="preserve">Thread 1: &#34;Cats&#34; &#38; &#60;Dogs&#62;</w:t></w:r>"#
+        ));
+        assert!(body.contains(
+            r#"<w:r><w:t xml:space="preserve">Eve&#x27;s &#34;twin&#34;</w:t></w:r><w:r><w:rPr><w:b w:val="0"/><w:i/></w:rPr><w:t xml:space="preserve"> (Alice &#38; &#60;Bob&#62;)</w:t></w:r>"#
+        ));
+        assert!(
+            body.contains(r#"<w:t xml:space="preserve">1 &#60; 2 &#38;&#38; &#34;yes&#34;</w:t>"#)
+        );
+        assert!(!body.contains("<Dogs>") && !body.contains("<Bob>"));
+
+        let properties = core_properties(&CoreProperties {
+            title: "A & <B>",
+            authors: &[User {
+                id: 1,
+                username: "C \"D\"".to_string(),
+            }],
+            created: "",
+            identifier: "id",
+            language: LanguageTag::default(),
+        });
+        assert!(properties.contains("<dc:title>A &#38; &#60;B&#62;</dc:title>"));
+        assert!(properties.contains("<dc:creator>C &#34;D&#34;</dc:creator>"));
+    }
+
+    #[test]
+    fn details_are_indented() {
+        let body = document_with(&thread(1, None), HashMap::new()).body;
+        let last: Vec<&str> = body
+            .iter()
+            .rev()
+            .take(2)
+            .rev()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(
+            last,
+            [
+                r#"<w:p><w:pPr><w:keepNext/><w:ind w:left="720" w:hanging="0"/></w:pPr><w:r><w:rPr><w:b/></w:rPr><w:t xml:space="preserve">More</w:t></w:r></w:p>"#,
+                r#"<w:p><w:pPr><w:ind w:left="720" w:hanging="0"/></w:pPr><w:r><w:t xml:space="preserve">Hidden</w:t></w:r></w:p>"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn images_are_embedded_or_linked() {
+        let icon = icon();
+        let name = icon.name();
+        let mut document = document_with(
+            &thread(1, None),
+            HashMap::from([(icon.original_url.clone(), icon)]),
+        );
+        let body = document.body.join("\n");
+        // Icons fit in a square, keeping their ratio.
+        assert!(body.contains(&format!(
+            r#"<wp:extent cx="{ICON_SIZE}" cy="{}"/><wp:docPr id="1" name="{name}" descr="Eve&#x27;s &#34;twin&#34;"/>"#,
+            ICON_SIZE / 2
+        )));
+        assert!(body.contains(r#"<a:blip r:embed="rIdImage1"/>"#));
+
+        document.content(
+            r#"<img src="https://example.com/image.webp" alt="A &amp; B">"#,
+            Options::default(),
+        );
+        assert_eq!(
+            document.body.last().unwrap(),
+            r#"<w:p><w:hyperlink r:id="rIdLink2"><w:r><w:rPr><w:rStyle w:val="Hyperlink"/></w:rPr><w:t xml:space="preserve">[A &#38; B]</w:t></w:r></w:hyperlink></w:p>"#
+        );
+
+        let relationships = document.relationships();
+        assert!(relationships.contains(&format!(
+            r#"<Relationship Id="rIdImage1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/image" Target="media/{name}"/>"#
+        )));
+        assert!(relationships.contains(
+            r#"Id="rIdLink2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink" Target="https://example.com/image.webp" TargetMode="External"/>"#
+        ));
+        assert!(document
+            .content_types()
+            .contains(r#"<Default Extension="png" ContentType="image/png"/>"#));
+    }
+
+    #[test]
+    fn unreadable_images_are_left_out() {
+        let image = InternedImage {
+            data: b"not a png".to_vec(),
+            ..icon()
+        };
+        let document = Document::new(HashMap::from([(image.original_url.clone(), image)]));
+        assert!(document.media.is_empty());
+        assert!(document.url_map.is_empty());
+    }
+
+    #[tokio::test]
+    async fn boards_have_a_heading_per_board_section() {
+        let board = Continuity {
+            board: Board {
+                id: 1,
+                name: "Board".to_string(),
+                board_sections: vec![Section {
+                    id: 1,
+                    name: "Section 1".to_string(),
+                    order: 1,
+                }],
+            },
+            threads: vec![thread(1, Some(1)), thread(2, None)],
+        };
+        let docx = board.to_docx(Options::default()).await.unwrap();
+
+        let mut zip = ZipArchive::new(Cursor::new(docx)).unwrap();
+        let mut document = String::new();
+        zip.by_name("word/document.xml")
+            .unwrap()
+            .read_to_string(&mut document)
+            .unwrap();
+        assert!(zip.by_name("docProps/core.xml").is_ok());
+
+        let headings: Vec<String> = document
+            .split(r#"<w:pStyle w:val=""#)
+            .skip(1)
+            .filter_map(|part| {
+                let (style, rest) = part.split_once('"')?;
+                let text = rest.split_once(r#"<w:t xml:space="preserve">"#)?.1;
+                let text = text.split_once("</w:t>")?.0;
+                style
+                    .starts_with("Heading")
+                    .then(|| format!("{style}: {text}"))
+            })
+            .collect();
+        assert_eq!(
+            headings,
+            [
+                "Heading1: Section 1",
+                "Heading2: Thread 1: &#34;Cats&#34; &#38; &#60;Dogs&#62;",
+                &format!("Heading1: {SECTIONLESS_NAME}"),
+                "Heading2: Thread 2: &#34;Cats&#34; &#38; &#60;Dogs&#62;",
+            ]
+        );
+    }
+}
@@ -1,95 +1,173 @@
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeMap, HashMap},
     error::Error,
+    io::{Cursor, Read, Write},
+    ops::Range,
+    sync::OnceLock,
 };
 
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
 use epub_builder::{EpubBuilder, EpubContent, ReferenceType, ZipLibrary};
-use rand::{Rng, SeedableRng};
+use regex::Regex;
 use uuid::Uuid;
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
 
 use crate::{
+    intern_images::{process_images, InternedImage},
     types::{Continuity, Section, User},
-    Board, Post, Reply, Thread,
+    Post, Reply, Thread,
 };
 
 use super::{
-    author_names, raw_content_page, raw_copyright_page, raw_title_page, transform, Options, STYLE,
+    board_url, post_url, raw_board_copyright_page, raw_board_title_page, raw_content_page,
+    raw_copyright_page, raw_recap_page, raw_section_title_page, raw_title_page, reply_url,
+    transform, with_glowfic_links, Block, DetailsMode, EpubVersion, Location, Options,
+    SECTIONLESS_NAME, STYLE,
 };
 
 impl Continuity {
     pub async fn to_epub(&self, options: Options) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut images_to_intern = self.images_to_intern().await?;
-
-        if let Some(size) = options.resize_icons {
-            images_to_intern = images_to_intern
-                .into_iter()
-                .map(|(k, v)| {
-                    if v.is_icon() {
-                        Ok((k, v.resize_down(size)?))
-                    } else {
-                        Ok((k, v))
-                    }
-                })
-                .collect::<Result<_, Box<dyn Error>>>()?;
+        self.volume_epub(options, None).await
+    }
+
+    /// An epub for Kobo readers, to be saved as `.kepub.epub` (see [Options::kepub]).
+    pub async fn to_kepub(&self, options: Options) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.to_epub(Options {
+            kepub: true,
+            ..options
+        })
+        .await
+    }
+
+    /// The board split into numbered volumes, for readers that struggle with huge books.
+    /// Each volume has its own cover and title page, and is part of a series named after the board.
+    /// Links to posts and replies in other volumes lead to glowfic.
+    ///
+    /// Boards that fit in a single volume give the same epub as [Continuity::to_epub].
+    pub async fn to_epub_volumes(
+        &self,
+        options: Options,
+        split: VolumeSplit,
+    ) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        let threads = self.threads_in_order();
+        let volumes = split.split(&threads, options);
+        if volumes.len() <= 1 {
+            return Ok(vec![self.to_epub(options).await?]);
         }
 
-        if options.jpeg {
-            images_to_intern = images_to_intern
-                .into_iter()
-                .map(|(k, v)| (k, v.try_into_jpeg()))
-                .collect();
+        let count = volumes.len();
+        let mut epubs = vec![];
+        for (i, threads) in volumes.into_iter().enumerate() {
+            let volume = Volume {
+                number: i + 1,
+                count,
+            };
+            epubs.push(
+                self.volume(threads)
+                    .volume_epub(options, Some(volume))
+                    .await?,
+            );
+        }
+
+        Ok(epubs)
+    }
+
+    /// Volumes of epubs for Kobo readers (see [Continuity::to_epub_volumes] and [Options::kepub]).
+    pub async fn to_kepub_volumes(
+        &self,
+        options: Options,
+        split: VolumeSplit,
+    ) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+        self.to_epub_volumes(
+            Options {
+                kepub: true,
+                ..options
+            },
+            split,
+        )
+        .await
+    }
+
+    /// The board with only these threads, and the sections they are in.
+    fn volume(&self, threads: &[&Thread]) -> Continuity {
+        let mut board = self.board.clone();
+        board.board_sections.retain(|section| {
+            threads
+                .iter()
+                .any(|thread| thread.post.section.as_ref().map(|s| s.id) == Some(section.id))
+        });
+
+        Continuity {
+            board,
+            threads: threads.iter().map(|&thread| thread.clone()).collect(),
         }
+    }
 
-        let mut builder = self.core_epub(
+    async fn volume_epub(
+        &self,
+        options: Options,
+        volume: Option<Volume>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let images_to_intern =
+            process_images(self.images_to_intern(options).await?, options).await?;
+
+        let (mut builder, additions) = self.core_epub(
             options,
-            &images_to_intern
+            images_to_intern
                 .iter()
                 .map(|(url, img)| (url.clone(), img.name()))
                 .collect(),
+            volume,
         )?;
 
-        // Images
-        for (_, image) in images_to_intern {
-            builder.add_resource(image.name(), image.data.as_slice(), image.mime.to_string())?;
-        }
+        add_images(&mut builder, images_to_intern)?;
 
-        let mut file: Vec<u8> = vec![];
-        builder.generate(&mut file)?;
-
-        Ok(file)
+        generate(builder, &additions)
     }
 
     pub fn to_epub_remote_images(&self, options: Options) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut builder = self.core_epub(options, &HashMap::new())?;
-
-        let mut file: Vec<u8> = vec![];
-        builder.generate(&mut file)?;
+        let (builder, additions) = self.core_epub(options, HashMap::new(), None)?;
 
-        Ok(file)
+        generate(builder, &additions)
     }
 
     fn core_epub(
         &self,
         options: Options,
-        url_map: &HashMap<String, String>,
-    ) -> Result<EpubBuilder<ZipLibrary>, Box<dyn Error>> {
-        let mut builder = EpubBuilder::new(ZipLibrary::new()?)?;
+        url_map: HashMap<String, String>,
+        volume: Option<Volume>,
+    ) -> Result<(EpubBuilder<ZipLibrary>, Additions), Box<dyn Error>> {
+        let mut builder = new_builder(options)?;
 
         let authors: Vec<User> = self.authors();
+        let title = match volume {
+            Some(Volume { number, .. }) => format!("{} - Volume {number}", self.board.name),
+            None => self.board.name.clone(),
+        };
 
         // Metadata
         for author in &authors {
             builder.metadata("author", &author.username)?;
         }
-        builder.metadata("title", &self.board.name)?;
+        builder.metadata("title", &title)?;
+        builder.metadata("lang", options.language.as_str())?;
+        builder.metadata("subject", &self.board.name)?;
+        for (section, _) in self.sections().0 {
+            builder.metadata("subject", section.name)?;
+        }
         if let Some(created_at) = self.created_at() {
             builder.set_publication_date(created_at);
         }
         if let Some(tagged_at) = self.tagged_at() {
             builder.set_modified_date(tagged_at);
         }
-        builder.set_uuid(self.uuid());
+        builder.set_uuid(match volume {
+            Some(Volume { number, .. }) => Uuid::new_v5(
+                &options.identifier_namespace(),
+                format!("{}#volume-{number}", board_url(self.board.id)).as_bytes(),
+            ),
+            None => self.uuid(options),
+        });
 
         // CSS
         builder.stylesheet(STYLE.as_bytes())?;
@@ -97,7 +175,7 @@ impl Continuity {
         // Cover Image
         builder.add_cover_image(
             "cover.png",
-            super::cover::image(&self.board.name, &authors).as_slice(),
+            super::cover::image(&title, &authors).as_slice(),
             mime::IMAGE_PNG.to_string(),
         )?;
 
@@ -105,7 +183,13 @@ impl Continuity {
         builder.add_content(
             EpubContent::new(
                 "title.xhtml",
-                self.to_title_page(options, url_map).as_bytes(),
+                wrap_xml(
+                    &title,
+                    &raw_board_title_page(self, volume.map(|v| (v.number, v.count))),
+                    options,
+                    &url_map,
+                )
+                .as_bytes(),
             )
             .title("Title")
             .reftype(ReferenceType::TitlePage),
@@ -129,6 +213,40 @@ impl Continuity {
 
         let (sections, sectionless_threads) = self.sections();
 
+        // Where each post and reply is, so that links to them stay in the book.
+        // The replies are also numbered in reading order for the page-list.
+        let threads_with_prefix: Vec<(String, &Thread)> = sections
+            .iter()
+            .flat_map(|(section, threads)| {
+                threads
+                    .iter()
+                    .map(|thread| (format!("section_{}", section.id), *thread))
+            })
+            .chain(
+                sectionless_threads
+                    .iter()
+                    .map(|thread| ("sectionless".to_string(), *thread)),
+            )
+            .collect();
+        let posts: Vec<Location> = threads_with_prefix
+            .iter()
+            .map(|(prefix, thread)| (thread.post.id, title_path(prefix, thread.post.id)))
+            .collect();
+        let splits: HashMap<u64, ReplySplit> = threads_with_prefix
+            .iter()
+            .map(|(_, thread)| (thread.post.id, thread.split_replies(options)))
+            .collect();
+        let locations: Vec<Location> = threads_with_prefix
+            .iter()
+            .flat_map(|(prefix, thread)| {
+                thread.reply_locations(&splits[&thread.post.id], |i| {
+                    part_path(prefix, thread.post.id, i)
+                })
+            })
+            .collect();
+        let page_list = page_list(&locations, 0);
+        let url_map = &with_glowfic_links(url_map, &posts, &locations);
+
         for (section, threads) in &sections {
             let Section { id, name, .. } = section;
 
@@ -148,7 +266,8 @@ impl Continuity {
                 .unwrap();
 
             for thread in threads {
-                thread.include(&section_path, 2, &mut builder, options, url_map)?;
+                let split = &splits[&thread.post.id];
+                thread.include(&section_path, 2, split, &mut builder, options, url_map)?;
             }
         }
 
@@ -171,6 +290,7 @@ impl Continuity {
             thread.include(
                 "sectionless",
                 if sections.is_empty() { 1 } else { 2 },
+                &splits[&thread.post.id],
                 &mut builder,
                 options,
                 url_map,
@@ -187,140 +307,132 @@ impl Continuity {
             .reftype(ReferenceType::Copyright),
         )?;
 
-        Ok(builder)
-    }
-
-    fn uuid(&self) -> Uuid {
-        let seed: [[u8; 8]; 4] = [
-            *b"glowfic!",
-            self.board.id.to_be_bytes(),
-            self.created_at()
-                .as_ref()
-                .map(DateTime::timestamp)
-                .unwrap_or(0)
-                .to_be_bytes(),
-            self.tagged_at()
-                .as_ref()
-                .map(DateTime::timestamp)
-                .unwrap_or(0)
-                .to_be_bytes(),
-        ];
-        let seed: Vec<_> = seed.iter().flatten().copied().collect();
-        let uuid = rand::rngs::StdRng::from_seed(seed.try_into().unwrap()).gen();
-        Uuid::from_u128(uuid)
+        let additions = Additions {
+            page_list,
+            series: volume.map(|v| (self.board.name.clone(), v.number as u64)),
+            status: None,
+            contributors: usernames(&authors),
+        };
+        Ok((builder, additions))
     }
-}
-
-impl Continuity {
-    fn to_title_page(&self, options: Options, url_map: &HashMap<String, String>) -> String {
-        let Board { id, name, .. } = &self.board;
 
-        let authors = self.authors();
-        let author_names = author_names(&authors);
-        let author_ids: Vec<u64> = authors.iter().map(|user| user.id).collect();
-
-        let thread_count = self.threads.len();
-
-        let title_page = format!(
-            r##"
+    /// Derived from the board only, so that updated books replace the previous ones in readers.
+    pub(super) fn uuid(&self, options: Options) -> Uuid {
+        Uuid::new_v5(
+            &options.identifier_namespace(),
+            board_url(self.board.id).as_bytes(),
+        )
+    }
+}
 
-        <div class="title-page">
-            <h1 board-id="{id}">{name}</h1>
-            <h2 glowfic-ids="{author_ids:?}">by {author_names}</h2>
-            <p>[{thread_count} threads]</p>
-        </div>
+/// How [Continuity::to_epub_volumes] splits a board. Threads are never split themselves,
+/// so a single long thread can go over the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolumeSplit {
+    /// A volume per section, and one for the sectionless threads.
+    Sections,
+    /// At most this many threads per volume.
+    Threads(usize),
+    /// At most this many replies per volume.
+    Replies(usize),
+    /// At most this many bytes of html per volume. Images are not counted.
+    Bytes(usize),
+}
+impl VolumeSplit {
+    /// Groups the consecutive threads into volumes, each with at least one thread.
+    fn split<'a, 'b>(&self, threads: &'b [&'a Thread], options: Options) -> Vec<&'b [&'a Thread]> {
+        let size = |thread: &Thread| match *self {
+            Self::Sections | Self::Threads(_) => 1,
+            Self::Replies(_) => thread.replies.len(),
+            Self::Bytes(_) => thread.content_blocks(options).iter().map(String::len).sum(),
+        };
+        let limit = match *self {
+            Self::Sections => {
+                return threads
+                    .chunk_by(|a, b| a.post.section == b.post.section)
+                    .collect()
+            }
+            Self::Threads(limit) | Self::Replies(limit) | Self::Bytes(limit) => limit,
+        };
+
+        let mut volumes = vec![];
+        let (mut start, mut volume_size) = (0, 0);
+        for (i, thread) in threads.iter().enumerate() {
+            let thread_size = size(thread);
+            if i > start && volume_size + thread_size > limit {
+                volumes.push(&threads[start..i]);
+                (start, volume_size) = (i, 0);
+            }
+            volume_size += thread_size;
+        }
+        if start < threads.len() {
+            volumes.push(&threads[start..]);
+        }
 
-        "##
-        );
+        volumes
+    }
+}
 
-        wrap_xml(name, &title_page, options, url_map)
-    }
-    fn to_copyright_page(&self, options: Options, url_map: &HashMap<String, String>) -> String {
-        let Board { id, name, .. } = &self.board;
-
-        let authors = self.authors();
-        let author_names = author_names(&authors);
-        let author_ids: Vec<u64> = authors.iter().map(|user| user.id).collect();
-
-        let copyright = format!(
-            r##"
-    
-        <div class="copyright-page">
-            <h3>This was</h3>
-            <h1 board-id="{id}">{name}</h1>
-            <h2 glowfic-ids="{author_ids:?}">by {author_names}</h2>
-            <h3 class="board" board-id="{id}">in {name}</h3>
-    
-            © {author_names}
-        </div>
-    
-        "##
-        );
+/// Which of the volumes of a board a book is.
+#[derive(Debug, Clone, Copy)]
+struct Volume {
+    /// Counted from 1.
+    number: usize,
+    count: usize,
+}
 
-        wrap_xml(&format!("{name} - Copyright"), &copyright, options, url_map)
+impl Continuity {
+    pub(super) fn to_title_page(
+        &self,
+        options: Options,
+        url_map: &HashMap<String, String>,
+    ) -> String {
+        wrap_xml(
+            &self.board.name,
+            &raw_board_title_page(self, None),
+            options,
+            url_map,
+        )
+    }
+    pub(super) fn to_copyright_page(
+        &self,
+        options: Options,
+        url_map: &HashMap<String, String>,
+    ) -> String {
+        let name = &self.board.name;
+        wrap_xml(
+            &format!("{name} - Copyright"),
+            &raw_board_copyright_page(self),
+            options,
+            url_map,
+        )
     }
 }
 impl Section {
-    fn to_title_page(
+    pub(super) fn to_title_page(
         &self,
         threads: &[&Thread],
         options: Options,
         url_map: &HashMap<String, String>,
     ) -> String {
-        let Section { id, name, .. } = self;
-        let authors: Vec<User> = threads
-            .iter()
-            .flat_map(|t| t.post.authors.clone())
-            .collect::<BTreeSet<_>>()
-            .into_iter()
-            .collect();
-        let author_names = author_names(&authors);
-        let author_ids: Vec<u64> = authors.iter().map(|user| user.id).collect();
-        let thread_count = threads.len();
-
-        let title_page = format!(
-            r##"
-    
-        <div class="title-page">
-            <h1 section-id="{id}">{name}</h1>
-            <h2 glowfic-ids="{author_ids:?}">by {author_names}</h2>
-            <p>[{thread_count} threads]</p>
-        </div>
-    
-        "##
-        );
-
-        wrap_xml(name, &title_page, options, url_map)
+        wrap_xml(
+            &self.name,
+            &raw_section_title_page(Some(self), threads),
+            options,
+            url_map,
+        )
     }
-    fn sectionless_title_page(
+    pub(super) fn sectionless_title_page(
         threads: &[&Thread],
         options: Options,
         url_map: &HashMap<String, String>,
     ) -> String {
-        let name = "Unsectioned Posts";
-        let authors: Vec<User> = threads
-            .iter()
-            .flat_map(|t| t.post.authors.clone())
-            .collect::<BTreeSet<_>>()
-            .into_iter()
-            .collect();
-        let author_names = author_names(&authors);
-        let author_ids: Vec<u64> = authors.iter().map(|user| user.id).collect();
-        let thread_count = threads.len();
-
-        let title_page = format!(
-            r##"
-    
-        <div class="title-page">
-            <h1>{name}</h1>
-            <h2 glowfic-ids="{author_ids:?}">by {author_names}</h2>
-            <p>[{thread_count} threads]</p>
-        </div>
-    
-        "##
-        );
-
-        wrap_xml(name, &title_page, options, url_map)
+        wrap_xml(
+            SECTIONLESS_NAME,
+            &raw_section_title_page(None, threads),
+            options,
+            url_map,
+        )
     }
 }
 impl Thread {
@@ -328,6 +440,7 @@ impl Thread {
         &self,
         prefix: &str,
         base_level: i32,
+        split: &ReplySplit,
         builder: &mut EpubBuilder<ZipLibrary>,
         options: Options,
         url_map: &HashMap<String, String>,
@@ -340,7 +453,7 @@ impl Thread {
         builder
             .add_content(
                 EpubContent::new(
-                    format!("{prefix}_{post_path}_title.xhtml"),
+                    title_path(prefix, post_id),
                     self.to_title_page(options, url_map).as_bytes(),
                 )
                 .title(&self.post.subject)
@@ -363,18 +476,19 @@ impl Thread {
             .unwrap();
 
         // Parts
-        for (i, reply_page) in self.reply_pages(options, url_map).iter().enumerate() {
-            builder
-                .add_content(
-                    EpubContent::new(
-                        format!("{prefix}_{post_path}_part_{i}.xhtml"),
-                        reply_page.as_bytes(),
-                    )
-                    // .title(format!("Part {i}")) // No title to avoid cluttering the table of contents.
-                    .reftype(ReferenceType::Text)
-                    .level(base_level + 1),
-                )
-                .unwrap();
+        for (i, ReplyPage { label, xhtml }) in self
+            .reply_pages(split, 0, options, url_map)
+            .into_iter()
+            .enumerate()
+        {
+            let mut content = EpubContent::new(part_path(prefix, post_id, i), xhtml.as_bytes())
+                .reftype(ReferenceType::Text)
+                .level(base_level + 1);
+            // Only titled with a nested table of contents, to avoid cluttering it.
+            if options.nested_toc {
+                content = content.title(label);
+            }
+            builder.add_content(content).unwrap();
         }
 
         Ok(())
@@ -383,71 +497,69 @@ impl Thread {
 
 impl Thread {
     pub async fn to_epub(&self, options: Options) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut images_to_intern = self.images_to_intern().await?;
-
-        if let Some(size) = options.resize_icons {
-            images_to_intern = images_to_intern
-                .into_iter()
-                .map(|(k, v)| {
-                    if v.is_icon() {
-                        Ok((k, v.resize_down(size)?))
-                    } else {
-                        Ok((k, v))
-                    }
-                })
-                .collect::<Result<_, Box<dyn Error>>>()?;
-        }
-
-        if options.jpeg {
-            images_to_intern = images_to_intern
-                .into_iter()
-                .map(|(k, v)| (k, v.try_into_jpeg()))
-                .collect();
-        }
+        let images_to_intern =
+            process_images(self.images_to_intern(options).await?, options).await?;
 
-        let mut builder = self.core_epub(
+        let (mut builder, additions) = self.core_epub(
             options,
-            &images_to_intern
+            images_to_intern
                 .iter()
                 .map(|(url, img)| (url.clone(), img.name()))
                 .collect(),
         )?;
 
-        // Images
-        for (_, image) in images_to_intern {
-            builder.add_resource(image.name(), image.data.as_slice(), image.mime.to_string())?;
-        }
+        add_images(&mut builder, images_to_intern)?;
 
-        let mut file: Vec<u8> = vec![];
-        builder.generate(&mut file)?;
+        generate(builder, &additions)
+    }
 
-        Ok(file)
+    /// An epub for Kobo readers, to be saved as `.kepub.epub` (see [Options::kepub]).
+    pub async fn to_kepub(&self, options: Options) -> Result<Vec<u8>, Box<dyn Error>> {
+        self.to_epub(Options {
+            kepub: true,
+            ..options
+        })
+        .await
     }
 
     pub fn to_epub_remote_images(&self, options: Options) -> Result<Vec<u8>, Box<dyn Error>> {
-        let mut builder = self.core_epub(options, &HashMap::new())?;
-
-        let mut file: Vec<u8> = vec![];
-        builder.generate(&mut file)?;
+        let (builder, additions) = self.core_epub(options, HashMap::new())?;
 
-        Ok(file)
+        generate(builder, &additions)
     }
 
     fn core_epub(
         &self,
         options: Options,
-        url_map: &HashMap<String, String>,
-    ) -> Result<EpubBuilder<ZipLibrary>, Box<dyn Error>> {
-        let mut builder = EpubBuilder::new(ZipLibrary::new()?)?;
+        url_map: HashMap<String, String>,
+    ) -> Result<(EpubBuilder<ZipLibrary>, Additions), Box<dyn Error>> {
+        let mut builder = new_builder(options)?;
 
         // Metadata
         for author in &self.post.authors {
             builder.metadata("author", &author.username)?;
         }
         builder.metadata("title", &self.post.subject)?;
+        builder.metadata("lang", options.language.as_str())?;
+        if let Some(description) = &self.post.description {
+            builder.metadata("description", transform::html_to_text(description))?;
+        }
+        builder.metadata("subject", &self.post.board.name)?;
+        if let Some(section) = &self.post.section {
+            builder.metadata("subject", &section.name)?;
+        }
         builder.set_publication_date(self.post.created_at);
         builder.set_modified_date(self.post.tagged_at);
-        builder.set_uuid(self.uuid());
+        builder.set_uuid(self.uuid(options));
+
+        let split = self.split_replies(options);
+        let locations = self.reply_locations(&split, |i| format!("part_{i}.xhtml"));
+        let page_list = page_list(&locations, 0);
+        let url_map = &with_glowfic_links(
+            url_map,
+            &[(self.post.id, "title.xhtml".to_string())],
+            &locations,
+        );
 
         // CSS
         builder.stylesheet(STYLE.as_bytes())?;
@@ -481,10 +593,14 @@ impl Thread {
         )?;
 
         // Parts
-        for (i, reply_page) in self.reply_pages(options, url_map).iter().enumerate() {
+        for (i, ReplyPage { label, xhtml }) in self
+            .reply_pages(&split, 0, options, url_map)
+            .into_iter()
+            .enumerate()
+        {
             builder.add_content(
-                EpubContent::new(format!("part_{i}.xhtml"), reply_page.as_bytes())
-                    .title(format!("Part {i}"))
+                EpubContent::new(format!("part_{i}.xhtml"), xhtml.as_bytes())
+                    .title(label)
                     .reftype(ReferenceType::Text)
                     .level(1),
             )?;
@@ -500,24 +616,204 @@ impl Thread {
             .reftype(ReferenceType::Copyright),
         )?;
 
-        Ok(builder)
+        let post = &self.post;
+        let additions = Additions {
+            page_list,
+            // Calibre counts from 1.
+            series: Some((post.board.name.clone(), post.section_order + 1)),
+            status: Some(post.status.clone()),
+            contributors: usernames(&post.authors),
+        };
+        Ok((builder, additions))
     }
 
-    fn uuid(&self) -> Uuid {
-        let seed: [[u8; 8]; 4] = [
-            *b"glowfic!",
-            self.post.id.to_be_bytes(),
-            self.post.created_at.timestamp().to_be_bytes(),
-            self.post.tagged_at.timestamp().to_be_bytes(),
-        ];
-        let seed: Vec<_> = seed.iter().flatten().copied().collect();
-        let uuid = rand::rngs::StdRng::from_seed(seed.try_into().unwrap()).gen();
-        Uuid::from_u128(uuid)
+    /// Derived from the post only, so that updated books replace the previous ones in readers.
+    pub(super) fn uuid(&self, options: Options) -> Uuid {
+        Uuid::new_v5(
+            &options.identifier_namespace(),
+            post_url(self.post.id).as_bytes(),
+        )
+    }
+}
+
+/// Where a delta epub (see [Thread::to_delta_epub]) starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Since {
+    /// After the reply with this id.
+    Reply(u64),
+    /// After the replies posted until then.
+    Time(DateTime<Utc>),
+}
+impl Since {
+    /// Whether the reply was posted after the cut-off.
+    fn is_new(&self, reply: &Reply) -> bool {
+        match self {
+            Since::Reply(id) => reply.id > *id,
+            Since::Time(time) => reply.created_at > *time,
+        }
+    }
+}
+
+impl Thread {
+    /// An epub of only the replies after `since`, for catching up on an ongoing thread.
+    /// It starts with a recap naming the thread and quoting the end of the last post or reply read.
+    ///
+    /// [None] if there are no new replies.
+    pub async fn to_delta_epub(
+        &self,
+        options: Options,
+        since: Since,
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let read = self
+            .replies
+            .iter()
+            .take_while(|reply| !since.is_new(reply))
+            .count();
+        if read == self.replies.len() {
+            return Ok(None);
+        }
+
+        let delta = Thread {
+            post: self.post.clone(),
+            replies: self.replies[read..].to_vec(),
+        };
+        // The post comes first, so this is the post when no replies were read.
+        let last_read = self.blocks().nth(read).unwrap();
+
+        let images_to_intern =
+            process_images(delta.images_to_intern(options).await?, options).await?;
+
+        let (mut builder, additions) = delta.delta_epub(
+            &last_read,
+            read,
+            options,
+            images_to_intern
+                .iter()
+                .map(|(url, img)| (url.clone(), img.name()))
+                .collect(),
+        )?;
+
+        add_images(&mut builder, images_to_intern)?;
+
+        generate(builder, &additions).map(Some)
+    }
+
+    /// The book of a thread that only has the new replies, the first of which is the `read`th of the thread.
+    fn delta_epub(
+        &self,
+        last_read: &Block,
+        read: usize,
+        options: Options,
+        url_map: HashMap<String, String>,
+    ) -> Result<(EpubBuilder<ZipLibrary>, Additions), Box<dyn Error>> {
+        let mut builder = new_builder(options)?;
+        let title = format!("{} - New replies", self.post.subject);
+
+        // Metadata
+        for author in &self.post.authors {
+            builder.metadata("author", &author.username)?;
+        }
+        builder.metadata("title", &title)?;
+        builder.metadata("lang", options.language.as_str())?;
+        builder.metadata("subject", &self.post.board.name)?;
+        if let Some(section) = &self.post.section {
+            builder.metadata("subject", &section.name)?;
+        }
+        builder.set_publication_date(self.replies[0].created_at);
+        builder.set_modified_date(self.post.tagged_at);
+        // Regenerating the delta from the same reply replaces it, without replacing the whole book.
+        builder.set_uuid(Uuid::new_v5(
+            &options.identifier_namespace(),
+            reply_url(self.replies[0].id).as_bytes(),
+        ));
+
+        let split = self.split_replies(options);
+        let locations = self.reply_locations(&split, |i| format!("part_{i}.xhtml"));
+        let page_list = page_list(&locations, read);
+        // The post and the replies already read are left on glowfic.
+        let url_map = &with_glowfic_links(url_map, &[], &locations);
+
+        // CSS
+        builder.stylesheet(STYLE.as_bytes())?;
+
+        // Cover Image
+        builder.add_cover_image(
+            "cover.png",
+            super::cover::image(&title, &self.post.authors).as_slice(),
+            mime::IMAGE_PNG.to_string(),
+        )?;
+
+        // Recap
+        builder.add_content(
+            EpubContent::new(
+                "recap.xhtml",
+                wrap_xml(
+                    &title,
+                    &raw_recap_page(&self.post, last_read, self.replies.len()),
+                    options,
+                    url_map,
+                )
+                .as_bytes(),
+            )
+            .title("Recap")
+            .reftype(ReferenceType::TitlePage),
+        )?;
+
+        // Parts
+        for (i, ReplyPage { label, xhtml }) in self
+            .reply_pages(&split, read, options, url_map)
+            .into_iter()
+            .enumerate()
+        {
+            builder.add_content(
+                EpubContent::new(format!("part_{i}.xhtml"), xhtml.as_bytes())
+                    .title(label)
+                    .reftype(ReferenceType::Text)
+                    .level(1),
+            )?;
+        }
+
+        // Copyright
+        builder.add_content(
+            EpubContent::new(
+                "copyright.xhtml",
+                self.to_copyright_page(options, url_map).as_bytes(),
+            )
+            .title("Copyright")
+            .reftype(ReferenceType::Copyright),
+        )?;
+
+        let post = &self.post;
+        let additions = Additions {
+            page_list,
+            // Not part of the series, it would take the place of the whole book.
+            series: None,
+            status: Some(post.status.clone()),
+            contributors: usernames(&post.authors),
+        };
+        Ok((builder, additions))
     }
 }
 
+/// The content blocks of the replies of a thread, and the range of replies on each page.
+pub(super) struct ReplySplit {
+    blocks: Vec<String>,
+    pages: Vec<Range<usize>>,
+}
+
+/// A page of replies.
+pub(super) struct ReplyPage {
+    /// For the table of contents.
+    pub label: String,
+    pub xhtml: String,
+}
+
 impl Thread {
-    fn to_title_page(&self, options: Options, url_map: &HashMap<String, String>) -> String {
+    pub(super) fn to_title_page(
+        &self,
+        options: Options,
+        url_map: &HashMap<String, String>,
+    ) -> String {
         wrap_xml(
             &self.post.subject,
             &raw_title_page(&self.post, self.replies.len()),
@@ -525,7 +821,11 @@ impl Thread {
             url_map,
         )
     }
-    fn description_page(&self, options: Options, url_map: &HashMap<String, String>) -> String {
+    pub(super) fn description_page(
+        &self,
+        options: Options,
+        url_map: &HashMap<String, String>,
+    ) -> String {
         let subject = &self.post.subject;
         wrap_xml(
             &format!("{subject} - Description"),
@@ -534,27 +834,70 @@ impl Thread {
             url_map,
         )
     }
-    fn reply_pages(&self, options: Options, url_map: &HashMap<String, String>) -> Vec<String> {
-        let subject = &self.post.subject;
-        let mut pages = vec![];
-
-        let replies: Vec<String> = self
+    /// The replies, split into pages by [Options::page_split].
+    pub(super) fn split_replies(&self, options: Options) -> ReplySplit {
+        let blocks: Vec<String> = self
             .replies
             .iter()
             .map(|reply| Reply::content_block(reply, options))
             .collect();
-        for (i, chunk) in replies.chunks(30).enumerate() {
-            pages.push(wrap_xml(
-                &format!("{subject} - Section {i}"),
-                &raw_content_page(chunk),
-                options,
-                url_map,
-            ));
+        let pages = options.page_split.split(&blocks);
+
+        ReplySplit { blocks, pages }
+    }
+    /// The id of each reply and where its anchor is, given the path of each page of replies.
+    fn reply_locations(
+        &self,
+        split: &ReplySplit,
+        page_path: impl Fn(usize) -> String,
+    ) -> Vec<Location> {
+        let mut locations = vec![];
+        for (i, page) in split.pages.iter().enumerate() {
+            let path = page_path(i);
+            for reply in &self.replies[page.clone()] {
+                locations.push((reply.id, format!("{path}#reply-{}", reply.id)));
+            }
         }
 
-        pages
+        locations
     }
-    fn to_copyright_page(&self, options: Options, url_map: &HashMap<String, String>) -> String {
+
+    /// The pages of replies, with their [Options::part_labels].
+    /// `first` is the position of the first reply in the whole thread (counting from 0),
+    /// as delta epubs only have the new replies.
+    pub(super) fn reply_pages(
+        &self,
+        split: &ReplySplit,
+        first: usize,
+        options: Options,
+        url_map: &HashMap<String, String>,
+    ) -> Vec<ReplyPage> {
+        let subject = &self.post.subject;
+
+        split
+            .pages
+            .iter()
+            .enumerate()
+            .map(|(i, page)| {
+                let replies = &self.replies[page.clone()];
+                let label = options.part_labels.label(i, first + page.start, replies);
+                ReplyPage {
+                    xhtml: wrap_xml(
+                        &format!("{subject} - {label}"),
+                        &raw_content_page(&split.blocks[page.clone()]),
+                        options,
+                        url_map,
+                    ),
+                    label,
+                }
+            })
+            .collect()
+    }
+    pub(super) fn to_copyright_page(
+        &self,
+        options: Options,
+        url_map: &HashMap<String, String>,
+    ) -> String {
         let subject = &self.post.subject;
         wrap_xml(
             &format!("{subject} - Copyright"),
@@ -565,6 +908,276 @@ impl Thread {
     }
 }
 
+fn new_builder(options: Options) -> Result<EpubBuilder<ZipLibrary>, Box<dyn Error>> {
+    let mut builder = EpubBuilder::new(ZipLibrary::new()?)?;
+    builder.epub_version(match options.epub_version {
+        EpubVersion::Epub2 => epub_builder::EpubVersion::V20,
+        EpubVersion::Epub3 => epub_builder::EpubVersion::V30,
+    });
+    Ok(builder)
+}
+
+/// Adds the interned images as resources.
+/// Deduplicated images are shared by several urls, but should only be added once.
+fn add_images(
+    builder: &mut EpubBuilder<ZipLibrary>,
+    images: HashMap<String, InternedImage>,
+) -> Result<(), Box<dyn Error>> {
+    let images: BTreeMap<String, InternedImage> = images
+        .into_values()
+        .map(|image| (image.name(), image))
+        .collect();
+
+    for (name, image) in images {
+        builder.add_resource(name, image.data.as_slice(), image.mime.to_string())?;
+    }
+
+    Ok(())
+}
+
+fn title_path(prefix: &str, post_id: u64) -> String {
+    format!("{prefix}_post_{post_id}_title.xhtml")
+}
+
+fn part_path(prefix: &str, post_id: u64, i: usize) -> String {
+    format!("{prefix}_post_{post_id}_part_{i}.xhtml")
+}
+
+/// An entry of the page-list, which readers use to go to a "page" (here, a reply) by its number.
+struct PageTarget {
+    number: usize,
+    href: String,
+}
+
+/// The replies, numbered in reading order from 1, or from after the `read` replies left out of a delta epub.
+fn page_list(locations: &[(u64, String)], read: usize) -> Vec<PageTarget> {
+    locations
+        .iter()
+        .enumerate()
+        .map(|(i, (_, href))| PageTarget {
+            number: read + i + 1,
+            href: href.clone(),
+        })
+        .collect()
+}
+
+/// What epub-builder doesn't support, added by [generate].
+struct Additions {
+    page_list: Vec<PageTarget>,
+    /// The name of the series, and the position of the book in it.
+    series: Option<(String, u64)>,
+    status: Option<String>,
+    /// The authors, who all wrote replies.
+    contributors: Vec<String>,
+}
+
+fn usernames(users: &[User]) -> Vec<String> {
+    users.iter().map(|user| user.username.clone()).collect()
+}
+
+/// An edit of a file generated by epub-builder.
+type Edit = fn(&str, &Additions) -> Result<String, Box<dyn Error>>;
+
+/// Generates the epub, and adds what epub-builder doesn't support:
+/// the page-list to the NCX and, in EPUB3, to the navigation document,
+/// and the series, status, and contributors to the package document.
+fn generate(
+    mut builder: EpubBuilder<ZipLibrary>,
+    additions: &Additions,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut file: Vec<u8> = vec![];
+    builder.generate(&mut file)?;
+
+    let mut archive = ZipArchive::new(Cursor::new(file))?;
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for i in 0..archive.len() {
+        let name = archive.by_index_raw(i)?.name().to_string();
+        let edit: Option<Edit> = match name.as_str() {
+            "OEBPS/toc.ncx" if !additions.page_list.is_empty() => {
+                Some(|ncx, additions| ncx_with_page_list(ncx, &additions.page_list))
+            }
+            "OEBPS/nav.xhtml" => Some(|nav, additions| edit_nav(nav, &additions.page_list)),
+            "OEBPS/content.opf" => Some(opf_with_metadata),
+            _ => None,
+        };
+        match edit {
+            Some(edit) => {
+                let mut content = String::new();
+                archive.by_index(i)?.read_to_string(&mut content)?;
+                zip.start_file(
+                    name,
+                    FileOptions::default().compression_method(CompressionMethod::Deflated),
+                )?;
+                zip.write_all(edit(&content, additions)?.as_bytes())?;
+            }
+            // Keeps the `mimetype` first and uncompressed.
+            None => zip.raw_copy_file(archive.by_index_raw(i)?)?,
+        }
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
+
+/// The series is written both the way Calibre reads it and, in EPUB3, as a collection.
+/// The status has no standard property, so it is a `glowfic:status` meta.
+fn opf_with_metadata(opf: &str, additions: &Additions) -> Result<String, Box<dyn Error>> {
+    let epub3 = opf.contains(r#"version="3.0""#);
+    let mut metadata = vec![];
+
+    for (i, name) in additions.contributors.iter().enumerate() {
+        let name = transform::escape_html(name);
+        if epub3 {
+            metadata.push(format!(
+                r#"<dc:contributor id="epub-contributor-{i}">{name}</dc:contributor>"#
+            ));
+            metadata.push(format!(
+                r##"<meta refines="#epub-contributor-{i}" property="role" scheme="marc:relators">ctb</meta>"##
+            ));
+        } else {
+            metadata.push(format!(
+                r#"<dc:contributor opf:role="ctb">{name}</dc:contributor>"#
+            ));
+        }
+    }
+    if let Some((name, index)) = &additions.series {
+        let name = transform::escape_html(name);
+        metadata.push(format!(r#"<meta name="calibre:series" content="{name}"/>"#));
+        metadata.push(format!(
+            r#"<meta name="calibre:series_index" content="{index}"/>"#
+        ));
+        if epub3 {
+            metadata.push(format!(
+                r#"<meta property="belongs-to-collection" id="epub-series">{name}</meta>"#
+            ));
+            metadata.push(
+                r##"<meta refines="#epub-series" property="collection-type">series</meta>"##
+                    .to_string(),
+            );
+            metadata.push(format!(
+                r##"<meta refines="#epub-series" property="group-position">{index}</meta>"##
+            ));
+        }
+    }
+    if let Some(status) = &additions.status {
+        let status = transform::escape_html(status);
+        metadata.push(format!(
+            r#"<meta name="glowfic:status" content="{status}"/>"#
+        ));
+    }
+
+    if metadata.is_empty() {
+        return Ok(opf.to_string());
+    }
+    let metadata: Vec<String> = metadata
+        .iter()
+        .map(|line| format!("    {line}\n"))
+        .collect();
+    replace_once(
+        opf,
+        "  </metadata>",
+        &format!("{}  </metadata>", metadata.concat()),
+    )
+}
+
+/// The page targets are played after the navigation points.
+fn ncx_with_page_list(ncx: &str, page_list: &[PageTarget]) -> Result<String, Box<dyn Error>> {
+    let nav_points = ncx.matches("<navPoint ").count();
+    let targets: Vec<String> = page_list
+        .iter()
+        .enumerate()
+        .map(|(i, PageTarget { number, href })| {
+            let href = transform::escape_html(href);
+            format!(
+                r#"    <pageTarget id="page-{number}" type="normal" value="{number}" playOrder="{}"><navLabel><text>{number}</text></navLabel><content src="{href}"/></pageTarget>"#,
+                nav_points + i + 1,
+            )
+        })
+        .collect();
+
+    let count = page_list.len();
+    let max = page_list.last().map_or(0, |target| target.number);
+    let ncx = replace_once(
+        ncx,
+        r#"<meta name="dtb:totalPageCount" content="0" />"#,
+        &format!(r#"<meta name="dtb:totalPageCount" content="{count}" />"#),
+    )?;
+    let ncx = replace_once(
+        &ncx,
+        r#"<meta name="dtb:maxPageNumber" content="0" />"#,
+        &format!(r#"<meta name="dtb:maxPageNumber" content="{max}" />"#),
+    )?;
+    replace_once(
+        &ncx,
+        "</ncx>",
+        &format!(
+            "  <pageList>\n    <navLabel><text>Replies</text></navLabel>\n{}\n  </pageList>\n</ncx>",
+            targets.join("\n")
+        ),
+    )
+}
+
+/// Only EPUB3 navigation documents have landmarks and a page-list.
+fn edit_nav(nav: &str, page_list: &[PageTarget]) -> Result<String, Box<dyn Error>> {
+    if !nav.contains("xmlns:epub") {
+        return Ok(nav.to_string());
+    }
+
+    let nav = unique_landmarks(nav);
+    if page_list.is_empty() {
+        return Ok(nav);
+    }
+
+    let targets: Vec<String> = page_list
+        .iter()
+        .map(|PageTarget { number, href }| {
+            let href = transform::escape_html(href);
+            format!(r#"      <li><a href="{href}">{number}</a></li>"#)
+        })
+        .collect();
+
+    replace_once(
+        &nav,
+        "</body>",
+        &format!(
+            "  <nav epub:type=\"page-list\" hidden=\"\">\n    <ol>\n{}\n    </ol>\n  </nav>\n</body>",
+            targets.join("\n")
+        ),
+    )
+}
+
+/// Replaces the first occurrence of `from`, which epub-builder is expected to have written.
+fn replace_once(text: &str, from: &str, to: &str) -> Result<String, Box<dyn Error>> {
+    if !text.contains(from) {
+        return Err(format!("{from:?} not found in the output of epub-builder").into());
+    }
+    Ok(text.replacen(from, to, 1))
+}
+
+/// epub-builder lists every titled page as a landmark, so there is one for each title page and chapter.
+/// Only the first landmark of each type is kept, like the start of the body matter.
+fn unique_landmarks(nav: &str) -> String {
+    static LANDMARK: OnceLock<Regex> = OnceLock::new();
+    let landmark = LANDMARK.get_or_init(|| Regex::new(r#"<li><a epub:type="([^"]*)""#).unwrap());
+
+    let mut seen = vec![];
+    nav.lines()
+        .filter(|line| match landmark.captures(line) {
+            Some(captures) => {
+                let epub_type = captures[1].to_string();
+                let first = !seen.contains(&epub_type);
+                seen.push(epub_type);
+                first
+            }
+            None => true,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Keeps the wrappers of kepub pages from adding margins.
+const KOBO_STYLE: &str = r##"
+        <style type="text/css">div#book-inner { margin-top: 0; margin-bottom: 0; }</style>"##;
+
 fn wrap_xml(
     subject: &str,
     content: &str,
@@ -572,18 +1185,53 @@ fn wrap_xml(
     url_map: &HashMap<String, String>,
 ) -> String {
     let subject = transform::escape_html(subject);
+    let language = options.language;
     let content = super::process_content(content, options, url_map); // We process the content before it is turned into xml.
-    let content = transform::html_to_xml(&content);
+    let content = transform::add_reply_anchors(&content);
+    let content = if options.link_footnotes {
+        transform::add_link_footnotes(&content)
+    } else {
+        content
+    };
+    let content = match options.details {
+        DetailsMode::Footnotes => transform::details_to_footnotes(&content),
+        DetailsMode::Keep | DetailsMode::Flatten => content,
+    };
+    let (content, doctype, epub_namespace, charset) = match options.epub_version {
+        EpubVersion::Epub2 => (
+            content,
+            "",
+            "",
+            r#"<meta http-equiv="Content-Type" content="text/html; charset=utf-8"/>"#,
+        ),
+        EpubVersion::Epub3 => (
+            transform::add_epub_semantics(&content),
+            "\n<!DOCTYPE html>",
+            r#" xmlns:epub="http://www.idpf.org/2007/ops""#,
+            r#"<meta charset="utf-8"/>"#,
+        ),
+    };
+    let (content, kobo_style) = if options.kepub {
+        (
+            format!(
+                r##"<div id="book-columns"><div id="book-inner">{}</div></div>"##,
+                transform::html_to_kepub_xml(&content)
+            ),
+            KOBO_STYLE,
+        )
+    } else {
+        (transform::html_to_xml(&content), "")
+    };
 
     format!(
-        r##"<?xml version='1.0' encoding='utf-8'?>
-<html xmlns="http://www.w3.org/1999/xhtml" lang="en" xml:lang="en">
+        r##"<?xml version='1.0' encoding='utf-8'?>{doctype}
+<html xmlns="http://www.w3.org/1999/xhtml"{epub_namespace} lang="{language}" xml:lang="{language}">
     <head>
         <meta name="viewport" content="width=device-width, initial-scale=1.0"/>
         <meta name="theme-color" content="#000000"/>
         <title>{subject}</title>
-        <meta http-equiv="Content-Type" content="text/html; charset=utf-8"/>
-        <link rel="stylesheet" type="text/css" href="stylesheet.css"/>
+        {charset}
+        <link rel="stylesheet" type="text/css" href="stylesheet.css"/>{kobo_style}
     </head>
     <body>
 
@@ -595,3 +1243,147 @@ fn wrap_xml(
     "##
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::types::BoardInPost;
+
+    use super::*;
+
+    #[test]
+    fn only_the_first_landmark_of_each_type_is_kept() {
+        let nav = r#"  <nav epub:type = "landmarks">
+    <ol>
+      <li><a epub:type="titlepage" href="title.xhtml">Title</a></li>
+      <li><a epub:type="bodymatter" href="section_1_title.xhtml">First Section</a></li>
+      <li><a epub:type="bodymatter" href="section_2_title.xhtml">Second Section</a></li>
+      <li><a epub:type="titlepage" href="section_1_post_100_title.xhtml">Thread</a></li>
+      <li><a epub:type="copyright-page" href="copyright.xhtml">Copyright</a></li>
+    </ol>
+  </nav>"#;
+
+        assert_eq!(
+            unique_landmarks(nav),
+            r#"  <nav epub:type = "landmarks">
+    <ol>
+      <li><a epub:type="titlepage" href="title.xhtml">Title</a></li>
+      <li><a epub:type="bodymatter" href="section_1_title.xhtml">First Section</a></li>
+      <li><a epub:type="copyright-page" href="copyright.xhtml">Copyright</a></li>
+    </ol>
+  </nav>"#
+        );
+    }
+
+    #[test]
+    fn other_lines_are_kept() {
+        let nav = r#"<nav epub:type = "toc" id="toc">
+  <li><a href="title.xhtml">Title</a></li>
+  <li><a href="title.xhtml">Title</a></li>
+</nav>"#;
+        assert_eq!(unique_landmarks(nav), nav);
+    }
+
+    fn thread(id: u64, section: Option<u64>, replies: usize) -> Thread {
+        let user = User {
+            id: 1,
+            username: "alice".to_string(),
+        };
+        let section = section.map(|id| Section {
+            id,
+            name: format!("Section {id}"),
+            order: id,
+        });
+        Thread {
+            post: Post {
+                id,
+                authors: vec![user.clone()],
+                board: BoardInPost {
+                    id: 1,
+                    name: "Board".to_string(),
+                },
+                character: None,
+                content: "<p>Post</p>".to_string(),
+                created_at: DateTime::default(),
+                description: None,
+                icon: None,
+                num_replies: replies as u64,
+                section,
+                section_order: id,
+                status: "active".to_string(),
+                subject: format!("Thread {id}"),
+                tagged_at: DateTime::default(),
+            },
+            replies: (0..replies as u64)
+                .map(|i| Reply {
+                    id: id * 1000 + i,
+                    character: None,
+                    character_name: None,
+                    content: "<p>Reply</p>".to_string(),
+                    created_at: DateTime::default(),
+                    icon: None,
+                    updated_at: DateTime::default(),
+                    user: user.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The ids of the threads of each volume.
+    fn volumes(split: VolumeSplit, threads: &[Thread]) -> Vec<Vec<u64>> {
+        let threads: Vec<&Thread> = threads.iter().collect();
+        split
+            .split(&threads, Options::default())
+            .iter()
+            .map(|volume| volume.iter().map(|thread| thread.post.id).collect())
+            .collect()
+    }
+
+    #[test]
+    fn volumes_fill_up_to_the_limit() {
+        let threads: Vec<Thread> = (1..=5).map(|id| thread(id, None, 2)).collect();
+        assert_eq!(
+            volumes(VolumeSplit::Threads(2), &threads),
+            [vec![1, 2], vec![3, 4], vec![5]]
+        );
+        assert_eq!(
+            volumes(VolumeSplit::Replies(5), &threads),
+            [vec![1, 2], vec![3, 4], vec![5]]
+        );
+        assert!(volumes(VolumeSplit::Threads(2), &[]).is_empty());
+    }
+
+    #[test]
+    fn oversized_threads_get_a_volume_of_their_own() {
+        let threads = [thread(1, None, 1), thread(2, None, 10), thread(3, None, 1)];
+        assert_eq!(
+            volumes(VolumeSplit::Replies(5), &threads),
+            [vec![1], vec![2], vec![3]]
+        );
+    }
+
+    #[test]
+    fn a_limit_of_zero_puts_each_thread_in_its_own_volume() {
+        let threads: Vec<Thread> = (1..=3).map(|id| thread(id, None, 2)).collect();
+        for split in [
+            VolumeSplit::Threads(0),
+            VolumeSplit::Replies(0),
+            VolumeSplit::Bytes(0),
+        ] {
+            assert_eq!(volumes(split, &threads), [vec![1], vec![2], vec![3]]);
+        }
+    }
+
+    #[test]
+    fn sections_make_volumes() {
+        let threads = [
+            thread(1, Some(1), 1),
+            thread(2, Some(1), 1),
+            thread(3, Some(2), 1),
+            thread(4, None, 1),
+        ];
+        assert_eq!(
+            volumes(VolumeSplit::Sections, &threads),
+            [vec![1, 2], vec![3], vec![4]]
+        );
+    }
+}
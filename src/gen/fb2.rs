@@ -0,0 +1,615 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+
+use crate::{
+    intern_images::{placeholder_url, process_images, InternedImage},
+    types::{Continuity, Section, User},
+    Thread,
+};
+
+use super::{
+    plain_author_names, process_content,
+    transform::{self, ParagraphKind, RichBlock, Run},
+    Block, ImageFormat, LanguageTag, Options, SECTIONLESS_NAME,
+};
+
+impl Thread {
+    /// The thread as a FictionBook 2 file, with the images embedded.
+    pub async fn to_fb2(&self, options: Options) -> Result<String, Box<dyn Error>> {
+        let options = fb2_options(options);
+        let images = Fb2Images::new(
+            process_images(self.images_to_intern(options).await?, options).await?,
+            super::cover::image(&self.post.subject, &self.post.authors),
+        );
+
+        let post = &self.post;
+        let description = post
+            .description
+            .as_deref()
+            .map(|description| fb2_content(description, options, &images.url_map))
+            .unwrap_or_default();
+
+        let title_info = TitleInfo {
+            title: &post.subject,
+            authors: &post.authors,
+            annotation: &description,
+            date: &post.created_at.format("%Y-%m-%d").to_string(),
+            language: options.language,
+        };
+        let body = format!(
+            "<body>{}{}</body>",
+            fb2_title(&[
+                &post.subject,
+                &format!("by {}", plain_author_names(&post.authors))
+            ]),
+            self.fb2_section(options, &images.url_map)
+        );
+
+        Ok(fb2_document(
+            &title_info,
+            &self.uuid(options).to_string(),
+            &body,
+            &images,
+        ))
+    }
+
+    /// The title, status, description, and all the posts and replies.
+    fn fb2_section(&self, options: Options, url_map: &HashMap<String, String>) -> String {
+        let post = &self.post;
+        let reply_count = self.replies.len();
+
+        let mut section = vec![
+            "<section>".to_string(),
+            fb2_title(&[&post.subject]),
+            format!(
+                "<p><emphasis>by {}</emphasis></p>",
+                transform::escape_html(&plain_author_names(&post.authors))
+            ),
+            format!(
+                r#"<p>[Status: <a l:href="https://glowfic.com/posts/{}">{}</a>] [{reply_count} replies]</p>"#,
+                post.id,
+                transform::escape_html(&post.status)
+            ),
+        ];
+        if let Some(description) = &post.description {
+            section.push(fb2_content(description, options, url_map));
+        }
+
+        for block in self.blocks() {
+            section.push(SEPARATOR.to_string());
+            section.push(fb2_block(&block, options, url_map));
+        }
+        section.push("</section>".to_string());
+
+        section.join("\n")
+    }
+}
+
+impl Continuity {
+    /// The entire board as a FictionBook 2 file, with the images embedded,
+    /// and a section for each board section and thread.
+    pub async fn to_fb2(&self, options: Options) -> Result<String, Box<dyn Error>> {
+        let options = fb2_options(options);
+        let authors = self.authors();
+        let images = Fb2Images::new(
+            process_images(self.images_to_intern(options).await?, options).await?,
+            super::cover::image(&self.board.name, &authors),
+        );
+
+        let name = &self.board.name;
+        let mut body = vec![
+            "<body>".to_string(),
+            fb2_title(&[name, &format!("by {}", plain_author_names(&authors))]),
+        ];
+
+        let (sections, sectionless_threads) = self.sections();
+        let section_iter = sections
+            .iter()
+            .map(|(section, threads)| (Some(section), threads))
+            .chain((!sectionless_threads.is_empty()).then_some((None, &sectionless_threads)));
+        for (section, threads) in section_iter {
+            let nested = !sections.is_empty();
+            if nested {
+                let name: &str = match section {
+                    Some(Section { name, .. }) => name,
+                    None => SECTIONLESS_NAME,
+                };
+                body.push("<section>".to_string());
+                body.push(fb2_title(&[name]));
+            }
+            for thread in threads {
+                body.push(thread.fb2_section(options, &images.url_map));
+            }
+            if nested {
+                body.push("</section>".to_string());
+            }
+        }
+        body.push("</body>".to_string());
+
+        let title_info = TitleInfo {
+            title: name,
+            authors: &authors,
+            annotation: "",
+            date: &self
+                .created_at()
+                .map(|date| date.format("%Y-%m-%d").to_string())
+                .unwrap_or_default(),
+            language: options.language,
+        };
+
+        Ok(fb2_document(
+            &title_info,
+            &self.uuid(options).to_string(),
+            &body.join("\n"),
+            &images,
+        ))
+    }
+}
+
+/// Goes between posts and replies, like the `<hr>` in other formats.
+const SEPARATOR: &str = "<subtitle>* * *</subtitle>";
+
+/// FictionBook readers only support png, jpeg, and sometimes gif images.
+fn fb2_options(options: Options) -> Options {
+    Options {
+        image_format: ImageFormat::Compatible,
+        ..options
+    }
+}
+
+struct TitleInfo<'a> {
+    title: &'a str,
+    authors: &'a [User],
+    /// FictionBook paragraphs.
+    annotation: &'a str,
+    /// Like `2020-12-31`.
+    date: &'a str,
+    language: LanguageTag,
+}
+
+fn fb2_document(title_info: &TitleInfo, id: &str, body: &str, images: &Fb2Images) -> String {
+    let TitleInfo {
+        title,
+        authors,
+        annotation,
+        date,
+        language,
+    } = title_info;
+
+    let title = transform::escape_html(title);
+    let authors: Vec<String> = authors
+        .iter()
+        .map(|User { username, .. }| {
+            let username = transform::escape_html(username);
+            format!("<author><nickname>{username}</nickname></author>")
+        })
+        .collect();
+    let authors = authors.join("");
+    let annotation = if annotation.is_empty() {
+        String::new()
+    } else {
+        format!("<annotation>{annotation}</annotation>")
+    };
+    let binaries: Vec<String> = images
+        .binaries
+        .iter()
+        .map(|(id, (content_type, data))| {
+            let data = BASE64_STANDARD.encode(data);
+            format!(r#"<binary id="{id}" content-type="{content_type}">{data}</binary>"#)
+        })
+        .collect();
+    let binaries = binaries.join("\n");
+
+    format!(
+        r##"<?xml version="1.0" encoding="utf-8"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0" xmlns:l="http://www.w3.org/1999/xlink">
+<description>
+<title-info>
+<genre>antique</genre>
+{authors}
+<book-title>{title}</book-title>
+{annotation}
+<date value="{date}">{date}</date>
+<coverpage><image l:href="#{COVER_ID}"/></coverpage>
+<lang>{language}</lang>
+</title-info>
+<document-info>
+<author><nickname>glowpub</nickname></author>
+<program-used>glowpub</program-used>
+<date value="{date}">{date}</date>
+<id>{id}</id>
+<version>1.0</version>
+</document-info>
+</description>
+{body}
+{binaries}
+</FictionBook>
+"##
+    )
+}
+
+const COVER_ID: &str = "cover.png";
+
+/// The images that FictionBook readers support, which are embedded as binaries.
+struct Fb2Images {
+    /// The urls of the images, and the `#id` of their binary.
+    url_map: HashMap<String, String>,
+    /// The ids, with the content type and data.
+    binaries: BTreeMap<String, (String, Vec<u8>)>,
+}
+impl Fb2Images {
+    fn new(images: HashMap<String, InternedImage>, cover: Vec<u8>) -> Self {
+        let images: HashMap<String, InternedImage> = images
+            .into_iter()
+            .filter(|(_, image)| {
+                matches!(
+                    (image.mime.type_(), image.mime.subtype()),
+                    (mime::IMAGE, mime::GIF | mime::JPEG | mime::PNG)
+                )
+            })
+            .collect();
+
+        let url_map = images
+            .iter()
+            .map(|(url, image)| (url.clone(), format!("#{}", image.name())))
+            .collect();
+
+        // Deduplicated images are shared by several urls, but should only be added once.
+        let mut binaries: BTreeMap<String, (String, Vec<u8>)> = images
+            .into_values()
+            .map(|image| (image.name(), (image.mime.to_string(), image.data)))
+            .collect();
+        binaries.insert(COVER_ID.to_string(), (mime::IMAGE_PNG.to_string(), cover));
+
+        Self { url_map, binaries }
+    }
+}
+
+fn fb2_title(lines: &[&str]) -> String {
+    let lines: Vec<String> = lines
+        .iter()
+        .map(|line| format!("<p>{}</p>", transform::escape_html(line)))
+        .collect();
+    format!("<title>{}</title>", lines.join(""))
+}
+
+/// The icon, the speaker, and the content.
+fn fb2_block(block: &Block, options: Options, url_map: &HashMap<String, String>) -> String {
+    let mut parts = vec![];
+
+    let icon_url = block.icon.and_then(|icon| icon.url.clone()).or_else(|| {
+        options
+            .placeholder_icons
+            .then_some(placeholder_url(block.character_id?))
+    });
+    if let Some(href) = icon_url.and_then(|url| url_map.get(&url)) {
+        parts.push(format!(r#"<image l:href="{href}"/>"#));
+    }
+
+    if let Some(speaker) = block.character_name.or(block.author) {
+        let speaker = transform::escape_html(speaker);
+        let author = match (block.character_name, block.author) {
+            (Some(_), Some(author)) => {
+                format!(" <emphasis>({})</emphasis>", transform::escape_html(author))
+            }
+            _ => String::new(),
+        };
+        parts.push(format!("<p><strong>{speaker}</strong>{author}</p>"));
+    }
+
+    parts.push(fb2_content(block.content, options, url_map));
+
+    parts.join("\n")
+}
+
+/// The processed content as FictionBook paragraphs.
+/// Quotes and `details` become `cite`s, as FictionBook has no nesting beyond that.
+fn fb2_content(content: &str, options: Options, url_map: &HashMap<String, String>) -> String {
+    let content = process_content(content, options, url_map);
+
+    let mut elements = vec![];
+    let mut in_cite = false;
+    for block in transform::html_to_rich_text(&content) {
+        let quoted = match &block {
+            RichBlock::Paragraph { quote_depth, .. } | RichBlock::Image { quote_depth, .. } => {
+                *quote_depth > 0
+            }
+            RichBlock::Rule | RichBlock::Table(_) => in_cite,
+        };
+        if quoted != in_cite {
+            elements.push(if quoted { "<cite>" } else { "</cite>" }.to_string());
+            in_cite = quoted;
+        }
+
+        match block {
+            RichBlock::Paragraph {
+                runs, kind, marker, ..
+            } => {
+                let marker = marker.map(|m| format!("{m} ")).unwrap_or_default();
+                // Lines can't be broken inside FictionBook paragraphs.
+                for (i, line) in split_lines(&runs).into_iter().enumerate() {
+                    let marker = if i == 0 { marker.as_str() } else { "" };
+                    let text = format!("{marker}{}", fb2_inline(&line));
+                    elements.push(match kind {
+                        ParagraphKind::Heading(_) => format!("<subtitle>{text}</subtitle>"),
+                        ParagraphKind::Label => format!("<p><strong>{text}</strong></p>"),
+                        ParagraphKind::Normal => format!("<p>{text}</p>"),
+                    });
+                }
+            }
+            RichBlock::Image { src, alt, .. } => {
+                let alt = transform::escape_html(alt.as_deref().unwrap_or("image"));
+                elements.push(if src.starts_with('#') && !in_cite {
+                    format!(r#"<image l:href="{src}" alt="{alt}"/>"#)
+                } else if src.starts_with('#') {
+                    format!(r#"<p><image l:href="{src}" alt="{alt}"/></p>"#)
+                } else {
+                    let src = transform::escape_html(&src);
+                    format!(r#"<p><a l:href="{src}">[{alt}]</a></p>"#)
+                });
+            }
+            RichBlock::Rule => elements.push("<empty-line/>".to_string()),
+            RichBlock::Table(rows) => {
+                let rows: Vec<String> = rows
+                    .iter()
+                    .map(|cells| {
+                        let cells: Vec<String> = cells
+                            .iter()
+                            .map(|cell| format!("<td>{}</td>", fb2_inline(cell)))
+                            .collect();
+                        format!("<tr>{}</tr>", cells.join(""))
+                    })
+                    .collect();
+                elements.push(format!("<table>{}</table>", rows.join("")));
+            }
+        }
+    }
+    if in_cite {
+        elements.push("</cite>".to_string());
+    }
+
+    elements.join("\n")
+}
+
+/// Splits the runs at their line breaks.
+fn split_lines(runs: &[Run]) -> Vec<Vec<Run>> {
+    let mut lines = vec![vec![]];
+    for run in runs {
+        for (i, text) in run.text.split('\n').enumerate() {
+            if i > 0 {
+                lines.push(vec![]);
+            }
+            if !text.is_empty() {
+                lines.last_mut().unwrap().push(Run {
+                    text: text.to_string(),
+                    ..run.clone()
+                });
+            }
+        }
+    }
+    lines.retain(|line| !line.is_empty());
+    lines
+}
+
+fn fb2_inline(runs: &[Run]) -> String {
+    let runs: Vec<String> = runs
+        .iter()
+        .map(|Run { text, style, link }| {
+            let mut text = transform::escape_html(&text.replace('\n', " "));
+            for (styled, tag) in [
+                (style.code, "code"),
+                (style.superscript, "sup"),
+                (style.subscript, "sub"),
+                (style.strikethrough, "strikethrough"),
+                (style.emphasis, "emphasis"),
+                (style.strong, "strong"),
+            ] {
+                if styled {
+                    text = format!("<{tag}>{text}</{tag}>");
+                }
+            }
+            match link {
+                Some(link) => {
+                    let link = transform::escape_html(link);
+                    format!(r#"<a l:href="{link}">{text}</a>"#)
+                }
+                None => text,
+            }
+        })
+        .collect();
+    runs.join("")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use crate::types::{Board, BoardInPost, Character, Icon, Post, Reply};
+
+    use super::*;
+
+    fn thread(id: u64, section: Option<u64>) -> Thread {
+        let user = User {
+            id: 1,
+            username: "Alice & <Bob>".to_string(),
+        };
+        Thread {
+            post: Post {
+                id,
+                authors: vec![user.clone()],
+                board: BoardInPost {
+                    id: 1,
+                    name: "Board".to_string(),
+                },
+                character: None,
+                content: "<p>Post</p>".to_string(),
+                created_at: DateTime::default(),
+                description: None,
+                icon: None,
+                num_replies: 1,
+                section: section.map(|id| Section {
+                    id,
+                    name: format!("Section {id}"),
+                    order: id,
+                }),
+                section_order: id,
+                status: "active".to_string(),
+                subject: format!(r#"Thread {id}: "Cats" & <Dogs>"#),
+                tagged_at: DateTime::default(),
+            },
+            replies: vec![Reply {
+                id: id * 1000,
+                character: Some(Character {
+                    id: 7,
+                    name: "Eve's \"twin\"".to_string(),
+                    screenname: None,
+                }),
+                character_name: None,
+                content: r#"<p>1 &lt; 2 &amp;&amp; "yes"</p><details><summary>More</summary><p>Hidden</p></details>"#.to_string(),
+                created_at: DateTime::default(),
+                icon: Some(Icon {
+                    id: 3,
+                    keyword: None,
+                    url: Some("https://example.com/icon.png".to_string()),
+                }),
+                updated_at: DateTime::default(),
+                user,
+            }],
+        }
+    }
+
+    #[test]
+    fn titles_names_and_content_are_escaped() {
+        let section = thread(1, None).fb2_section(Options::default(), &HashMap::new());
+
+        assert!(
+            section.contains("<title><p>Thread 1: &#34;Cats&#34; &#38; &#60;Dogs&#62;</p></title>")
+        );
+        assert!(section.contains("<p><emphasis>by Alice &#38; &#60;Bob&#62;</emphasis></p>"));
+        assert!(section.contains(
+            "<p><strong>Eve&#x27;s &#34;twin&#34;</strong> <emphasis>(Alice &#38; &#60;Bob&#62;)</emphasis></p>"
+        ));
+        assert!(section.contains("<p>1 &#60; 2 &#38;&#38; &#34;yes&#34;</p>"));
+        assert!(!section.contains("<Dogs>") && !section.contains("<Bob>"));
+    }
+
+    #[test]
+    fn details_become_cites() {
+        let section = thread(1, None).fb2_section(Options::default(), &HashMap::new());
+        assert!(section
+            .contains("<cite>\n<p><strong>More</strong></p>\n<p>Hidden</p>\n</cite>\n</section>"));
+    }
+
+    #[test]
+    fn embedded_images_are_binaries() {
+        let image = InternedImage {
+            id: Some(3),
+            original_url: "https://example.com/icon.png".to_string(),
+            mime: mime::IMAGE_PNG,
+            data: b"png".to_vec(),
+        };
+        let webp = InternedImage {
+            id: None,
+            original_url: "https://example.com/image.webp".to_string(),
+            mime: "image/webp".parse().unwrap(),
+            data: b"webp".to_vec(),
+        };
+        let name = image.name();
+        let images = Fb2Images::new(
+            HashMap::from([
+                (image.original_url.clone(), image),
+                (webp.original_url.clone(), webp),
+            ]),
+            b"cover".to_vec(),
+        );
+        // Readers don't support WebP.
+        assert_eq!(images.url_map.len(), 1);
+        assert_eq!(
+            images.binaries.keys().collect::<Vec<_>>(),
+            [COVER_ID, &name]
+        );
+
+        let section = thread(1, None).fb2_section(Options::default(), &images.url_map);
+        assert!(section.contains(&format!(
+            "{SEPARATOR}\n<image l:href=\"#{name}\"/>\n<p><strong>Eve"
+        )));
+
+        let content = fb2_content(
+            r#"<p>Text</p><img src="https://example.com/image.webp" alt="A &amp; B"><blockquote><img src="https://example.com/icon.png"></blockquote>"#,
+            Options::default(),
+            &images.url_map,
+        );
+        assert_eq!(
+            content,
+            [
+                "<p>Text</p>",
+                r#"<p><a l:href="https://example.com/image.webp">[A &#38; B]</a></p>"#,
+                "<cite>",
+                &format!(r##"<p><image l:href="#{name}" alt="image"/></p>"##),
+                "</cite>",
+            ]
+            .join("\n")
+        );
+
+        let document = fb2_document(
+            &TitleInfo {
+                title: "A & B",
+                authors: &[],
+                annotation: "",
+                date: "2020-01-01",
+                language: LanguageTag::default(),
+            },
+            "id",
+            "<body></body>",
+            &images,
+        );
+        assert!(document.contains("<book-title>A &#38; B</book-title>"));
+        assert!(document.contains(&format!(
+            r#"<binary id="{name}" content-type="image/png">cG5n</binary>"#
+        )));
+    }
+
+    #[tokio::test]
+    async fn boards_have_a_section_per_board_section() {
+        let board = Continuity {
+            board: Board {
+                id: 1,
+                name: "Board".to_string(),
+                board_sections: vec![Section {
+                    id: 1,
+                    name: "Section 1".to_string(),
+                    order: 1,
+                }],
+            },
+            threads: vec![thread(1, Some(1)), thread(2, None)],
+        };
+        let fb2 = board.to_fb2(Options::default()).await.unwrap();
+
+        let titles: Vec<&str> = fb2
+            .lines()
+            .filter(|line| *line == "<section>" || line.starts_with("<title>"))
+            .collect();
+        assert_eq!(
+            titles,
+            [
+                "<title><p>Board</p><p>by Alice &#38; &#60;Bob&#62;</p></title>",
+                "<section>",
+                "<title><p>Section 1</p></title>",
+                "<section>",
+                "<title><p>Thread 1: &#34;Cats&#34; &#38; &#60;Dogs&#62;</p></title>",
+                "<section>",
+                &format!("<title><p>{SECTIONLESS_NAME}</p></title>"),
+                "<section>",
+                "<title><p>Thread 2: &#34;Cats&#34; &#38; &#60;Dogs&#62;</p></title>",
+            ]
+        );
+        assert_eq!(
+            fb2.matches("<section>").count(),
+            fb2.matches("</section>").count()
+        );
+    }
+}
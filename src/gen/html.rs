@@ -1,30 +1,483 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+};
+
+use base64::Engine;
+
+use crate::{
+    intern_images::{process_images, InternedImage},
+    types::{Continuity, Section},
+};
 
 use super::{
-    raw_content_page, raw_copyright_page, raw_title_page, transform, Options, Thread, STYLE,
+    raw_board_copyright_page, raw_board_title_page, raw_content_page, raw_copyright_page,
+    raw_section_title_page, raw_title_page, transform, with_glowfic_links, HtmlImages, Location,
+    Options, Thread, SECTIONLESS_NAME, STYLE,
 };
 
+use super::search::{search_index_script, SEARCH_SCRIPT};
+
+const READER_STYLE: &str = include_str!("reader.css");
+const READER_SCRIPT: &str = include_str!("reader.js");
+
+/// A html page, along with the local files it refers to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HtmlPage {
+    pub html: String,
+    /// The files the page refers to, by their path relative to the page.
+    /// Only populated with [HtmlImages::Assets].
+    pub assets: BTreeMap<String, Vec<u8>>,
+}
+
 impl Thread {
+    /// Like [Thread::to_single_html_page], but images are handled according to [Options::html_images].
+    pub async fn to_html(&self, options: Options) -> Result<HtmlPage, Box<dyn Error>> {
+        let (url_map, assets) = match options.html_images {
+            HtmlImages::Remote => (HashMap::new(), BTreeMap::new()),
+            HtmlImages::Inline | HtmlImages::Assets => {
+                let images = process_images(self.images_to_intern(options).await?, options).await?;
+                html_images(images, options.html_images)
+            }
+        };
+
+        Ok(HtmlPage {
+            html: self.html_page(options, url_map),
+            assets,
+        })
+    }
+
     pub fn to_single_html_page(&self, options: Options) -> String {
-        let front = raw_title_page(&self.post, self.replies.len());
-        let content = raw_content_page(&self.content_blocks(options));
+        self.html_page(options, HashMap::new())
+    }
+
+    fn html_page(&self, options: Options, url_map: HashMap<String, String>) -> String {
+        let (posts, replies) = glowfic_links(&[self], |_| "#".to_string(), |_| String::new());
+        let url_map = &with_glowfic_links(url_map, &posts, &replies);
+
         let back = raw_copyright_page(&self.post);
 
         wrap_html(
             &self.post.subject,
-            &format!("{front}{content}{back}"),
+            &format!("{}{back}", self.raw_html_content(options)),
             options,
+            url_map,
         )
     }
+
+    /// The title page followed by all the content.
+    fn raw_html_content(&self, options: Options) -> String {
+        let front = raw_title_page(&self.post, self.replies.len());
+        let content = raw_content_page(&self.content_blocks(options));
+
+        format!("{front}{content}")
+    }
 }
 
-fn wrap_html(subject: &str, content: &str, options: Options) -> String {
-    let content = super::process_content(content, options, &HashMap::new());
+/// A static website made of several html pages.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HtmlSite {
+    /// The pages, by their path relative to the root of the site. The entry point is `index.html`.
+    pub pages: BTreeMap<String, String>,
+    /// The other files the pages refer to, by their path relative to the root of the site.
+    /// This is the search index, and the images with [HtmlImages::Assets].
+    pub assets: BTreeMap<String, Vec<u8>>,
+}
+
+impl Continuity {
+    /// The entire board as a single page, with a table of contents linking to each section and thread.
+    pub async fn to_html(&self, options: Options) -> Result<HtmlPage, Box<dyn Error>> {
+        let (url_map, assets) = match options.html_images {
+            HtmlImages::Remote => (HashMap::new(), BTreeMap::new()),
+            HtmlImages::Inline | HtmlImages::Assets => {
+                let images = process_images(self.images_to_intern(options).await?, options).await?;
+                html_images(images, options.html_images)
+            }
+        };
+
+        Ok(HtmlPage {
+            html: self.html_page(options, url_map),
+            assets,
+        })
+    }
+
+    pub fn to_single_html_page(&self, options: Options) -> String {
+        self.html_page(options, HashMap::new())
+    }
+
+    fn html_page(&self, options: Options, url_map: HashMap<String, String>) -> String {
+        let (posts, replies) = glowfic_links(
+            &self.threads_in_order(),
+            |thread| format!("#{}", thread_anchor(thread)),
+            |_| String::new(),
+        );
+        let url_map = &with_glowfic_links(url_map, &posts, &replies);
+        let process = |content: &str| process_html(content, options, url_map);
+
+        let (sections, sectionless_threads) = self.sections();
+
+        let front = process(&raw_board_title_page(self, None));
+        let toc = board_toc(
+            &sections,
+            &sectionless_threads,
+            |section| format!("#{}", section_anchor(section)),
+            |thread| format!("#{}", thread_anchor(thread)),
+        );
+        let toc = format!(r##"<nav class="toc">{toc}</nav>"##);
+
+        let mut body = String::new();
+        let section_iter = sections
+            .iter()
+            .map(|(section, threads)| (Some(section), threads))
+            .chain((!sectionless_threads.is_empty()).then_some((None, &sectionless_threads)));
+        for (section, threads) in section_iter {
+            let anchor = section_anchor(section);
+            let title = process(&raw_section_title_page(section, threads));
+            body.push_str(&format!(r##"<section id="{anchor}">{title}"##));
+
+            for thread in threads {
+                let anchor = thread_anchor(thread);
+                let content = process(&thread.raw_html_content(options));
+                body.push_str(&format!(r##"<article id="{anchor}">{content}</article>"##));
+            }
+
+            body.push_str("</section>");
+        }
+
+        let back = process(&raw_board_copyright_page(self));
+
+        html_document(
+            &self.board.name,
+            &format!("{front}{toc}{body}{back}"),
+            options,
+        )
+    }
+
+    /// The board as a static website: an index, a page per section, and a page per thread,
+    /// with links to move between them in reading order.
+    pub async fn to_html_site(&self, options: Options) -> Result<HtmlSite, Box<dyn Error>> {
+        let (url_map, assets) = match options.html_images {
+            HtmlImages::Remote => (HashMap::new(), BTreeMap::new()),
+            HtmlImages::Inline | HtmlImages::Assets => {
+                let images = process_images(self.images_to_intern(options).await?, options).await?;
+                html_images(images, options.html_images)
+            }
+        };
+
+        let mut assets = assets;
+        assets.insert(
+            SEARCH_INDEX_PATH.to_string(),
+            search_index_script(&self.threads_in_order(), thread_page_path).into_bytes(),
+        );
+
+        Ok(HtmlSite {
+            pages: self.html_site_pages(options, url_map),
+            assets,
+        })
+    }
+
+    fn html_site_pages(
+        &self,
+        options: Options,
+        url_map: HashMap<String, String>,
+    ) -> BTreeMap<String, String> {
+        let (posts, replies) =
+            glowfic_links(&self.threads_in_order(), thread_page_path, thread_page_path);
+        let url_map = &with_glowfic_links(url_map, &posts, &replies);
+        let process = |content: &str| process_html(content, options, url_map);
+
+        let mut pages = BTreeMap::new();
+
+        let (sections, sectionless_threads) = self.sections();
+        let section_list: Vec<(Option<&Section>, &Vec<&Thread>)> = sections
+            .iter()
+            .map(|(section, threads)| (Some(section), threads))
+            .chain((!sectionless_threads.is_empty()).then_some((None, &sectionless_threads)))
+            .collect();
+        let threads = self.threads_in_order();
+
+        // Index
+        let front = process(&raw_board_title_page(self, None));
+        let toc = board_toc(
+            &sections,
+            &sectionless_threads,
+            section_page_path,
+            thread_page_path,
+        );
+        let toc = format!(r##"<nav class="toc">{toc}</nav>"##);
+        let back = process(&raw_board_copyright_page(self));
+        let nav = format!(r##"<nav class="site-nav">{}</nav>"##, search_link());
+        pages.insert(
+            INDEX_PATH.to_string(),
+            html_document(
+                &self.board.name,
+                &format!("{nav}{front}{toc}{back}"),
+                options,
+            ),
+        );
+
+        // Search
+        let nav = site_nav(None, (INDEX_PATH.to_string(), &self.board.name), None);
+        let search = format!(
+            r##"{nav}<form class="search"><input type="search" class="search-input" placeholder="Search" aria-label="Search" autofocus></form>
+        <p class="search-status"><noscript>Searching needs javascript.</noscript></p>
+        <ol class="search-results"></ol>
+        <script src="{SEARCH_INDEX_PATH}"></script>
+        <script>
+            {SEARCH_SCRIPT}
+        </script>"##
+        );
+        pages.insert(
+            SEARCH_PATH.to_string(),
+            html_document(&format!("Search {}", self.board.name), &search, options),
+        );
+
+        // Sections
+        for (i, (section, section_threads)) in section_list.iter().enumerate() {
+            let title = process(&raw_section_title_page(*section, section_threads));
+            let list = thread_list(section_threads);
+            let nav = site_nav(
+                i.checked_sub(1)
+                    .and_then(|i| section_list.get(i))
+                    .map(|(s, _)| (section_page_path(*s), section_name(*s))),
+                (INDEX_PATH.to_string(), &self.board.name),
+                section_list
+                    .get(i + 1)
+                    .map(|(s, _)| (section_page_path(*s), section_name(*s))),
+            );
+            pages.insert(
+                section_page_path(*section),
+                html_document(
+                    section_name(*section),
+                    &format!(r##"{nav}{title}<nav class="toc">{list}</nav>{nav}"##),
+                    options,
+                ),
+            );
+        }
+
+        // Threads
+        for (i, thread) in threads.iter().enumerate() {
+            let section = thread.post.section.as_ref();
+            let content = process(&thread.raw_html_content(options));
+            let nav = site_nav(
+                i.checked_sub(1)
+                    .and_then(|i| threads.get(i))
+                    .map(|t| (thread_page_path(t), &*t.post.subject)),
+                (section_page_path(section), section_name(section)),
+                threads
+                    .get(i + 1)
+                    .map(|t| (thread_page_path(t), &*t.post.subject)),
+            );
+            pages.insert(
+                thread_page_path(thread),
+                html_document(
+                    &thread.post.subject,
+                    &format!("{nav}{content}{nav}"),
+                    options,
+                ),
+            );
+        }
+
+        pages
+    }
+}
+
+const INDEX_PATH: &str = "index.html";
+const SEARCH_PATH: &str = "search.html";
+const SEARCH_INDEX_PATH: &str = "search-index.js";
+
+fn section_anchor(section: Option<&Section>) -> String {
+    match section {
+        Some(Section { id, .. }) => format!("section-{id}"),
+        None => "sectionless".to_string(),
+    }
+}
+fn thread_anchor(thread: &Thread) -> String {
+    let id = thread.post.id;
+    format!("post-{id}")
+}
+
+fn section_page_path(section: Option<&Section>) -> String {
+    match section {
+        Some(Section { id, .. }) => format!("section_{id}.html"),
+        None => "sectionless.html".to_string(),
+    }
+}
+fn thread_page_path(thread: &Thread) -> String {
+    let id = thread.post.id;
+    format!("post_{id}.html")
+}
+/// Where each post and reply of the threads is, for [with_glowfic_links],
+/// given where each thread starts and the page it is on.
+fn glowfic_links(
+    threads: &[&Thread],
+    thread_href: impl Fn(&Thread) -> String,
+    page_path: impl Fn(&Thread) -> String,
+) -> (Vec<Location>, Vec<Location>) {
+    let posts = threads
+        .iter()
+        .map(|thread| (thread.post.id, thread_href(thread)))
+        .collect();
+    let replies = threads
+        .iter()
+        .flat_map(|thread| {
+            let page = page_path(thread);
+            thread
+                .replies
+                .iter()
+                .map(move |reply| (reply.id, format!("{page}#reply-{}", reply.id)))
+        })
+        .collect();
+
+    (posts, replies)
+}
+
+fn section_name(section: Option<&Section>) -> &str {
+    match section {
+        Some(Section { name, .. }) => name,
+        None => SECTIONLESS_NAME,
+    }
+}
+
+/// A nested list of links to the sections and their threads.
+fn board_toc(
+    sections: &[(Section, Vec<&Thread>)],
+    sectionless_threads: &[&Thread],
+    section_link: impl Fn(Option<&Section>) -> String,
+    thread_link: impl Fn(&Thread) -> String,
+) -> String {
+    let mut items: Vec<(Option<&Section>, &[&Thread])> = sections
+        .iter()
+        .map(|(section, threads)| (Some(section), &**threads))
+        .collect();
+    if !sectionless_threads.is_empty() {
+        items.push((None, sectionless_threads));
+    }
+
+    let items: Vec<String> = items
+        .into_iter()
+        .map(|(section, threads)| {
+            let link = transform::escape_html(&section_link(section));
+            let name = transform::escape_html(section_name(section));
+            let threads: Vec<String> = threads
+                .iter()
+                .map(|t| {
+                    let link = transform::escape_html(&thread_link(t));
+                    let subject = transform::escape_html(&t.post.subject);
+                    format!(r##"<li><a href="{link}">{subject}</a></li>"##)
+                })
+                .collect();
+            let threads = threads.join("");
+            format!(r##"<li><a href="{link}">{name}</a><ul>{threads}</ul></li>"##)
+        })
+        .collect();
+    let items = items.join("");
+
+    format!("<ul>{items}</ul>")
+}
+
+/// A list of links to the threads' pages.
+fn thread_list(threads: &[&Thread]) -> String {
+    let items: Vec<String> = threads
+        .iter()
+        .map(|t| {
+            let path = thread_page_path(t);
+            let subject = transform::escape_html(&t.post.subject);
+            format!(r##"<li><a href="{path}">{subject}</a></li>"##)
+        })
+        .collect();
+    let items = items.join("");
+
+    format!("<ol>{items}</ol>")
+}
+
+/// Links to the previous page, the parent page, and the next page.
+fn site_nav(
+    previous: Option<(String, &str)>,
+    up: (String, &str),
+    next: Option<(String, &str)>,
+) -> String {
+    let link = |(path, title): (String, &str), label: &str| {
+        let path = transform::escape_html(&path);
+        let title = transform::escape_html(title);
+        format!(r##"<a href="{path}" title="{title}">{label}</a>"##)
+    };
+
+    let previous = previous.map(|p| link(p, "← Previous")).unwrap_or_default();
+    let up = link(up, "↑ Up");
+    let next = next.map(|n| link(n, "Next →")).unwrap_or_default();
+    let search = search_link();
+
+    format!(r##"<nav class="site-nav">{previous}{up}{next}{search}</nav>"##)
+}
+
+fn search_link() -> String {
+    format!(r##"<a href="{SEARCH_PATH}">Search</a>"##)
+}
+
+/// Returns the map from the original urls to the ones used in the page, and the assets to write.
+pub(super) fn html_images(
+    images: HashMap<String, InternedImage>,
+    mode: HtmlImages,
+) -> (HashMap<String, String>, BTreeMap<String, Vec<u8>>) {
+    let mut url_map = HashMap::new();
+    let mut assets = BTreeMap::new();
+
+    for (url, image) in images {
+        match mode {
+            HtmlImages::Remote => {}
+            HtmlImages::Inline => {
+                let data = base64::engine::general_purpose::STANDARD.encode(&image.data);
+                url_map.insert(url, format!("data:{};base64,{data}", image.mime));
+            }
+            HtmlImages::Assets => {
+                let path = format!("assets/{}", image.name());
+                url_map.insert(url, path.clone());
+                assets.insert(path, image.data);
+            }
+        }
+    }
+
+    (url_map, assets)
+}
+
+fn wrap_html(
+    subject: &str,
+    content: &str,
+    options: Options,
+    url_map: &HashMap<String, String>,
+) -> String {
+    let content = process_html(content, options, url_map);
+    html_document(subject, &content, options)
+}
+
+/// [super::process_content], plus the ids html readers can use to link to each reply.
+fn process_html(content: &str, options: Options, url_map: &HashMap<String, String>) -> String {
+    transform::add_reply_anchors(&super::process_content(content, options, url_map))
+}
+
+/// Wraps already processed content into a full html document.
+fn html_document(subject: &str, content: &str, options: Options) -> String {
     let subject = transform::escape_html(subject);
+    let language = options.language;
+    let reader = if options.interactive_html {
+        format!(
+            r##"
+        <style>
+            {READER_STYLE}
+        </style>
+        <script>
+            {READER_SCRIPT}
+        </script>
+"##
+        )
+    } else {
+        String::new()
+    };
 
     format!(
         r##"<!DOCTYPE html>
-<html lang="en">
+<html lang="{language}">
     <head>
         <meta charset="utf-8"/>
         <meta name="viewport" content="width=device-width, initial-scale=1.0"/>
@@ -34,7 +487,7 @@ fn wrap_html(subject: &str, content: &str, options: Options) -> String {
         <style>
             {STYLE}
         </style>
-
+{reader}
     </head>
     <body>
 
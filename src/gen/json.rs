@@ -0,0 +1,626 @@
+//! A stable json export of the normalized threads, for analysis tools.
+//!
+//! Unlike the cached api responses, each thread is a single object with the processed html,
+//! its plain text, and the images the html refers to. Bump [JSON_EXPORT_VERSION] whenever
+//! the format changes.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    intern_images::{placeholder_url, process_images},
+    types::{BoardInPost, Character, Continuity, Icon, Section, User},
+    Thread,
+};
+
+use super::{html::html_images, process_content, transform, HtmlImages, Options};
+
+pub const JSON_EXPORT_VERSION: u32 = 1;
+
+/// A json (or ndjson) file, along with the local files it refers to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JsonExport {
+    pub json: String,
+    /// The images, by their path relative to the json file.
+    /// Only populated with [HtmlImages::Assets].
+    pub assets: BTreeMap<String, Vec<u8>>,
+}
+
+/// The format of [Continuity::to_json].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedBoard {
+    pub version: u32,
+    pub id: u64,
+    pub name: String,
+    /// In order.
+    pub sections: Vec<ExportedSection>,
+    /// In reading order: by section, then the sectionless ones.
+    pub threads: Vec<ExportedThread>,
+}
+
+/// The format of [Thread::to_json], and of each line of [Continuity::to_ndjson].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedThread {
+    pub version: u32,
+    pub id: u64,
+    pub subject: String,
+    pub status: String,
+    pub description: Option<ExportedContent>,
+    pub board: ExportedBoardInfo,
+    pub section: Option<ExportedSection>,
+    pub section_order: u64,
+    pub authors: Vec<ExportedUser>,
+    #[serde(with = "crate::rfc3339")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "crate::rfc3339")]
+    pub tagged_at: DateTime<Utc>,
+    /// The post, followed by the replies.
+    pub entries: Vec<ExportedEntry>,
+}
+
+/// The post or one of the replies.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedEntry {
+    /// [None] for the post itself.
+    pub reply_id: Option<u64>,
+    /// The user who wrote it. For the post, the first of the authors.
+    pub author: Option<ExportedUser>,
+    pub character: Option<ExportedCharacter>,
+    /// The name used for the character here, which can be an alias.
+    pub character_name: Option<String>,
+    pub icon: Option<ExportedIcon>,
+    #[serde(with = "crate::rfc3339")]
+    pub created_at: DateTime<Utc>,
+    /// Only known for replies.
+    #[serde(default, with = "crate::rfc3339::option")]
+    pub updated_at: Option<DateTime<Utc>>,
+    pub content: ExportedContent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedContent {
+    /// Sanitized, with the images replaced according to [Options::html_images].
+    pub html: String,
+    /// Paragraphs are separated by blank lines.
+    pub text: String,
+}
+
+/// The board a thread is in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedBoardInfo {
+    pub id: u64,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedSection {
+    pub id: u64,
+    pub name: String,
+    pub order: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedUser {
+    pub id: u64,
+    pub username: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedCharacter {
+    pub id: u64,
+    pub name: String,
+    pub screenname: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedIcon {
+    /// [None] for placeholder icons.
+    pub id: Option<u64>,
+    pub keyword: Option<String>,
+    pub url: String,
+    /// What the html uses instead of the url, following [Options::html_images]:
+    /// the path of the image relative to the json file, or a data url.
+    pub src: Option<String>,
+}
+
+impl Thread {
+    /// The thread as a pretty-printed [ExportedThread].
+    /// Images are handled according to [Options::html_images].
+    pub async fn to_json(&self, options: Options) -> Result<JsonExport, Box<dyn Error>> {
+        let (url_map, assets) = self.json_images(options).await?;
+
+        Ok(JsonExport {
+            json: serde_json::to_string_pretty(&self.exported(options, &url_map))?,
+            assets,
+        })
+    }
+
+    /// The thread as a single line [ExportedThread], like a line of [Continuity::to_ndjson].
+    pub async fn to_ndjson(&self, options: Options) -> Result<JsonExport, Box<dyn Error>> {
+        let (url_map, assets) = self.json_images(options).await?;
+
+        Ok(JsonExport {
+            json: format!(
+                "{}\n",
+                serde_json::to_string(&self.exported(options, &url_map))?
+            ),
+            assets,
+        })
+    }
+
+    async fn json_images(
+        &self,
+        options: Options,
+    ) -> Result<(HashMap<String, String>, BTreeMap<String, Vec<u8>>), Box<dyn Error>> {
+        Ok(match options.html_images {
+            HtmlImages::Remote => (HashMap::new(), BTreeMap::new()),
+            HtmlImages::Inline | HtmlImages::Assets => {
+                let images = process_images(self.images_to_intern(options).await?, options).await?;
+                html_images(images, options.html_images)
+            }
+        })
+    }
+
+    /// The thread as an [ExportedThread], with the images replaced according to `url_map`.
+    pub fn exported(&self, options: Options, url_map: &HashMap<String, String>) -> ExportedThread {
+        let post = &self.post;
+
+        let post_entry = ExportedEntry {
+            reply_id: None,
+            author: post.authors.first().map(ExportedUser::from),
+            character: post.character.as_ref().map(ExportedCharacter::from),
+            character_name: post.character.as_ref().map(|c| c.name.clone()),
+            icon: exported_icon(
+                post.icon.as_ref(),
+                post.character.as_ref(),
+                options,
+                url_map,
+            ),
+            created_at: post.created_at,
+            updated_at: None,
+            content: exported_content(&post.content, options, url_map),
+        };
+        let reply_entries = self.replies.iter().map(|reply| ExportedEntry {
+            reply_id: Some(reply.id),
+            author: Some(ExportedUser::from(&reply.user)),
+            character: reply.character.as_ref().map(ExportedCharacter::from),
+            character_name: reply
+                .character_name
+                .clone()
+                .or(reply.character.as_ref().map(|c| c.name.clone())),
+            icon: exported_icon(
+                reply.icon.as_ref(),
+                reply.character.as_ref(),
+                options,
+                url_map,
+            ),
+            created_at: reply.created_at,
+            updated_at: Some(reply.updated_at),
+            content: exported_content(&reply.content, options, url_map),
+        });
+
+        ExportedThread {
+            version: JSON_EXPORT_VERSION,
+            id: post.id,
+            subject: post.subject.clone(),
+            status: post.status.clone(),
+            description: post
+                .description
+                .as_deref()
+                .map(|description| exported_content(description, options, url_map)),
+            board: ExportedBoardInfo::from(&post.board),
+            section: post.section.as_ref().map(ExportedSection::from),
+            section_order: post.section_order,
+            authors: post.authors.iter().map(ExportedUser::from).collect(),
+            created_at: post.created_at,
+            tagged_at: post.tagged_at,
+            entries: std::iter::once(post_entry).chain(reply_entries).collect(),
+        }
+    }
+}
+
+impl Continuity {
+    /// The entire board as a pretty-printed [ExportedBoard].
+    /// Images are handled according to [Options::html_images].
+    pub async fn to_json(&self, options: Options) -> Result<JsonExport, Box<dyn Error>> {
+        let (url_map, assets) = self.json_images(options).await?;
+
+        let mut sections = self.board.board_sections.clone();
+        sections.sort_by_key(|s| s.order);
+        let board = ExportedBoard {
+            version: JSON_EXPORT_VERSION,
+            id: self.board.id,
+            name: self.board.name.clone(),
+            sections: sections.iter().map(ExportedSection::from).collect(),
+            threads: self
+                .threads_in_order()
+                .into_iter()
+                .map(|thread| thread.exported(options, &url_map))
+                .collect(),
+        };
+
+        Ok(JsonExport {
+            json: serde_json::to_string_pretty(&board)?,
+            assets,
+        })
+    }
+
+    /// One [ExportedThread] per line, in reading order.
+    /// Images are handled according to [Options::html_images].
+    pub async fn to_ndjson(&self, options: Options) -> Result<JsonExport, Box<dyn Error>> {
+        let (url_map, assets) = self.json_images(options).await?;
+
+        let mut json = String::new();
+        for thread in self.threads_in_order() {
+            json.push_str(&serde_json::to_string(&thread.exported(options, &url_map))?);
+            json.push('\n');
+        }
+
+        Ok(JsonExport { json, assets })
+    }
+
+    async fn json_images(
+        &self,
+        options: Options,
+    ) -> Result<(HashMap<String, String>, BTreeMap<String, Vec<u8>>), Box<dyn Error>> {
+        Ok(match options.html_images {
+            HtmlImages::Remote => (HashMap::new(), BTreeMap::new()),
+            HtmlImages::Inline | HtmlImages::Assets => {
+                let images = process_images(self.images_to_intern(options).await?, options).await?;
+                html_images(images, options.html_images)
+            }
+        })
+    }
+}
+
+impl From<&BoardInPost> for ExportedBoardInfo {
+    fn from(BoardInPost { id, name }: &BoardInPost) -> Self {
+        Self {
+            id: *id,
+            name: name.clone(),
+        }
+    }
+}
+impl From<&Section> for ExportedSection {
+    fn from(Section { id, name, order }: &Section) -> Self {
+        Self {
+            id: *id,
+            name: name.clone(),
+            order: *order,
+        }
+    }
+}
+impl From<&User> for ExportedUser {
+    fn from(User { id, username }: &User) -> Self {
+        Self {
+            id: *id,
+            username: username.clone(),
+        }
+    }
+}
+impl From<&Character> for ExportedCharacter {
+    fn from(
+        Character {
+            id,
+            name,
+            screenname,
+        }: &Character,
+    ) -> Self {
+        Self {
+            id: *id,
+            name: name.clone(),
+            screenname: screenname.clone(),
+        }
+    }
+}
+
+fn exported_content(
+    content: &str,
+    options: Options,
+    url_map: &HashMap<String, String>,
+) -> ExportedContent {
+    let html = process_content(content, options, url_map);
+    ExportedContent {
+        text: transform::html_to_text(&html),
+        html,
+    }
+}
+
+/// The icon, or the placeholder of the character if enabled.
+fn exported_icon(
+    icon: Option<&Icon>,
+    character: Option<&Character>,
+    options: Options,
+    url_map: &HashMap<String, String>,
+) -> Option<ExportedIcon> {
+    let icon = match icon {
+        Some(Icon {
+            id,
+            keyword,
+            url: Some(url),
+        }) => ExportedIcon {
+            id: Some(*id),
+            keyword: keyword.clone(),
+            url: url.clone(),
+            src: None,
+        },
+        _ if options.placeholder_icons => ExportedIcon {
+            id: None,
+            keyword: None,
+            url: placeholder_url(character?.id),
+            src: None,
+        },
+        _ => return None,
+    };
+
+    Some(ExportedIcon {
+        src: url_map.get(&icon.url).cloned(),
+        ..icon
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use crate::{intern_images::InternedImage, types::Post, types::Reply};
+
+    use super::*;
+
+    fn thread() -> Thread {
+        let user = User {
+            id: 1,
+            username: "alice".to_string(),
+        };
+        let character = Character {
+            id: 7,
+            name: "Eve".to_string(),
+            screenname: Some("eve".to_string()),
+        };
+        Thread {
+            post: Post {
+                id: 10,
+                authors: vec![user.clone()],
+                board: BoardInPost {
+                    id: 1,
+                    name: "Board".to_string(),
+                },
+                character: Some(character.clone()),
+                content: r#"<p>Post <img src="https://example.com/image.png"></p>"#.to_string(),
+                created_at: DateTime::default(),
+                description: Some("<p>Description</p>".to_string()),
+                icon: Some(Icon {
+                    id: 3,
+                    keyword: Some("smile".to_string()),
+                    url: Some("https://example.com/icon.png".to_string()),
+                }),
+                num_replies: 1,
+                section: Some(Section {
+                    id: 2,
+                    name: "Section".to_string(),
+                    order: 0,
+                }),
+                section_order: 4,
+                status: "active".to_string(),
+                subject: "Thread".to_string(),
+                tagged_at: DateTime::default(),
+            },
+            replies: vec![Reply {
+                id: 100,
+                character: Some(character),
+                character_name: Some("Evie".to_string()),
+                content: "<p>Reply</p>".to_string(),
+                created_at: DateTime::default(),
+                icon: None,
+                updated_at: DateTime::default(),
+                user,
+            }],
+        }
+    }
+
+    fn image(id: Option<usize>, url: &str) -> (String, InternedImage) {
+        (
+            url.to_string(),
+            InternedImage {
+                id,
+                original_url: url.to_string(),
+                mime: mime::IMAGE_PNG,
+                data: b"png".to_vec(),
+            },
+        )
+    }
+
+    fn images() -> HashMap<String, InternedImage> {
+        HashMap::from([
+            image(Some(3), "https://example.com/icon.png"),
+            image(None, "https://example.com/image.png"),
+        ])
+    }
+
+    /// The keys of the objects, with the path to them, sorted.
+    fn field_names(value: &Value, path: &str, names: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map {
+                    let path = format!("{path}.{key}");
+                    names.push(path.clone());
+                    field_names(value, &path, names);
+                }
+            }
+            Value::Array(values) => {
+                if let Some(value) = values.first() {
+                    field_names(value, &format!("{path}[]"), names);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn exports_can_be_read_back() {
+        let exported = thread().exported(Options::default(), &HashMap::new());
+        let json = serde_json::to_string_pretty(&exported).unwrap();
+        assert_eq!(
+            serde_json::from_str::<ExportedThread>(&json).unwrap(),
+            exported
+        );
+    }
+
+    /// Changing these needs a new [JSON_EXPORT_VERSION].
+    #[test]
+    fn the_field_names_are_stable() {
+        let exported = thread().exported(Options::default(), &HashMap::new());
+        let mut names = vec![];
+        field_names(&serde_json::to_value(&exported).unwrap(), "", &mut names);
+        assert_eq!(
+            names,
+            [
+                ".authors",
+                ".authors[].id",
+                ".authors[].username",
+                ".board",
+                ".board.id",
+                ".board.name",
+                ".created_at",
+                ".description",
+                ".description.html",
+                ".description.text",
+                ".entries",
+                ".entries[].author",
+                ".entries[].author.id",
+                ".entries[].author.username",
+                ".entries[].character",
+                ".entries[].character.id",
+                ".entries[].character.name",
+                ".entries[].character.screenname",
+                ".entries[].character_name",
+                ".entries[].content",
+                ".entries[].content.html",
+                ".entries[].content.text",
+                ".entries[].created_at",
+                ".entries[].icon",
+                ".entries[].icon.id",
+                ".entries[].icon.keyword",
+                ".entries[].icon.src",
+                ".entries[].icon.url",
+                ".entries[].reply_id",
+                ".entries[].updated_at",
+                ".id",
+                ".section",
+                ".section.id",
+                ".section.name",
+                ".section.order",
+                ".section_order",
+                ".status",
+                ".subject",
+                ".tagged_at",
+                ".version",
+            ]
+        );
+        assert_eq!(exported.version, 1);
+    }
+
+    #[test]
+    fn entries_follow_the_post_and_replies() {
+        let exported = thread().exported(Options::default(), &HashMap::new());
+        let [post, reply] = &exported.entries[..] else {
+            panic!("expected two entries");
+        };
+
+        assert_eq!(post.reply_id, None);
+        assert_eq!(post.character_name.as_deref(), Some("Eve"));
+        assert_eq!(post.updated_at, None);
+        assert_eq!(reply.reply_id, Some(100));
+        assert_eq!(reply.character_name.as_deref(), Some("Evie"));
+        assert_eq!(reply.updated_at, Some(DateTime::default()));
+        assert_eq!(reply.content.text, "Reply");
+        // Without placeholders, replies without an icon have none.
+        assert_eq!(reply.icon, None);
+    }
+
+    #[test]
+    fn placeholder_icons_have_no_id() {
+        let options = Options {
+            placeholder_icons: true,
+            ..Options::default()
+        };
+        let exported = thread().exported(options, &HashMap::new());
+
+        assert_eq!(
+            exported.entries[1].icon,
+            Some(ExportedIcon {
+                id: None,
+                keyword: None,
+                url: placeholder_url(7),
+                src: None,
+            })
+        );
+        // Real icons are kept.
+        assert_eq!(exported.entries[0].icon.as_ref().unwrap().id, Some(3));
+    }
+
+    #[tokio::test]
+    async fn remote_images_keep_their_urls() {
+        let options = Options {
+            html_images: HtmlImages::Remote,
+            ..Options::default()
+        };
+        let export = thread().to_json(options).await.unwrap();
+        assert!(export.assets.is_empty());
+
+        let exported: ExportedThread = serde_json::from_str(&export.json).unwrap();
+        let post = &exported.entries[0];
+        assert_eq!(post.icon.as_ref().unwrap().src, None);
+        assert!(post
+            .content
+            .html
+            .contains(r#"<img src="https://example.com/image.png">"#));
+    }
+
+    #[test]
+    fn inline_images_are_data_urls() {
+        let (url_map, assets) = html_images(images(), HtmlImages::Inline);
+        assert!(assets.is_empty());
+
+        let exported = thread().exported(Options::default(), &url_map);
+        let post = &exported.entries[0];
+        let icon = post.icon.as_ref().unwrap();
+        assert_eq!(icon.url, "https://example.com/icon.png");
+        assert_eq!(icon.src.as_deref(), Some("data:image/png;base64,cG5n"));
+        assert!(post
+            .content
+            .html
+            .contains(r#"<img src="data:image/png;base64,cG5n">"#));
+    }
+
+    #[test]
+    fn asset_images_are_relative_paths() {
+        let images = images();
+        let icon_name = images["https://example.com/icon.png"].name();
+        let image_name = images["https://example.com/image.png"].name();
+        let (url_map, assets) = html_images(images, HtmlImages::Assets);
+        assert_eq!(
+            assets.keys().collect::<Vec<_>>(),
+            [
+                &format!("assets/{icon_name}"),
+                &format!("assets/{image_name}")
+            ]
+        );
+
+        let exported = thread().exported(Options::default(), &url_map);
+        let post = &exported.entries[0];
+        assert_eq!(
+            post.icon.as_ref().unwrap().src,
+            Some(format!("assets/{icon_name}"))
+        );
+        assert!(post
+            .content
+            .html
+            .contains(&format!(r#"<img src="assets/{image_name}">"#)));
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use crate::types::{Continuity, Section};
+
+use super::{author_names, process_content, transform, Options, Thread, SECTIONLESS_NAME};
+
+impl Thread {
+    /// The thread as CommonMark, with each post and reply starting with a bold `Character (Author):` line.
+    pub fn to_markdown(&self, options: Options) -> String {
+        transform::html_to_markdown(&self.markdown_html(options, 1)) + "\n"
+    }
+
+    /// Html that converts cleanly to markdown, with the subject as a heading of the given level.
+    fn markdown_html(&self, options: Options, level: usize) -> String {
+        let post = &self.post;
+        let subject = transform::escape_html(&post.subject);
+        let author_names = author_names(&post.authors);
+        let board_name = transform::escape_html(&post.board.name);
+        let status = transform::escape_html(&post.status);
+        let id = post.id;
+        let reply_count = self.replies.len();
+        let description = post
+            .description
+            .as_deref()
+            .map(|description| process_content(description, options, &HashMap::new()))
+            .unwrap_or_default();
+
+        let mut html = vec![format!(
+            r##"<h{level}>{subject}</h{level}>
+            <p>by {author_names}, in {board_name}</p>
+            <p>Status: <a href="https://glowfic.com/posts/{id}">{status}</a>, {reply_count} replies</p>
+            {description}"##
+        )];
+
+        for block in self.blocks() {
+            let speaker = block
+                .speaker()
+                .map(|speaker| transform::escape_html(&speaker))
+                .map(|speaker| format!("<p><strong>{speaker}:</strong></p>"))
+                .unwrap_or_default();
+            let content = process_content(block.content, options, &HashMap::new());
+            html.push(format!("<hr>{speaker}{content}"));
+        }
+
+        html.join("")
+    }
+}
+
+impl Continuity {
+    /// The entire board as CommonMark, with a heading for each section and thread.
+    pub fn to_markdown(&self, options: Options) -> String {
+        let name = transform::escape_html(&self.board.name);
+        let author_names = author_names(&self.authors());
+        let thread_count = self.threads.len();
+
+        let mut html = vec![format!(
+            r##"<h1>{name}</h1>
+            <p>by {author_names}</p>
+            <p>{thread_count} threads</p>"##
+        )];
+
+        let (sections, sectionless_threads) = self.sections();
+        let section_iter = sections
+            .iter()
+            .map(|(section, threads)| (Some(section), threads))
+            .chain((!sectionless_threads.is_empty()).then_some((None, &sectionless_threads)));
+        for (section, threads) in section_iter {
+            let name = match section {
+                Some(Section { name, .. }) => transform::escape_html(name),
+                None => SECTIONLESS_NAME.to_string(),
+            };
+            html.push(format!("<h2>{name}</h2>"));
+
+            for thread in threads {
+                html.push(thread.markdown_html(options, 3));
+            }
+        }
+
+        transform::html_to_markdown(&html.join("")) + "\n"
+    }
+}
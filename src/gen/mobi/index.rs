@@ -0,0 +1,342 @@
+//! The NCX index, which Kindles show in their "Go To" menu.
+//!
+//! It is an INDX header record describing the entries (in a TAGX block), the INDX records of the entries,
+//! and CNCX records with their labels. Each text record also ends with a trailing byte sequence (TBS)
+//! naming the entries it has a part of. Like Calibre does for books, the entries are all at the same level.
+
+use super::writer::{pad, put_u16, put_u32};
+
+/// The label and start of a chapter in the NCX.
+pub(super) struct TocEntry {
+    pub label: String,
+    /// In bytes from the start of the html.
+    pub position: usize,
+}
+
+/// The length of the header of every INDX record.
+const INDX_HEADER_LENGTH: usize = 192;
+/// Records can't be larger than 64KB, and some room is kept like kindlegen does.
+const RECORD_LIMIT: usize = 0x10000 - 1024;
+/// Longer labels are cut.
+const MAX_LABEL_LENGTH: usize = 500;
+/// The tags of each entry, with their bitmask in the control byte:
+/// the position (1), length (2), label offset (3) and depth (4).
+const TAGS: [(u8, u8); 4] = [(1, 0b0001), (2, 0b0010), (3, 0b0100), (4, 0b1000)];
+
+struct Entry {
+    position: usize,
+    length: usize,
+    /// In the CNCX records, which are 0x10000 apart.
+    label_offset: usize,
+}
+
+pub(super) struct Ncx {
+    entries: Vec<Entry>,
+    cncx_records: Vec<Vec<u8>>,
+}
+impl Ncx {
+    /// [None] if there are no entries, as an empty index is invalid.
+    pub fn new(toc: &[TocEntry], text_length: usize) -> Option<Self> {
+        if toc.is_empty() {
+            return None;
+        }
+
+        let mut cncx_records = vec![];
+        let mut cncx = vec![];
+        let mut entries = vec![];
+        for (i, TocEntry { label, position }) in toc.iter().enumerate() {
+            let label: String = label.chars().take(MAX_LABEL_LENGTH).collect();
+            let mut raw = forward_int(label.len());
+            raw.extend(label.as_bytes());
+            if cncx.len() + raw.len() > RECORD_LIMIT {
+                pad(&mut cncx);
+                cncx_records.push(std::mem::take(&mut cncx));
+            }
+
+            let next = toc.get(i + 1).map_or(text_length, |next| next.position);
+            entries.push(Entry {
+                position: *position,
+                length: next.saturating_sub(*position),
+                label_offset: cncx_records.len() * 0x10000 + cncx.len(),
+            });
+            cncx.extend(raw);
+        }
+        pad(&mut cncx);
+        cncx_records.push(cncx);
+
+        Some(Self {
+            entries,
+            cncx_records,
+        })
+    }
+
+    /// The INDX header record, followed by the INDX records of the entries and the CNCX records.
+    pub fn records(&self) -> Vec<Vec<u8>> {
+        let encoded: Vec<Vec<u8>> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| entry_bytes(i, entry))
+            .collect();
+
+        // As many entries in each record as fit, and the index of the last one in each.
+        let mut groups: Vec<(usize, &[Vec<u8>])> = vec![];
+        let mut start = 0;
+        let mut size = 0;
+        for (i, entry) in encoded.iter().enumerate() {
+            // The entry and its offset in the IDXT block, with some room for the padding.
+            if i > start
+                && INDX_HEADER_LENGTH + size + entry.len() + 2 * (i - start + 1) + 12 > RECORD_LIMIT
+            {
+                groups.push((i - 1, &encoded[start..i]));
+                start = i;
+                size = 0;
+            }
+            size += entry.len();
+        }
+        groups.push((encoded.len() - 1, &encoded[start..]));
+
+        let mut records = vec![self.header_record(&groups)];
+        records.extend(groups.iter().map(|(_, entries)| entries_record(entries)));
+        records.extend(self.cncx_records.iter().cloned());
+        records
+    }
+
+    fn header_record(&self, groups: &[(usize, &[Vec<u8>])]) -> Vec<u8> {
+        let mut tagx = b"TAGX".to_vec();
+        put_u32(&mut tagx, 12 + 4 * (TAGS.len() as u32 + 1));
+        put_u32(&mut tagx, 1); // Control bytes
+        for (tag, bitmask) in TAGS {
+            tagx.extend([tag, 1, bitmask, 0]);
+        }
+        tagx.extend([0, 0, 0, 1]); // End of the tags
+
+        // The index of the last entry of each record, and their count.
+        let mut geometry = vec![];
+        let mut offsets = vec![];
+        for (last, entries) in groups {
+            offsets.push(INDX_HEADER_LENGTH + tagx.len() + geometry.len());
+            geometry.extend(entry_id(*last));
+            put_u16(&mut geometry, entries.len() as u16);
+        }
+
+        let mut record = b"INDX".to_vec();
+        put_u32(&mut record, INDX_HEADER_LENGTH as u32);
+        record.extend([0; 8]);
+        put_u32(&mut record, 2); // Index type
+        let idxt_offset_position = record.len();
+        put_u32(&mut record, 0); // IDXT offset, set below
+        put_u32(&mut record, groups.len() as u32);
+        put_u32(&mut record, 65001); // UTF-8
+        put_u32(&mut record, 0xFFFF_FFFF);
+        put_u32(&mut record, self.entries.len() as u32);
+        put_u32(&mut record, 0); // ORDT offset
+        put_u32(&mut record, 0); // LIGT offset
+        put_u32(&mut record, 0); // LIGT count
+        put_u32(&mut record, self.cncx_records.len() as u32);
+        record.extend([0; 124]);
+        put_u32(&mut record, INDX_HEADER_LENGTH as u32); // TAGX offset
+        record.extend([0; 8]);
+        debug_assert_eq!(record.len(), INDX_HEADER_LENGTH);
+
+        record.extend(tagx);
+        record.extend(geometry);
+        pad(&mut record);
+
+        let idxt_offset = record.len() as u32;
+        record[idxt_offset_position..idxt_offset_position + 4]
+            .copy_from_slice(&idxt_offset.to_be_bytes());
+        record.extend(b"IDXT");
+        for offset in offsets {
+            put_u16(&mut record, offset as u16);
+        }
+
+        pad(&mut record);
+        record
+    }
+
+    /// The trailing byte sequence of the text record between the positions, with its size.
+    /// This follows what Calibre writes for books.
+    pub fn trailing_entry(&self, start: usize, end: usize) -> Vec<u8> {
+        let mut starts = 0;
+        let mut ends = 0;
+        let mut completes = 0;
+        let mut spans = None;
+        let mut first = None;
+        for (i, entry) in self.entries.iter().enumerate() {
+            let entry_end = entry.position + entry.length;
+            if entry.position >= end {
+                break;
+            }
+            if entry_end <= start {
+                continue;
+            }
+            first.get_or_insert(i);
+            match (entry.position >= start, entry_end <= end) {
+                (true, true) => completes += 1,
+                (true, false) => starts += 1,
+                (false, true) => ends += 1,
+                (false, false) => spans = Some(i),
+            }
+        }
+
+        let sequence = match (spans, first) {
+            (Some(i), _) => tbs(i, 0b011, &[]),
+            (None, Some(i)) if completes == 0 && starts + ends == 1 => tbs(i, 0b010, &[]),
+            (None, Some(i)) => tbs(i, 0b110, &[(starts + ends + completes) as u8]),
+            (None, None) => vec![],
+        };
+
+        with_backward_size(sequence)
+    }
+}
+
+/// The entry id (its index, in hexadecimal) and its tags.
+fn entry_bytes(index: usize, entry: &Entry) -> Vec<u8> {
+    let mut bytes = entry_id(index);
+    bytes.push(
+        TAGS.iter()
+            .fold(0, |control, (_, bitmask)| control | bitmask),
+    );
+    bytes.extend(forward_int(entry.position));
+    bytes.extend(forward_int(entry.length));
+    bytes.extend(forward_int(entry.label_offset));
+    bytes.extend(forward_int(0)); // Depth
+    bytes
+}
+
+fn entry_id(index: usize) -> Vec<u8> {
+    let mut id = format!("{index:X}");
+    if id.len() % 2 == 1 {
+        id.insert(0, '0');
+    }
+    let mut bytes = vec![id.len() as u8];
+    bytes.extend(id.as_bytes());
+    bytes
+}
+
+fn entries_record(entries: &[Vec<u8>]) -> Vec<u8> {
+    let mut body = vec![];
+    let mut offsets = vec![];
+    for entry in entries {
+        offsets.push(INDX_HEADER_LENGTH + body.len());
+        body.extend(entry);
+    }
+    pad(&mut body);
+
+    let mut record = b"INDX".to_vec();
+    put_u32(&mut record, INDX_HEADER_LENGTH as u32);
+    put_u32(&mut record, 0);
+    put_u32(&mut record, 1);
+    put_u32(&mut record, 0);
+    put_u32(&mut record, (INDX_HEADER_LENGTH + body.len()) as u32); // IDXT offset
+    put_u32(&mut record, entries.len() as u32);
+    record.extend([0xFF; 8]);
+    record.extend([0; 156]);
+    debug_assert_eq!(record.len(), INDX_HEADER_LENGTH);
+
+    record.extend(body);
+    record.extend(b"IDXT");
+    for offset in offsets {
+        put_u16(&mut record, offset as u16);
+    }
+
+    pad(&mut record);
+    record
+}
+
+/// The entry index shifted left by the size of the flags (3 for books), then the extra values of the flags.
+fn tbs(index: usize, flags: usize, counts: &[u8]) -> Vec<u8> {
+    let mut sequence = forward_int((index << 3) | flags);
+    if flags & 0b010 != 0 {
+        sequence.extend(forward_int(0));
+    }
+    sequence.extend(counts);
+    if flags & 0b001 != 0 {
+        sequence.extend(forward_int(0));
+    }
+    sequence
+}
+
+/// Trailing entries end with their size (including the size itself), read backwards from the end of the record.
+fn with_backward_size(mut data: Vec<u8>) -> Vec<u8> {
+    let mut size_length = 1;
+    while backward_int(data.len() + size_length).len() != size_length {
+        size_length += 1;
+    }
+    data.extend(backward_int(data.len() + size_length));
+    data
+}
+
+/// A variable width integer, 7 bits per byte, big-endian, with the high bit set on the last byte.
+fn forward_int(value: usize) -> Vec<u8> {
+    let mut bytes = seven_bit_groups(value);
+    *bytes.last_mut().unwrap() |= 0x80;
+    bytes
+}
+
+/// Like [forward_int], but with the high bit set on the first byte, to be read from the end.
+fn backward_int(value: usize) -> Vec<u8> {
+    let mut bytes = seven_bit_groups(value);
+    bytes[0] |= 0x80;
+    bytes
+}
+
+fn seven_bit_groups(mut value: usize) -> Vec<u8> {
+    let mut bytes = vec![];
+    loop {
+        bytes.insert(0, (value & 0x7F) as u8);
+        value >>= 7;
+        if value == 0 {
+            break bytes;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variable_width_integers() {
+        assert_eq!(forward_int(0), [0x80]);
+        assert_eq!(forward_int(0x7F), [0xFF]);
+        assert_eq!(forward_int(0x80), [0x01, 0x80]);
+        assert_eq!(backward_int(0x80), [0x81, 0x00]);
+    }
+
+    #[test]
+    fn trailing_entries_count_their_size() {
+        assert_eq!(with_backward_size(vec![]), [0x81]);
+        assert_eq!(with_backward_size(vec![7; 126]).len(), 127);
+        // 127 bytes of data and one byte of size would be 128, which needs two bytes.
+        let entry = with_backward_size(vec![7; 127]);
+        assert_eq!(entry.len(), 129);
+        assert_eq!(entry[127..], [0x81, 0x01]);
+    }
+
+    #[test]
+    fn entries_end_where_the_next_starts() {
+        let toc = [
+            TocEntry {
+                label: "One".to_string(),
+                position: 10,
+            },
+            TocEntry {
+                label: "Two".to_string(),
+                position: 5000,
+            },
+        ];
+        let ncx = Ncx::new(&toc, 9000).unwrap();
+        let lengths: Vec<usize> = ncx.entries.iter().map(|entry| entry.length).collect();
+        assert_eq!(lengths, [4990, 4000]);
+        assert_eq!(ncx.entries[1].label_offset, 4);
+
+        // The first record only has the start of the first entry, the second one has the end of it
+        // and the start of the next one, and the last one has the end of that.
+        assert_eq!(ncx.trailing_entry(0, 4096), [0x82, 0x80, 0x83]);
+        assert_eq!(ncx.trailing_entry(4096, 8192), [0x86, 0x80, 2, 0x84]);
+        assert_eq!(ncx.trailing_entry(8192, 9000), [0x8A, 0x80, 0x83]);
+        assert!(Ncx::new(&[], 100).is_none());
+    }
+}
@@ -0,0 +1,516 @@
+//! Kindle books, in the MOBI format that every Kindle can read.
+//!
+//! The pages are the same xhtml as in epubs, put one after the other with page breaks in between.
+//! MOBI readers ignore stylesheets, so the classes of `book.css` are replaced with html attributes,
+//! and the table of contents is a page of links, which the Kindle menu opens (through the guide).
+//! The chapters are also in the NCX index, for the "Go To" menu, where they aren't nested.
+//! The KF8 (AZW3) sections are not written.
+
+mod index;
+mod writer;
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    sync::OnceLock,
+};
+
+use lol_html::{
+    html_content::ContentType, rewrite_str, ElementContentHandlers, RewriteStrSettings,
+};
+use regex::Regex;
+
+use crate::{
+    intern_images::{process_images, InternedImage},
+    types::{Continuity, Section},
+    Post, Thread,
+};
+
+use self::{index::TocEntry, writer::Book};
+
+use super::{epub::ReplyPage, transform, DetailsMode, EpubVersion, ImageFormat, Options};
+
+/// In pixels.
+const COVER_WIDTH: u32 = 800;
+
+/// An xhtml page of the book.
+struct Chapter {
+    /// Only the chapters with a title are in the table of contents.
+    title: Option<String>,
+    level: usize,
+    page: String,
+}
+
+impl Thread {
+    /// A Kindle book of the thread, with the images embedded.
+    pub async fn to_mobi(&self, options: Options) -> Result<Vec<u8>, Box<dyn Error>> {
+        let options = kindle_options(options);
+        let images = process_images(self.images_to_intern(options).await?, options).await?;
+        let cover = kindle_cover(super::cover::image(&self.post.subject, &self.post.authors))?;
+        let images = ImageRecords::new(images, cover);
+        let url_map = &images.url_map;
+
+        let mut chapters = vec![Chapter {
+            title: Some("Title".to_string()),
+            level: 0,
+            page: self.to_title_page(options, url_map),
+        }];
+        chapters.push(Chapter {
+            title: Some("Description".to_string()),
+            level: 1,
+            page: self.description_page(options, url_map),
+        });
+        for ReplyPage { label, xhtml } in
+            self.reply_pages(&self.split_replies(options), 0, options, url_map)
+        {
+            chapters.push(Chapter {
+                title: Some(label),
+                level: 1,
+                page: xhtml,
+            });
+        }
+        chapters.push(Chapter {
+            title: Some("Copyright".to_string()),
+            level: 0,
+            page: self.to_copyright_page(options, url_map),
+        });
+
+        let (html, toc) = mobi_html(&chapters, &images);
+        let post = &self.post;
+        Ok(Book {
+            title: post.subject.clone(),
+            authors: post.authors.iter().map(|a| a.username.clone()).collect(),
+            description: post.description.as_deref().map(transform::html_to_text),
+            published: Some(post.created_at.to_rfc3339()),
+            language: options.language,
+            unique_id: unique_id(self.uuid(options).as_bytes()),
+            timestamp: post.tagged_at.timestamp() as u32,
+            html,
+            toc,
+            cover: Some(0),
+            images: images.records,
+        }
+        .to_bytes())
+    }
+
+    /// The title page of the thread, followed by its content, starting at `base_level`.
+    fn mobi_chapters(
+        &self,
+        base_level: usize,
+        options: Options,
+        url_map: &HashMap<String, String>,
+    ) -> Vec<Chapter> {
+        let Post { subject, .. } = &self.post;
+
+        let mut chapters = vec![
+            Chapter {
+                title: Some(subject.clone()),
+                level: base_level,
+                page: self.to_title_page(options, url_map),
+            },
+            Chapter {
+                title: None,
+                level: base_level + 1,
+                page: self.description_page(options, url_map),
+            },
+        ];
+        chapters.extend(
+            self.reply_pages(&self.split_replies(options), 0, options, url_map)
+                .into_iter()
+                .map(|ReplyPage { label, xhtml }| Chapter {
+                    title: options.nested_toc.then_some(label),
+                    level: base_level + 1,
+                    page: xhtml,
+                }),
+        );
+
+        chapters
+    }
+}
+
+impl Continuity {
+    /// A Kindle book of the entire board, with the images embedded.
+    /// The table of contents has the same hierarchy as in epubs.
+    pub async fn to_mobi(&self, options: Options) -> Result<Vec<u8>, Box<dyn Error>> {
+        let options = kindle_options(options);
+        let authors = self.authors();
+        let images = process_images(self.images_to_intern(options).await?, options).await?;
+        let cover = kindle_cover(super::cover::image(&self.board.name, &authors))?;
+        let images = ImageRecords::new(images, cover);
+        let url_map = &images.url_map;
+
+        let mut chapters = vec![Chapter {
+            title: Some("Title".to_string()),
+            level: 0,
+            page: self.to_title_page(options, url_map),
+        }];
+
+        let (sections, sectionless_threads) = self.sections();
+        for (section, threads) in &sections {
+            chapters.push(Chapter {
+                title: Some(section.name.clone()),
+                level: 1,
+                page: section.to_title_page(threads, options, url_map),
+            });
+            for thread in threads {
+                chapters.extend(thread.mobi_chapters(2, options, url_map));
+            }
+        }
+        if !sectionless_threads.is_empty() && !sections.is_empty() {
+            chapters.push(Chapter {
+                title: None,
+                level: 1,
+                page: Section::sectionless_title_page(&sectionless_threads, options, url_map),
+            });
+        }
+        for thread in &sectionless_threads {
+            let base_level = if sections.is_empty() { 1 } else { 2 };
+            chapters.extend(thread.mobi_chapters(base_level, options, url_map));
+        }
+
+        chapters.push(Chapter {
+            title: Some("Copyright".to_string()),
+            level: 0,
+            page: self.to_copyright_page(options, url_map),
+        });
+
+        let (html, toc) = mobi_html(&chapters, &images);
+        Ok(Book {
+            title: self.board.name.clone(),
+            authors: authors.into_iter().map(|a| a.username).collect(),
+            description: None,
+            published: self.created_at().map(|date| date.to_rfc3339()),
+            language: options.language,
+            unique_id: unique_id(self.uuid(options).as_bytes()),
+            timestamp: self.tagged_at().map_or(0, |date| date.timestamp() as u32),
+            html,
+            toc,
+            cover: Some(0),
+            images: images.records,
+        }
+        .to_bytes())
+    }
+}
+
+/// Kindles can't show `details` tags, nor WebP and AVIF images.
+fn kindle_options(options: Options) -> Options {
+    Options {
+        details: DetailsMode::Flatten,
+        image_format: ImageFormat::Compatible,
+        // MOBI pages are html, without the semantics of EPUB3.
+        epub_version: EpubVersion::Epub2,
+        // The pages are joined into one html, where the `#footnote-N` links of each page would collide.
+        link_footnotes: false,
+        ..options
+    }
+}
+
+/// Kindles only show covers of up to 127KB, which the generated png is not.
+fn kindle_cover(png: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let cover = InternedImage {
+        id: None,
+        original_url: "cover.png".to_string(),
+        mime: mime::IMAGE_PNG,
+        data: png,
+    };
+
+    let cover = cover.resize_down(COVER_WIDTH)?.try_into_jpeg();
+    Ok(cover
+        .ok_or("the cover could not be converted to jpeg")?
+        .data)
+}
+
+fn unique_id(uuid: &[u8; 16]) -> u32 {
+    u32::from_be_bytes(uuid[..4].try_into().unwrap())
+}
+
+/// The images of the book, in the order of their records, starting with the cover.
+struct ImageRecords {
+    records: Vec<Vec<u8>>,
+    /// The urls of the interned images that MOBI readers support, and their names.
+    url_map: HashMap<String, String>,
+    /// The image names, and their `recindex` (which starts at 1).
+    indexes: HashMap<String, usize>,
+}
+impl ImageRecords {
+    fn new(images: HashMap<String, InternedImage>, cover: Vec<u8>) -> Self {
+        let images: HashMap<String, InternedImage> = images
+            .into_iter()
+            .filter(|(_, image)| {
+                matches!(
+                    (image.mime.type_(), image.mime.subtype()),
+                    (mime::IMAGE, mime::BMP | mime::GIF | mime::JPEG | mime::PNG)
+                )
+            })
+            .collect();
+        let url_map = images
+            .iter()
+            .map(|(url, image)| (url.clone(), image.name()))
+            .collect();
+
+        // Deduplicated images are shared by several urls, but should only be added once.
+        let unique: BTreeMap<String, InternedImage> = images
+            .into_values()
+            .map(|image| (image.name(), image))
+            .collect();
+
+        let mut records = vec![cover];
+        let mut indexes = HashMap::new();
+        for (name, image) in unique {
+            records.push(image.data);
+            indexes.insert(name, records.len());
+        }
+
+        Self {
+            records,
+            url_map,
+            indexes,
+        }
+    }
+}
+
+/// Stands in for the position of a chapter in `filepos` attributes until all the positions are known.
+/// Like positions, it is 10 characters long.
+fn filepos_placeholder(chapter: usize) -> String {
+    format!("filepos=@{chapter:09}")
+}
+
+/// All the chapters as a single html document, with the table of contents after the first one,
+/// and the position of each chapter that has a title.
+fn mobi_html(chapters: &[Chapter], images: &ImageRecords) -> (Vec<u8>, Vec<TocEntry>) {
+    let toc_index = chapters.len();
+    let first_content = 1.min(chapters.len() - 1);
+
+    let mut html = format!(
+        r##"<html><head><guide><reference type="toc" title="Table of Contents" {} /><reference type="text" title="Beginning" {} /></guide></head><body>"##,
+        filepos_placeholder(toc_index),
+        filepos_placeholder(first_content),
+    );
+
+    // In bytes from the start of the text.
+    let mut positions = vec![0; chapters.len() + 1];
+    for (i, chapter) in chapters.iter().enumerate() {
+        if i > 0 {
+            html.push_str("<mbp:pagebreak/>");
+        }
+        positions[i] = html.len();
+        html.push_str(&kindle_body(&chapter.page, images));
+
+        if i == 0 {
+            html.push_str("<mbp:pagebreak/>");
+            positions[toc_index] = html.len();
+            html.push_str(&table_of_contents(chapters));
+        }
+    }
+    html.push_str("</body></html>");
+
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    let placeholder = PLACEHOLDER.get_or_init(|| Regex::new("filepos=@([0-9]{9})").unwrap());
+    let html = placeholder
+        .replace_all(&html, |captures: &regex::Captures| {
+            let chapter: usize = captures[1].parse().unwrap();
+            format!("filepos={:010}", positions[chapter])
+        })
+        .into_owned()
+        .into_bytes();
+
+    let toc = chapters
+        .iter()
+        .zip(positions)
+        .filter_map(|(chapter, position)| {
+            Some(TocEntry {
+                label: chapter.title.clone()?,
+                position,
+            })
+        })
+        .collect();
+
+    (html, toc)
+}
+
+/// Links to the chapters that have a title, nested by level.
+fn table_of_contents(chapters: &[Chapter]) -> String {
+    let mut toc = vec![r##"<div align="center"><h1>Table of Contents</h1></div>"##.to_string()];
+
+    let mut depth = 0;
+    for (i, chapter) in chapters.iter().enumerate() {
+        let Some(title) = &chapter.title else {
+            continue;
+        };
+        while depth < chapter.level {
+            toc.push("<blockquote>".to_string());
+            depth += 1;
+        }
+        while depth > chapter.level {
+            toc.push("</blockquote>".to_string());
+            depth -= 1;
+        }
+        let title = transform::escape_html(title);
+        toc.push(format!(
+            r##"<p><a {}>{title}</a></p>"##,
+            filepos_placeholder(i)
+        ));
+    }
+    toc.extend((0..depth).map(|_| "</blockquote>".to_string()));
+
+    toc.join("\n")
+}
+
+/// The content of the `body` of an xhtml page, with the images referring to their records,
+/// and the layout from `book.css` done with attributes.
+fn kindle_body(page: &str, images: &ImageRecords) -> String {
+    let body = match (page.find("<body>"), page.rfind("</body>")) {
+        (Some(start), Some(end)) => &page[start + "<body>".len()..end],
+        _ => page,
+    };
+    // Void elements are closed the xml way, which html parsers could read as a second `<br>`.
+    let body = body.replace("<br></br>", "<br/>").replace("></img>", "/>");
+
+    rewrite_str(
+        &body,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                (
+                    Cow::Owned("img[src]".parse().unwrap()),
+                    ElementContentHandlers::default().element(|el| {
+                        let src = el.get_attribute("src").unwrap();
+                        match images.indexes.get(&src) {
+                            Some(index) => {
+                                el.remove_attribute("src");
+                                el.set_attribute("recindex", &format!("{index:05}"))?;
+                            }
+                            // Unsupported or remote images become links. Attribute values
+                            // come still escaped, so they are decoded before being escaped again.
+                            None => {
+                                let alt = el.get_attribute("alt").unwrap_or("image".to_string());
+                                let link = format!(
+                                    r##"<a href="{}">[{}]</a>"##,
+                                    transform::escape_html(&html_escape::decode_html_entities(
+                                        &src
+                                    )),
+                                    transform::escape_html(&html_escape::decode_html_entities(
+                                        &alt
+                                    ))
+                                );
+                                el.replace(&link, ContentType::Html);
+                            }
+                        }
+                        Ok(())
+                    }),
+                ),
+                (
+                    Cow::Owned(
+                        ".title-page, .copyright-page, .description"
+                            .parse()
+                            .unwrap(),
+                    ),
+                    ElementContentHandlers::default().element(|el| {
+                        el.set_attribute("align", "center")?;
+                        Ok(())
+                    }),
+                ),
+                (
+                    // Instead of floating next to the content, the icon goes above the caption.
+                    Cow::Owned(".icon-caption".parse().unwrap()),
+                    ElementContentHandlers::default().element(|el| {
+                        el.set_tag_name("b")?;
+                        el.before("<br/>", ContentType::Html);
+                        el.after("<br/>", ContentType::Html);
+                        Ok(())
+                    }),
+                ),
+            ],
+            ..RewriteStrSettings::default()
+        },
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapter(title: Option<&str>, level: usize, body: &str) -> Chapter {
+        Chapter {
+            title: title.map(str::to_string),
+            level,
+            page: format!("<html><head></head><body>{body}</body></html>"),
+        }
+    }
+
+    fn no_images() -> ImageRecords {
+        ImageRecords::new(HashMap::new(), vec![])
+    }
+
+    #[test]
+    fn chapters_are_found_at_their_filepos() {
+        let chapters = [
+            chapter(Some("Title"), 0, "<p>Title page</p>"),
+            chapter(None, 1, "<p>Description</p>"),
+            chapter(Some("Part 0"), 1, "<p>First reply</p>"),
+        ];
+        let (html, toc) = mobi_html(&chapters, &no_images());
+        let html = String::from_utf8(html).unwrap();
+
+        assert!(!html.contains("filepos=@"));
+        let labels: Vec<&str> = toc.iter().map(|entry| &*entry.label).collect();
+        assert_eq!(labels, ["Title", "Part 0"]);
+        assert!(html[toc[0].position..].starts_with("<p>Title page</p>"));
+        assert!(html[toc[1].position..].starts_with("<p>First reply</p>"));
+
+        // The table of contents links to the same positions.
+        assert!(html.contains(&format!(r#"<a filepos={:010}>Part 0</a>"#, toc[1].position)));
+        // The guide leads to the table of contents, right after the title page.
+        let toc_position = html
+            .find(r#"<div align="center"><h1>Table of Contents"#)
+            .unwrap();
+        assert!(html.contains(&format!(
+            r#"<reference type="toc" title="Table of Contents" filepos={toc_position:010} />"#
+        )));
+    }
+
+    #[test]
+    fn the_table_of_contents_is_nested_by_level() {
+        let chapters = [
+            chapter(Some("Board"), 0, ""),
+            chapter(Some("Section"), 1, ""),
+            chapter(Some("Thread"), 2, ""),
+            chapter(Some("Copyright"), 0, ""),
+        ];
+        let toc = table_of_contents(&chapters);
+        let structure: Vec<&str> = toc
+            .lines()
+            .skip(1)
+            .map(|line| if line.starts_with("<p>") { "p" } else { line })
+            .collect();
+        assert_eq!(
+            structure,
+            [
+                "p",
+                "<blockquote>",
+                "p",
+                "<blockquote>",
+                "p",
+                "</blockquote>",
+                "</blockquote>",
+                "p"
+            ]
+        );
+    }
+
+    #[test]
+    fn kindle_bodies_use_records_and_attributes() {
+        let images = ImageRecords {
+            records: vec![vec![], vec![]],
+            url_map: HashMap::new(),
+            indexes: HashMap::from([("image.png".to_string(), 2)]),
+        };
+        let page = r#"<html><body><div class="title-page">A<br></br>B</div><img src="image.png"></img><img src="https://example.com/a.webp" alt="A &amp; B"></img></body></html>"#;
+
+        assert_eq!(
+            kindle_body(page, &images),
+            r#"<div class="title-page" align="center">A<br/>B</div><img recindex="00002" /><a href="https://example.com/a.webp">[A &#38; B]</a>"#
+        );
+    }
+}
@@ -0,0 +1,444 @@
+//! The MOBI (version 6) container: a Palm database whose first record describes the book,
+//! followed by the html split into text records, the NCX index, the images, and a few end-of-book records.
+
+use crate::gen::LanguageTag;
+
+use super::index::{Ncx, TocEntry};
+
+/// The uncompressed size of each text record.
+const TEXT_RECORD_SIZE: usize = 4096;
+/// The length of the MOBI header, which starts after the 16 bytes of the PalmDOC header.
+const MOBI_HEADER_LENGTH: u32 = 232;
+/// UTF-8.
+const TEXT_ENCODING: u32 = 65001;
+/// Used by unset record numbers and indexes.
+const NULL_INDEX: u32 = 0xFFFF_FFFF;
+/// Text records end with the bytes of any multibyte character they cut, followed by their count.
+const EXTRA_DATA_MULTIBYTE: u32 = 0b1;
+/// Text records end with the trailing byte sequence of the NCX entries in them.
+const EXTRA_DATA_INDEXING: u32 = 0b10;
+
+const FLIS: &[u8] = b"FLIS\0\0\0\x08\0\x41\0\0\0\0\0\0\xff\xff\xff\xff\0\x01\0\x03\0\0\0\x03\0\0\0\x01\xff\xff\xff\xff";
+const EOF: &[u8] = b"\xe9\x8e\r\n";
+
+pub(super) struct Book {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub description: Option<String>,
+    /// An RFC 3339 date.
+    pub published: Option<String>,
+    pub language: LanguageTag,
+    pub unique_id: u32,
+    /// In seconds since the unix epoch.
+    pub timestamp: u32,
+    /// The html, in which images refer to their position in [Book::images] plus one (`<img recindex="00001">`).
+    pub html: Vec<u8>,
+    /// The chapters for the NCX index, in order.
+    pub toc: Vec<TocEntry>,
+    pub images: Vec<Vec<u8>>,
+    /// The index of the cover in [Book::images].
+    pub cover: Option<u32>,
+}
+impl Book {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let ncx = Ncx::new(&self.toc, self.html.len());
+        let text_records = text_records(&self.html, ncx.as_ref());
+        let index_records = ncx.as_ref().map(Ncx::records).unwrap_or_default();
+
+        let first_index = 1 + text_records.len() as u32;
+        let first_image = first_index + index_records.len() as u32;
+        let flis = first_image + self.images.len() as u32;
+        let fcis = flis + 1;
+
+        let mut records = vec![self.header_record(Layout {
+            text_record_count: text_records.len() as u16,
+            ncx: ncx.is_some().then_some(first_index),
+            first_image,
+            last_content: flis as u16 - 1,
+            flis,
+            fcis,
+        })];
+        records.extend(text_records);
+        records.extend(index_records);
+        records.extend(self.images.iter().cloned());
+        records.push(FLIS.to_vec());
+        records.push(self.fcis_record());
+        records.push(EOF.to_vec());
+
+        self.palm_database(&records)
+    }
+
+    fn palm_database(&self, records: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = vec![];
+
+        // The database name is limited to 31 ascii characters.
+        let mut name: Vec<u8> = self
+            .title
+            .bytes()
+            .map(|b| if b.is_ascii_alphanumeric() { b } else { b'_' })
+            .take(31)
+            .collect();
+        name.resize(32, 0);
+        file.extend(name);
+
+        put_u16(&mut file, 0); // Attributes
+        put_u16(&mut file, 0); // Version
+        put_u32(&mut file, self.timestamp); // Creation date
+        put_u32(&mut file, self.timestamp); // Modification date
+        put_u32(&mut file, 0); // Last backup date
+        put_u32(&mut file, 0); // Modification number
+        put_u32(&mut file, 0); // App info
+        put_u32(&mut file, 0); // Sort info
+        file.extend(b"BOOKMOBI");
+        put_u32(&mut file, 2 * records.len() as u32 - 1); // Unique id seed
+        put_u32(&mut file, 0); // Next record list
+        put_u16(&mut file, records.len() as u16);
+
+        let mut offset = file.len() + 8 * records.len() + 2;
+        for (i, record) in records.iter().enumerate() {
+            put_u32(&mut file, offset as u32);
+            // The attributes (0) followed by a 3 bytes unique id.
+            put_u32(&mut file, (2 * i as u32) & 0x00FF_FFFF);
+            offset += record.len();
+        }
+        put_u16(&mut file, 0); // Gap
+
+        for record in records {
+            file.extend(record);
+        }
+
+        file
+    }
+
+    fn header_record(&self, layout: Layout) -> Vec<u8> {
+        let Layout {
+            text_record_count,
+            ncx,
+            first_image,
+            last_content,
+            flis,
+            fcis,
+        } = layout;
+        let mut record = vec![];
+
+        // PalmDOC header
+        put_u16(&mut record, 1); // No compression
+        put_u16(&mut record, 0);
+        put_u32(&mut record, self.html.len() as u32);
+        put_u16(&mut record, text_record_count);
+        put_u16(&mut record, TEXT_RECORD_SIZE as u16);
+        put_u16(&mut record, 0); // No encryption
+        put_u16(&mut record, 0);
+
+        // MOBI header
+        record.extend(b"MOBI");
+        put_u32(&mut record, MOBI_HEADER_LENGTH);
+        put_u32(&mut record, 2); // Book
+        put_u32(&mut record, TEXT_ENCODING);
+        put_u32(&mut record, self.unique_id);
+        put_u32(&mut record, 6); // File version
+        for _ in 0..10 {
+            put_u32(&mut record, NULL_INDEX); // Dictionary indexes
+        }
+        put_u32(&mut record, ncx.unwrap_or(first_image)); // First non-book record
+        let full_name_offset_position = record.len();
+        put_u32(&mut record, 0); // Full name offset, set below
+        put_u32(&mut record, self.title.len() as u32);
+        put_u32(&mut record, locale(self.language.primary()));
+        put_u32(&mut record, 0); // Input language
+        put_u32(&mut record, 0); // Output language
+        put_u32(&mut record, 6); // Minimum reader version
+        put_u32(
+            &mut record,
+            if self.images.is_empty() {
+                NULL_INDEX
+            } else {
+                first_image
+            },
+        );
+        for _ in 0..4 {
+            put_u32(&mut record, 0); // Huffman compression
+        }
+        put_u32(&mut record, 0x40); // Has an EXTH header
+        record.extend([0; 32]);
+        put_u32(&mut record, NULL_INDEX);
+        put_u32(&mut record, NULL_INDEX); // DRM offset
+        put_u32(&mut record, 0); // DRM count
+        put_u32(&mut record, 0); // DRM size
+        put_u32(&mut record, 0); // DRM flags
+        record.extend([0; 8]);
+        put_u16(&mut record, 1); // First content record
+        put_u16(&mut record, last_content);
+        put_u32(&mut record, 1);
+        put_u32(&mut record, fcis);
+        put_u32(&mut record, 1);
+        put_u32(&mut record, flis);
+        put_u32(&mut record, 1);
+        record.extend([0; 8]);
+        put_u32(&mut record, NULL_INDEX);
+        put_u32(&mut record, 0);
+        put_u32(&mut record, NULL_INDEX);
+        put_u32(&mut record, NULL_INDEX);
+        match ncx {
+            Some(ncx) => {
+                put_u32(&mut record, EXTRA_DATA_MULTIBYTE | EXTRA_DATA_INDEXING);
+                put_u32(&mut record, ncx);
+            }
+            None => {
+                put_u32(&mut record, EXTRA_DATA_MULTIBYTE);
+                put_u32(&mut record, NULL_INDEX); // No index
+            }
+        }
+        debug_assert_eq!(record.len(), 16 + MOBI_HEADER_LENGTH as usize);
+
+        record.extend(self.exth_header());
+
+        let full_name_offset = record.len() as u32;
+        record[full_name_offset_position..full_name_offset_position + 4]
+            .copy_from_slice(&full_name_offset.to_be_bytes());
+        record.extend(self.title.as_bytes());
+        record.extend([0; 2]);
+        pad(&mut record);
+
+        record
+    }
+
+    fn exth_header(&self) -> Vec<u8> {
+        let mut entries: Vec<(u32, Vec<u8>)> = vec![];
+        for author in &self.authors {
+            entries.push((100, author.as_bytes().to_vec()));
+        }
+        if let Some(description) = &self.description {
+            entries.push((103, description.as_bytes().to_vec()));
+        }
+        if let Some(published) = &self.published {
+            entries.push((106, published.as_bytes().to_vec()));
+        }
+        if let Some(cover) = self.cover {
+            entries.push((201, cover.to_be_bytes().to_vec())); // Cover
+            entries.push((202, cover.to_be_bytes().to_vec())); // Thumbnail
+        }
+        entries.push((501, b"EBOK".to_vec())); // Document type
+        entries.push((503, self.title.as_bytes().to_vec()));
+        entries.push((524, self.language.as_str().as_bytes().to_vec()));
+
+        let mut header = vec![];
+        header.extend(b"EXTH");
+        let length = 12
+            + entries
+                .iter()
+                .map(|(_, data)| 8 + data.len())
+                .sum::<usize>();
+        put_u32(&mut header, length as u32);
+        put_u32(&mut header, entries.len() as u32);
+        for (kind, data) in entries {
+            put_u32(&mut header, kind);
+            put_u32(&mut header, 8 + data.len() as u32);
+            header.extend(data);
+        }
+        pad(&mut header);
+
+        header
+    }
+
+    fn fcis_record(&self) -> Vec<u8> {
+        let mut record = b"FCIS\0\0\0\x14\0\0\0\x10\0\0\0\x01\0\0\0\0".to_vec();
+        put_u32(&mut record, self.html.len() as u32);
+        record.extend(b"\0\0\0\0\0\0\0\x20\0\0\0\x08\0\x01\0\x01\0\0\0\0");
+        record
+    }
+}
+
+/// Where the records of each kind start.
+struct Layout {
+    text_record_count: u16,
+    /// The INDX header record, if there is an index.
+    ncx: Option<u32>,
+    first_image: u32,
+    last_content: u16,
+    flis: u32,
+    fcis: u32,
+}
+
+/// Splits the text in records of [TEXT_RECORD_SIZE] bytes.
+/// A character cut by the end of a record is completed at the end of it (see [EXTRA_DATA_MULTIBYTE]),
+/// and with an index, the entries in it come after that (see [EXTRA_DATA_INDEXING]).
+fn text_records(text: &[u8], ncx: Option<&Ncx>) -> Vec<Vec<u8>> {
+    text.chunks(TEXT_RECORD_SIZE)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let rest = &text[(i * TEXT_RECORD_SIZE + chunk.len())..];
+            let overlap = rest
+                .iter()
+                .take(3)
+                .take_while(|b| *b & 0b1100_0000 == 0b1000_0000)
+                .count();
+
+            let mut record = chunk.to_vec();
+            record.extend(&rest[..overlap]);
+            record.push(overlap as u8);
+            if let Some(ncx) = ncx {
+                let start = i * TEXT_RECORD_SIZE;
+                record.extend(ncx.trailing_entry(start, start + chunk.len()));
+            }
+            record
+        })
+        .collect()
+}
+
+/// The Windows locale identifier of the language.
+fn locale(language: &str) -> u32 {
+    match language {
+        "de" => 7,
+        "en" => 9,
+        "es" => 10,
+        "fr" => 12,
+        "it" => 16,
+        "ja" => 17,
+        "nl" => 19,
+        "pt" => 22,
+        _ => 0,
+    }
+}
+
+pub(super) fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend(value.to_be_bytes());
+}
+pub(super) fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend(value.to_be_bytes());
+}
+/// Pads with zeros to a multiple of 4 bytes.
+pub(super) fn pad(buffer: &mut Vec<u8>) {
+    buffer.resize(buffer.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(html: &str, toc: Vec<TocEntry>, images: Vec<Vec<u8>>) -> Book {
+        Book {
+            title: "A title, with punctuation".to_string(),
+            authors: vec!["Author".to_string()],
+            description: None,
+            published: None,
+            language: "fr-CA".parse().unwrap(),
+            unique_id: 42,
+            timestamp: 1_700_000_000,
+            html: html.as_bytes().to_vec(),
+            toc,
+            images,
+            cover: None,
+        }
+    }
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// The records of a Palm database, read back from their offsets.
+    fn records(file: &[u8]) -> Vec<&[u8]> {
+        assert_eq!(&file[60..68], b"BOOKMOBI");
+        let count = usize::from(u16_at(file, 76));
+        let offsets: Vec<usize> = (0..count)
+            .map(|i| u32_at(file, 78 + 8 * i) as usize)
+            .chain([file.len()])
+            .collect();
+        assert_eq!(offsets[0], 78 + 8 * count + 2);
+        offsets.windows(2).map(|w| &file[w[0]..w[1]]).collect()
+    }
+
+    #[test]
+    fn records_are_found_at_their_offsets() {
+        let html = "a".repeat(5000);
+        let file = book(&html, vec![], vec![b"image".to_vec()]).to_bytes();
+        assert_eq!(&file[..32], b"A_title__with_punctuation\0\0\0\0\0\0\0");
+
+        let records = records(&file);
+        // The header, 2 text records, the image, FLIS, FCIS and EOF.
+        assert_eq!(records.len(), 7);
+        assert_eq!(&records[1][..4096], &html.as_bytes()[..4096]);
+        assert_eq!(&records[2][..904], &html.as_bytes()[4096..]);
+        assert_eq!(records[3], b"image");
+        assert!(records[4].starts_with(b"FLIS"));
+        assert!(records[5].starts_with(b"FCIS"));
+        assert_eq!(records[6], EOF);
+    }
+
+    #[test]
+    fn the_header_describes_the_book() {
+        let html = "b".repeat(5000);
+        let toc = vec![TocEntry {
+            label: "Chapter".to_string(),
+            position: 0,
+        }];
+        let file = book(&html, toc, vec![]).to_bytes();
+        let records = records(&file);
+        let header = records[0];
+
+        // PalmDOC
+        assert_eq!(u16_at(header, 0), 1);
+        assert_eq!(u32_at(header, 4), 5000);
+        assert_eq!(u16_at(header, 8), 2);
+        assert_eq!(u16_at(header, 10), 4096);
+        // MOBI
+        assert_eq!(&header[16..20], b"MOBI");
+        assert_eq!(u32_at(header, 20), MOBI_HEADER_LENGTH);
+        assert_eq!(u32_at(header, 28), TEXT_ENCODING);
+        assert_eq!(u32_at(header, 32), 42);
+        assert_eq!(u32_at(header, 92), locale("fr"));
+        assert_eq!(u32_at(header, 128) & 0x40, 0x40);
+        assert_eq!(&header[248..252], b"EXTH");
+        // The index starts right after the text records.
+        assert_eq!(u32_at(header, 80), 3);
+        assert_eq!(
+            u32_at(header, 240),
+            EXTRA_DATA_MULTIBYTE | EXTRA_DATA_INDEXING
+        );
+        assert_eq!(u32_at(header, 244), 3);
+        assert!(records[3].starts_with(b"INDX"));
+
+        let full_name_offset = u32_at(header, 84) as usize;
+        let full_name_length = u32_at(header, 88) as usize;
+        assert_eq!(
+            &header[full_name_offset..full_name_offset + full_name_length],
+            b"A title, with punctuation"
+        );
+    }
+
+    #[test]
+    fn cut_characters_are_completed_at_the_end_of_records() {
+        let text = format!("{}éb", "a".repeat(4095));
+        let records = text_records(text.as_bytes(), None);
+
+        assert_eq!(records.len(), 2);
+        // The first byte of `é` is in the first record, which ends with the second and their count.
+        assert_eq!(records[0].len(), 4096 + 2);
+        assert_eq!(records[0][4094..], [b'a', 0xC3, 0xA9, 1]);
+        // The second record still starts with the rest of the character.
+        assert_eq!(records[1], [0xA9, b'b', 0]);
+    }
+
+    #[test]
+    fn trailing_entries_follow_the_multibyte_bytes() {
+        let text = format!("{}éb", "a".repeat(4095));
+        let toc = [TocEntry {
+            label: "Chapter".to_string(),
+            position: 0,
+        }];
+        let ncx = Ncx::new(&toc, text.len()).unwrap();
+        let records = text_records(text.as_bytes(), Some(&ncx));
+
+        let first = ncx.trailing_entry(0, 4096);
+        assert_eq!(
+            records[0][..4098],
+            [text.as_bytes()[..4097].to_vec(), vec![1]].concat()
+        );
+        assert_eq!(records[0][4098..], first);
+        assert_eq!(records[1][..3], [0xA9, b'b', 0]);
+        assert_eq!(records[1][3..], ncx.trailing_entry(4096, text.len()));
+    }
+}
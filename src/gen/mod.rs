@@ -1,26 +1,295 @@
-mod cover;
+pub(crate) mod cover;
 
+pub mod docx;
 pub mod epub;
+pub mod fb2;
 pub mod html;
+pub mod json;
+pub mod markdown;
+pub mod mobi;
+pub mod pdf;
+mod search;
+pub mod text;
 pub mod transform;
+pub mod tts;
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+    ops::Range,
+    str::FromStr,
+    sync::OnceLock,
+};
 
-use std::collections::HashMap;
+use regex::Regex;
+use uuid::Uuid;
 
 use crate::{
-    types::{BoardInPost, Character, Icon, User},
-    Post, Reply,
+    intern_images::placeholder_url,
+    types::{BoardInPost, Character, Continuity, Icon, Section, User},
+    Board, Post, Reply,
 };
 
 use super::Thread;
 
 const STYLE: &str = include_str!("book.css");
+/// How many words of the last read post or reply the recap of delta epubs quotes.
+const RECAP_WORDS: usize = 40;
 
 #[derive(Debug, Clone, Copy, Default)]
 pub struct Options {
     pub text_to_speech: bool,
-    pub flatten_details: bool,
+    /// What is done with `<details>` tags, which are often spoilers.
+    pub details: DetailsMode,
     pub jpeg: bool,
     pub resize_icons: Option<u32>,
+    /// Merge interned images whose perceptual hashes are within this hamming distance (out of 64).
+    /// Images with identical content are always merged.
+    pub perceptual_dedup: Option<u32>,
+    /// How many images may be converted in parallel. Defaults to the number of available cores.
+    pub image_workers: Option<usize>,
+    /// Show a generated avatar for characters without an icon, and for icons that failed to download.
+    /// Only useful when images are interned, as the placeholders only exist locally.
+    pub placeholder_icons: bool,
+    /// Which formats interned images are stored in (ignored if `jpeg` is set).
+    pub image_format: ImageFormat,
+    /// How html output refers to images.
+    pub html_images: HtmlImages,
+    /// Add a table of contents, jump-to-reply, a dark theme, collapsible long replies,
+    /// and remembering the reading position to html output. Requires javascript.
+    pub interactive_html: bool,
+    /// Wrap the sentences of epubs in the spans that Kobo readers use for page statistics (see `to_kepub`).
+    pub kepub: bool,
+    /// How the replies of a thread are split into the pages of epubs (and the other paged formats).
+    pub page_split: PageSplit,
+    /// What the pages of replies are called in the table of contents.
+    pub part_labels: PartLabels,
+    /// List the pages of replies in the table of contents of board epubs too,
+    /// nested under their post so that readers can collapse them.
+    pub nested_toc: bool,
+    /// In epubs, number the links that still lead outside the book
+    /// and list their urls at the end of the page, for readers that can't open links.
+    pub link_footnotes: bool,
+    /// The version of the epubs (and kepubs).
+    pub epub_version: EpubVersion,
+    /// The language the books declare, for hyphenation and text-to-speech.
+    pub language: LanguageTag,
+    /// The namespace of the identifiers of the books, which are derived from the url of the post or board.
+    /// Defaults to the url namespace, so any tool can compute them.
+    pub uuid_namespace: Option<Uuid>,
+}
+impl Options {
+    fn identifier_namespace(&self) -> Uuid {
+        self.uuid_namespace.unwrap_or(Uuid::NAMESPACE_URL)
+    }
+}
+
+/// A BCP 47 language tag, like `en` or `pt-BR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LanguageTag {
+    bytes: [u8; LanguageTag::MAX_LEN],
+    len: u8,
+}
+impl LanguageTag {
+    /// The longest tag that all implementations must support, according to RFC 5646.
+    const MAX_LEN: usize = 35;
+
+    pub fn as_str(&self) -> &str {
+        // Only ascii is ever stored.
+        std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap()
+    }
+
+    /// The language without its script or region, like `pt` for `pt-BR`.
+    pub fn primary(&self) -> &str {
+        self.as_str().split('-').next().unwrap_or_default()
+    }
+}
+impl Default for LanguageTag {
+    fn default() -> Self {
+        "en".parse().unwrap()
+    }
+}
+impl FromStr for LanguageTag {
+    type Err = String;
+
+    /// Only the shape of the tag is checked: subtags of 1 to 8 letters or digits, starting with the language.
+    fn from_str(tag: &str) -> Result<Self, Self::Err> {
+        let valid_subtags = tag.split('-').enumerate().all(|(i, subtag)| {
+            (1..=8).contains(&subtag.len())
+                && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+                && (i > 0 || (subtag.len() >= 2 && subtag.chars().all(|c| c.is_ascii_alphabetic())))
+        });
+        if !valid_subtags || tag.len() > Self::MAX_LEN {
+            return Err(format!("invalid language tag: {tag:?}"));
+        }
+
+        let mut bytes = [0; Self::MAX_LEN];
+        bytes[..tag.len()].copy_from_slice(tag.as_bytes());
+        Ok(Self {
+            bytes,
+            len: tag.len() as u8,
+        })
+    }
+}
+impl Display for LanguageTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The version of the epubs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EpubVersion {
+    /// EPUB 2.0.1, for older readers. The pages are plain xhtml.
+    Epub2,
+    /// EPUB 3, with a navigation document (with landmarks and the page-list),
+    /// and pages made of `<section>`s and `<article>`s with `epub:type` semantics.
+    #[default]
+    Epub3,
+}
+
+/// What is done with `<details>` tags, which are often spoilers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DetailsMode {
+    /// They are left as they are.
+    #[default]
+    Keep,
+    /// They become blockquotes that always seem open, as many e-readers have trouble with them
+    /// (see [transform::flatten_details]).
+    Flatten,
+    /// In epubs, the summary becomes a link to a footnote at the end of the page holding the content,
+    /// which readers with pop-up footnotes only show when it is tapped (see [transform::details_to_footnotes]).
+    /// Other formats keep them.
+    Footnotes,
+}
+
+/// What the pages of replies are called in the table of contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PartLabels {
+    /// `Part 0`, `Part 1`, and so on.
+    #[default]
+    Numbered,
+    /// The numbers of the first and last replies, like `Replies 31–60`.
+    Replies,
+    /// When the first and last replies were written, like `2020-01-02 – 2020-02-15`.
+    Dates,
+    /// The characters speaking, like `Keltham, Carissa Sevar, and 2 more`.
+    Characters,
+}
+impl PartLabels {
+    /// The label of the `i`th page, whose replies start at `first` (counting from 0).
+    fn label(&self, i: usize, first: usize, replies: &[Reply]) -> String {
+        let (Some(first_reply), Some(last_reply)) = (replies.first(), replies.last()) else {
+            return format!("Part {i}");
+        };
+
+        match self {
+            Self::Numbered => format!("Part {i}"),
+            Self::Replies if replies.len() == 1 => format!("Reply {}", first + 1),
+            Self::Replies => format!("Replies {}–{}", first + 1, first + replies.len()),
+            Self::Dates => {
+                let start = first_reply.created_at.format("%Y-%m-%d").to_string();
+                let end = last_reply.created_at.format("%Y-%m-%d").to_string();
+                if start == end {
+                    start
+                } else {
+                    format!("{start} – {end}")
+                }
+            }
+            Self::Characters => {
+                let mut names: Vec<&str> = vec![];
+                for reply in replies {
+                    let name = reply
+                        .character_name
+                        .as_deref()
+                        .or(reply.character.as_ref().map(|c| &*c.name))
+                        .unwrap_or(&reply.user.username);
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+                match names.len() {
+                    0..=3 => names.join(", "),
+                    n => format!("{}, and {} more", names[..2].join(", "), n - 2),
+                }
+            }
+        }
+    }
+}
+
+/// How the replies of a thread are split into pages. Replies are never split themselves,
+/// so a single long reply can go over the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSplit {
+    /// At most this many replies per page.
+    Replies(usize),
+    /// At most this many bytes of the replies' html per page, as downloaded.
+    /// The pages end up a little larger, with the markup around each reply.
+    /// Kindle conversion and some readers struggle with files above 300KB.
+    Bytes(usize),
+    /// At most this many words per page.
+    Words(usize),
+}
+impl Default for PageSplit {
+    fn default() -> Self {
+        Self::Replies(30)
+    }
+}
+impl PageSplit {
+    /// Groups the consecutive content blocks into pages, each with at least one block,
+    /// and returns the range of blocks on each page.
+    fn split(&self, blocks: &[String]) -> Vec<Range<usize>> {
+        let (limit, size): (usize, fn(&str) -> usize) = match *self {
+            Self::Replies(limit) => (limit, |_| 1),
+            Self::Bytes(limit) => (limit, str::len),
+            Self::Words(limit) => (limit, |block| {
+                transform::html_to_text(block).split_whitespace().count()
+            }),
+        };
+
+        let mut pages = vec![];
+        let (mut start, mut page_size) = (0, 0);
+        for (i, block) in blocks.iter().enumerate() {
+            let block_size = size(block);
+            if i > start && page_size + block_size > limit {
+                pages.push(start..i);
+                (start, page_size) = (i, 0);
+            }
+            page_size += block_size;
+        }
+        if start < blocks.len() {
+            pages.push(start..blocks.len());
+        }
+
+        pages
+    }
+}
+
+/// How html output refers to images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HtmlImages {
+    /// Images are hot-linked from their original hosts.
+    #[default]
+    Remote,
+    /// Images are embedded in the page as data URIs, so that a single file works offline.
+    Inline,
+    /// Images are stored in an `assets/` folder next to the page.
+    Assets,
+}
+
+/// The formats interned images are stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImageFormat {
+    /// WebP images are converted to png, as EPUB2 and many e-readers (like Kindles) don't support them.
+    /// AVIF images, which can't be decoded, are left out.
+    #[default]
+    Compatible,
+    /// Images are kept in the format they were downloaded in.
+    Original,
+    /// Bmp and png images are transcoded to lossless WebP. Best suited to html output.
+    WebP,
+    /// Raster images are transcoded to AVIF. Best suited to html output.
+    Avif,
 }
 
 fn raw_title_page(post: &Post, reply_count: usize) -> String {
@@ -63,6 +332,142 @@ fn raw_title_page(post: &Post, reply_count: usize) -> String {
     )
 }
 
+/// The start of a delta epub: the thread, and the end of the post or reply that was read last.
+fn raw_recap_page(post: &Post, last_read: &Block, new_reply_count: usize) -> String {
+    let Post {
+        authors,
+        board,
+        id,
+        subject,
+        ..
+    } = post;
+
+    let author_names = author_names(authors);
+    let author_ids: Vec<u64> = authors.iter().map(|user| user.id).collect();
+
+    let BoardInPost {
+        id: board_id,
+        name: board_name,
+    } = board;
+
+    let (what, url) = match last_read.reply_id {
+        Some(reply_id) => ("reply", reply_url(reply_id)),
+        None => ("post", post_url(*id)),
+    };
+    let speaker = last_read
+        .speaker()
+        .map(|speaker| format!(" by {}", transform::escape_html(&speaker)))
+        .unwrap_or_default();
+    let text = transform::html_to_text(last_read.content);
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let excerpt = match words.len() {
+        0 => String::new(),
+        n if n <= RECAP_WORDS => words.join(" "),
+        n => format!("… {}", words[n - RECAP_WORDS..].join(" ")),
+    };
+    let excerpt = transform::escape_html(&excerpt);
+
+    format!(
+        r##"
+
+    <div class="title-page">
+        <h1 post-id="{id}">{subject}</h1>
+        <h2 author-ids="{author_ids:?}">by {author_names}</h2>
+        <h3 board-id="{board_id}">in {board_name}</h3>
+        <p>[{new_reply_count} new replies]</p>
+        <div class="description">
+            <p>Last read: the <a href="{url}" rel="noopener noreferrer">{what}</a>{speaker}, ending with</p>
+            <blockquote><p>{excerpt}</p></blockquote>
+        </div>
+    </div>
+
+    "##
+    )
+}
+
+/// `volume` is the number of the volume and how many there are, for boards split into volumes.
+fn raw_board_title_page(continuity: &Continuity, volume: Option<(usize, usize)>) -> String {
+    let Board { id, name, .. } = &continuity.board;
+
+    let authors = continuity.authors();
+    let author_names = author_names(&authors);
+    let author_ids: Vec<u64> = authors.iter().map(|user| user.id).collect();
+
+    let thread_count = continuity.threads.len();
+    let volume = volume
+        .map(|(number, count)| format!("<h3>Volume {number} of {count}</h3>"))
+        .unwrap_or_default();
+
+    format!(
+        r##"
+
+        <div class="title-page">
+            <h1 board-id="{id}">{name}</h1>
+            <h2 glowfic-ids="{author_ids:?}">by {author_names}</h2>
+            {volume}
+            <p>[{thread_count} threads]</p>
+        </div>
+
+        "##
+    )
+}
+
+fn raw_board_copyright_page(continuity: &Continuity) -> String {
+    let Board { id, name, .. } = &continuity.board;
+
+    let authors = continuity.authors();
+    let author_names = author_names(&authors);
+    let author_ids: Vec<u64> = authors.iter().map(|user| user.id).collect();
+
+    format!(
+        r##"
+    
+        <div class="copyright-page">
+            <h3>This was</h3>
+            <h1 board-id="{id}">{name}</h1>
+            <h2 glowfic-ids="{author_ids:?}">by {author_names}</h2>
+            <h3 class="board" board-id="{id}">in {name}</h3>
+    
+            © {author_names}
+        </div>
+    
+        "##
+    )
+}
+
+/// The title used for threads that are not in any section.
+const SECTIONLESS_NAME: &str = "Unsectioned Posts";
+
+/// Pass [None] for the threads that are not in any section.
+fn raw_section_title_page(section: Option<&Section>, threads: &[&Thread]) -> String {
+    let authors: Vec<User> = threads
+        .iter()
+        .flat_map(|t| t.post.authors.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let author_names = author_names(&authors);
+    let author_ids: Vec<u64> = authors.iter().map(|user| user.id).collect();
+    let thread_count = threads.len();
+
+    let heading = match section {
+        Some(Section { id, name, .. }) => format!(r##"<h1 section-id="{id}">{name}</h1>"##),
+        None => format!(r##"<h1>{SECTIONLESS_NAME}</h1>"##),
+    };
+
+    format!(
+        r##"
+    
+        <div class="title-page">
+            {heading}
+            <h2 glowfic-ids="{author_ids:?}">by {author_names}</h2>
+            <p>[{thread_count} threads]</p>
+        </div>
+    
+        "##
+    )
+}
+
 pub fn raw_content_page(content_blocks: &[String]) -> String {
     let content: String = content_blocks
         .iter()
@@ -81,7 +486,54 @@ pub fn raw_content_page(content_blocks: &[String]) -> String {
     )
 }
 
+/// The parts of a post or reply that formats other than html need.
+struct Block<'a> {
+    /// [None] for the post itself.
+    reply_id: Option<u64>,
+    character_id: Option<u64>,
+    character_name: Option<&'a str>,
+    author: Option<&'a str>,
+    icon: Option<&'a Icon>,
+    content: &'a str,
+}
+impl Block<'_> {
+    /// `Character (Author)`, or whichever of the two is known.
+    fn speaker(&self) -> Option<String> {
+        match (self.character_name, self.author) {
+            (Some(character), Some(author)) => Some(format!("{character} ({author})")),
+            (Some(name), None) | (None, Some(name)) => Some(name.to_string()),
+            (None, None) => None,
+        }
+    }
+}
+
 impl Thread {
+    /// The post followed by the replies.
+    fn blocks(&self) -> impl Iterator<Item = Block<'_>> {
+        let post = &self.post;
+        let post_block = Block {
+            reply_id: None,
+            character_id: post.character.as_ref().map(|c| c.id),
+            character_name: post.character.as_ref().map(|c| &*c.name),
+            author: post.authors.first().map(|u| &*u.username),
+            icon: post.icon.as_ref(),
+            content: &post.content,
+        };
+        let reply_blocks = self.replies.iter().map(|reply| Block {
+            reply_id: Some(reply.id),
+            character_id: reply.character.as_ref().map(|c| c.id),
+            character_name: reply
+                .character_name
+                .as_deref()
+                .or(reply.character.as_ref().map(|c| &*c.name)),
+            author: Some(&reply.user.username),
+            icon: reply.icon.as_ref(),
+            content: &reply.content,
+        });
+
+        std::iter::once(post_block).chain(reply_blocks)
+    }
+
     fn content_blocks(&self, options: Options) -> Vec<String> {
         std::iter::once(self.post.content_block(options))
             .chain(
@@ -210,6 +662,15 @@ fn content_block(
                 r##"<img src="{url}"{keyword} icon-id="{id}" class="icon">"##
             ))
         })
+        .or_else(|| {
+            if !options.placeholder_icons {
+                return None;
+            }
+            let Character { id, name, .. } = character.as_ref()?;
+            let url = transform::escape_html(&placeholder_url(*id));
+            let name = transform::escape_html(character_name.as_ref().unwrap_or(name));
+            Some(format!(r##"<img src="{url}" alt="{name}" class="icon">"##))
+        })
         .unwrap_or_default();
 
     let reply_id = reply_id
@@ -270,26 +731,220 @@ fn author_names(authors: &[User]) -> String {
         .map(|a| transform::escape_html(&a.username))
         .collect();
 
-    match &*usernames {
+    list_names(&usernames, "&#38;")
+}
+
+/// Like [author_names], but not escaped, for formats other than html.
+fn plain_author_names(authors: &[User]) -> String {
+    let usernames: Vec<_> = authors.iter().map(|a| a.username.clone()).collect();
+
+    list_names(&usernames, "&")
+}
+
+fn list_names(names: &[String], ampersand: &str) -> String {
+    match names {
         [] => String::new(),
-        [username] => username.to_string(),
-        [one, two] => format!("{one} &#38; {two}"),
+        [name] => name.to_string(),
+        [one, two] => format!("{one} {ampersand} {two}"),
         [leading @ .., last] => {
             let leading = leading.join(", ");
-            format!("{leading}, &#38; {last}")
+            format!("{leading}, {ampersand} {last}")
         }
     }
 }
 
+/// Sanitizes the content, and replaces the images and the links to glowfic posts and replies
+/// (by their [canonical_glowfic_url]) found in `url_map`.
 fn process_content(content: &str, options: Options, url_map: &HashMap<String, String>) -> String {
     let content = transform::repair_and_sanitize(content);
     let content = transform::decode_named_entities(content);
     let content =
         transform::edit_image_urls(&content, |url| url_map.get(&url).cloned().unwrap_or(url));
+    let content = transform::edit_link_urls(&content, |url| {
+        canonical_glowfic_url(&url)
+            .and_then(|url| url_map.get(&url).cloned())
+            .unwrap_or(url)
+    });
+
+    match options.details {
+        DetailsMode::Flatten => transform::flatten_details(&content),
+        // Footnotes are made for whole pages, by the epub.
+        DetailsMode::Keep | DetailsMode::Footnotes => content,
+    }
+}
+
+fn board_url(id: u64) -> String {
+    format!("https://glowfic.com/boards/{id}")
+}
+fn post_url(id: u64) -> String {
+    format!("https://glowfic.com/posts/{id}")
+}
+fn reply_url(id: u64) -> String {
+    format!("https://glowfic.com/replies/{id}")
+}
+
+/// Links to posts and replies come in several shapes, like `https://glowfic.com/replies/123#reply-123`,
+/// `http://www.glowfic.com/posts/45?page=2#reply-123`, or `/posts/45`.
+/// They are all turned into `https://glowfic.com/replies/123` or `https://glowfic.com/posts/45`.
+fn canonical_glowfic_url(url: &str) -> Option<String> {
+    static GLOWFIC_URL: OnceLock<Regex> = OnceLock::new();
+    let glowfic_url = GLOWFIC_URL.get_or_init(|| {
+        Regex::new(
+            r"^(?:https?://(?:www\.)?glowfic\.com)?/(?:replies/([0-9]+)(?:[?#]|$)|posts/[0-9]+[^#]*#reply-([0-9]+)|posts/([0-9]+)(?:[?#]|$))",
+        )
+        .unwrap()
+    });
+
+    let captures = glowfic_url.captures(url)?;
+    match (captures.get(1).or(captures.get(2)), captures.get(3)) {
+        (Some(reply_id), _) => Some(reply_url(reply_id.as_str().parse().ok()?)),
+        (None, Some(post_id)) => Some(post_url(post_id.as_str().parse().ok()?)),
+        (None, None) => None,
+    }
+}
 
-    if options.flatten_details {
-        transform::flatten_details(&content)
-    } else {
-        content
+/// The id of a post or reply, and the href of where it is in the book.
+type Location = (u64, String);
+
+/// The url map, plus the canonical urls of posts and replies mapped to where they are in the book.
+fn with_glowfic_links(
+    mut url_map: HashMap<String, String>,
+    posts: &[Location],
+    replies: &[Location],
+) -> HashMap<String, String> {
+    url_map.extend(posts.iter().map(|(id, href)| (post_url(*id), href.clone())));
+    url_map.extend(
+        replies
+            .iter()
+            .map(|(id, href)| (reply_url(*id), href.clone())),
+    );
+    url_map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(sizes: &[usize]) -> Vec<String> {
+        sizes.iter().map(|size| "a".repeat(*size)).collect()
+    }
+
+    #[test]
+    fn no_blocks_make_no_pages() {
+        assert!(PageSplit::Replies(30).split(&[]).is_empty());
+        assert!(PageSplit::Bytes(100).split(&[]).is_empty());
+    }
+
+    #[test]
+    fn pages_fill_up_to_the_limit() {
+        let blocks = blocks(&[1; 7]);
+        assert_eq!(PageSplit::Replies(3).split(&blocks), [0..3, 3..6, 6..7]);
+
+        let blocks = self::blocks(&[40, 60, 30, 70]);
+        assert_eq!(PageSplit::Bytes(100).split(&blocks), [0..2, 2..4]);
+    }
+
+    #[test]
+    fn oversized_blocks_get_a_page_of_their_own() {
+        let blocks = blocks(&[500, 10, 500, 10]);
+        assert_eq!(
+            PageSplit::Bytes(100).split(&blocks),
+            [0..1, 1..2, 2..3, 3..4]
+        );
+        // Even with a limit of 0, every page has a block.
+        assert_eq!(
+            PageSplit::Replies(0).split(&blocks),
+            [0..1, 1..2, 2..3, 3..4]
+        );
+    }
+
+    #[test]
+    fn words_are_counted_in_the_text() {
+        let blocks = vec![
+            "<p>one two <em>three</em></p>".to_string(),
+            "<p>four<br>five</p>".to_string(),
+            "<p>six</p>".to_string(),
+        ];
+        assert_eq!(PageSplit::Words(5).split(&blocks), [0..2, 2..3]);
+    }
+
+    #[test]
+    fn language_tags_are_parsed() {
+        for tag in ["en", "pt-BR", "zh-Hant-TW", "es-419"] {
+            assert_eq!(tag.parse::<LanguageTag>().unwrap().as_str(), tag);
+        }
+        assert_eq!("pt-BR".parse::<LanguageTag>().unwrap().primary(), "pt");
+    }
+
+    #[test]
+    fn invalid_language_tags_are_rejected() {
+        let too_long = format!("en{}", "-abcdefgh".repeat(4));
+        for tag in [
+            "",
+            "e",
+            "12",
+            "en-",
+            "en--US",
+            "en_US",
+            "en-US-toolongsub",
+            "fr-é",
+            "en US",
+            &too_long,
+        ] {
+            assert!(tag.parse::<LanguageTag>().is_err(), "{tag:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn reply_links_are_canonical() {
+        for url in [
+            "https://glowfic.com/replies/123#reply-123",
+            "https://glowfic.com/replies/123",
+            "http://www.glowfic.com/replies/123",
+            "https://glowfic.com/posts/45?page=2#reply-123",
+            "https://www.glowfic.com/posts/45#reply-123",
+            "/replies/123#reply-123",
+            "/posts/45?page=2&per_page=25#reply-123",
+        ] {
+            assert_eq!(
+                canonical_glowfic_url(url).as_deref(),
+                Some("https://glowfic.com/replies/123"),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn post_links_are_canonical() {
+        for url in [
+            "https://glowfic.com/posts/45",
+            "http://glowfic.com/posts/45",
+            "https://www.glowfic.com/posts/45?page=2",
+            "https://glowfic.com/posts/45#top",
+            "/posts/45",
+        ] {
+            assert_eq!(
+                canonical_glowfic_url(url).as_deref(),
+                Some("https://glowfic.com/posts/45"),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn other_links_are_not_glowfic_links() {
+        for url in [
+            "/posts/12abc",
+            "/replies/12abc",
+            "https://glowfic.com/posts/",
+            "https://glowfic.com/boards/3",
+            "https://glowfic.com/posts/45/stats",
+            "https://example.com/posts/45",
+            "https://glowfic.com.example.com/posts/45",
+            "posts/45",
+            "#reply-123",
+        ] {
+            assert_eq!(canonical_glowfic_url(url), None, "{url}");
+        }
     }
 }
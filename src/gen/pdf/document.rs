@@ -0,0 +1,624 @@
+//! Pages being filled from top to bottom, and their serialization.
+
+use std::{
+    collections::HashMap,
+    io::{Cursor, Write},
+};
+
+use flate2::{write::ZlibEncoder, Compression};
+use image::{DynamicImage, GenericImageView, ImageFormat, ImageReader};
+use pdf_writer::{
+    types::{ActionType, AnnotationType},
+    Content, Filter, Finish, Name, Pdf, Rect, Ref, Str, TextStr,
+};
+
+use crate::intern_images::InternedImage;
+
+use super::{
+    fonts::{Face, Font, Fonts},
+    layout::{LineBreaker, Paragraph},
+};
+
+/// A5, which is close to the size of a paperback and of most tablets.
+pub(super) const PAGE_WIDTH: f32 = 419.53;
+pub(super) const PAGE_HEIGHT: f32 = 595.28;
+pub(super) const MARGIN: f32 = 42.0;
+pub(super) const CONTENT_WIDTH: f32 = PAGE_WIDTH - 2.0 * MARGIN;
+/// The page numbers are drawn in the bottom margin.
+const PAGE_NUMBER_SIZE: f32 = 9.0;
+
+const LINE_HEIGHT: f32 = 1.3;
+/// After each paragraph, relative to its font size.
+const PARAGRAPH_SPACING: f32 = 0.5;
+/// Images larger than this (in pixels) are scaled down, as they won't be shown any bigger.
+const MAX_IMAGE_SIZE: u32 = 1200;
+
+pub(super) struct Document {
+    pages: Vec<Page>,
+    /// The top of the free space on the current page, from the bottom of the page.
+    y: f32,
+    /// An image the text flows around: the bottom of it, and the width it takes (from the left of the text column).
+    float: Option<(f32, f32)>,
+    /// The downloaded images, by their original url.
+    sources: HashMap<String, InternedImage>,
+    images: Vec<PdfImage>,
+    /// The index in `images` for each url, or [None] if the image couldn't be decoded.
+    image_indices: HashMap<String, Option<usize>>,
+    outline: Vec<OutlineEntry>,
+    fonts: Fonts,
+}
+
+struct Page {
+    content: Content,
+    /// The areas to click on, and the urls they lead to.
+    links: Vec<(Rect, String)>,
+    numbered: bool,
+}
+
+struct PdfImage {
+    width: u32,
+    height: u32,
+    data: PdfImageData,
+}
+enum PdfImageData {
+    /// Embedded as-is.
+    Jpeg(Vec<u8>),
+    Raw {
+        rgb: Vec<u8>,
+        alpha: Option<Vec<u8>>,
+    },
+}
+
+struct OutlineEntry {
+    title: String,
+    /// 0 for the top level.
+    level: usize,
+    page: usize,
+    top: f32,
+}
+
+/// The document information dictionary.
+pub(super) struct Metadata {
+    pub title: String,
+    pub author: String,
+}
+
+impl Document {
+    pub fn new(images: HashMap<String, InternedImage>) -> Self {
+        Self {
+            pages: vec![],
+            y: 0.0,
+            float: None,
+            sources: images,
+            images: vec![],
+            image_indices: HashMap::new(),
+            outline: vec![],
+            fonts: Fonts::default(),
+        }
+    }
+
+    /// Starts a new page. The page number is shown unless it's a title page.
+    pub fn new_page(&mut self, numbered: bool) {
+        self.pages.push(Page {
+            content: Content::new(),
+            links: vec![],
+            numbered,
+        });
+        self.y = PAGE_HEIGHT - MARGIN;
+        self.float = None;
+    }
+
+    /// Makes sure that there is enough vertical space left on the page, or else starts a new one.
+    pub fn ensure_space(&mut self, height: f32) {
+        if self.pages.is_empty() || self.y - height < MARGIN {
+            self.new_page(true);
+        }
+    }
+
+    pub fn vertical_space(&mut self, height: f32) {
+        self.y -= height;
+    }
+
+    /// Adds a bookmark to the current position.
+    pub fn bookmark(&mut self, title: &str, level: usize) {
+        if self.pages.is_empty() {
+            self.new_page(true);
+        }
+        self.outline.push(OutlineEntry {
+            title: title.to_string(),
+            level,
+            page: self.pages.len() - 1,
+            top: self.y + 10.0,
+        });
+    }
+
+    pub fn paragraph(&mut self, paragraph: &Paragraph) {
+        let size = paragraph.size;
+        let line_height = size * LINE_HEIGHT;
+        let mut breaker = LineBreaker::new(paragraph, &mut self.fonts);
+        let mut first = true;
+
+        while !breaker.is_done() {
+            self.ensure_space(line_height);
+            let float_width = match self.float {
+                Some((bottom, width)) if self.y > bottom => width,
+                _ => 0.0,
+            };
+            let left = MARGIN + float_width + paragraph.indent;
+            let width = CONTENT_WIDTH - float_width - paragraph.indent;
+
+            let Some(line) = breaker.next_line(width, &self.fonts) else {
+                break;
+            };
+            let baseline = self.y - size;
+            let x = if paragraph.centered {
+                left + (width - line.width) / 2.0
+            } else {
+                left
+            };
+
+            if first {
+                if let Some(marker) = &paragraph.marker {
+                    let (face, marker) = self.fonts.encode(marker, Font::Regular);
+                    let marker_width = self.fonts.width(face, &marker, size);
+                    self.text(&marker, face, size, x - marker_width - 4.0, baseline);
+                }
+                first = false;
+            }
+            for word in &line.words {
+                self.text(&word.text, word.face, size, x + word.x, baseline);
+                if let Some(link) = &word.link {
+                    let rect = Rect::new(
+                        x + word.x,
+                        baseline - size * 0.25,
+                        x + word.x + word.width,
+                        baseline + size * 0.8,
+                    );
+                    self.page().links.push((rect, link.clone()));
+                }
+            }
+
+            self.y -= line_height;
+        }
+
+        self.y -= size * PARAGRAPH_SPACING;
+    }
+
+    /// A thin line across the text column.
+    pub fn rule(&mut self) {
+        self.clear_float();
+        self.ensure_space(12.0);
+        let y = self.y - 6.0;
+        self.page()
+            .content
+            .set_line_width(0.5)
+            .set_stroke_gray(0.6)
+            .move_to(MARGIN + CONTENT_WIDTH * 0.2, y)
+            .line_to(MARGIN + CONTENT_WIDTH * 0.8, y)
+            .stroke();
+        self.y -= 12.0;
+    }
+
+    /// Draws the image on its own lines, scaled down to fit the page if needed.
+    /// Returns false if the image wasn't downloaded, or couldn't be decoded.
+    pub fn image(&mut self, url: &str) -> bool {
+        self.clear_float();
+        let Some(index) = self.image_index(url) else {
+            return false;
+        };
+        let image = &self.images[index];
+
+        // Pixels are shown at 96 dpi, like browsers do.
+        let natural_width = image.width as f32 * 0.75;
+        let natural_height = image.height as f32 * 0.75;
+        let max_height = (PAGE_HEIGHT - 2.0 * MARGIN) * 0.8;
+        let scale = (CONTENT_WIDTH / natural_width)
+            .min(max_height / natural_height)
+            .min(1.0);
+        let (width, height) = (natural_width * scale, natural_height * scale);
+
+        self.ensure_space(height);
+        let x = MARGIN + (CONTENT_WIDTH - width) / 2.0;
+        self.draw_image(index, x, self.y - height, width, height);
+        self.y -= height + 6.0;
+
+        true
+    }
+
+    /// Draws the image in a square at the top left of the text column, and has the following text flow around it.
+    pub fn float_image(&mut self, url: &str, size: f32) -> bool {
+        self.clear_float();
+        let Some(index) = self.image_index(url) else {
+            return false;
+        };
+        let image = &self.images[index];
+
+        // Fit in the square, keeping the aspect ratio.
+        let scale = size / image.width.max(image.height) as f32;
+        let (width, height) = (image.width as f32 * scale, image.height as f32 * scale);
+
+        self.ensure_space(size);
+        self.draw_image(index, MARGIN, self.y - height, width, height);
+        self.float = Some((self.y - size - 6.0, size + 10.0));
+
+        true
+    }
+
+    /// Moves below the floating image, if any.
+    pub fn clear_float(&mut self) {
+        if let Some((bottom, _)) = self.float.take() {
+            if self.pages.is_empty() {
+                return;
+            }
+            self.y = self.y.min(bottom);
+        }
+    }
+
+    fn page(&mut self) -> &mut Page {
+        if self.pages.is_empty() {
+            self.new_page(true);
+        }
+        self.pages.last_mut().unwrap()
+    }
+
+    fn text(&mut self, text: &[u8], face: Face, size: f32, x: f32, y: f32) {
+        self.page()
+            .content
+            .begin_text()
+            .set_font(Name(face.resource_name().as_bytes()), size)
+            .set_text_matrix([1.0, 0.0, 0.0, 1.0, x, y])
+            .show(Str(text))
+            .end_text();
+    }
+
+    fn draw_image(&mut self, index: usize, x: f32, y: f32, width: f32, height: f32) {
+        let name = format!("Im{index}");
+        self.page()
+            .content
+            .save_state()
+            .transform([width, 0.0, 0.0, height, x, y])
+            .x_object(Name(name.as_bytes()))
+            .restore_state();
+    }
+
+    fn image_index(&mut self, url: &str) -> Option<usize> {
+        if let Some(index) = self.image_indices.get(url) {
+            return *index;
+        }
+
+        let image = self.sources.get(url).and_then(|image| {
+            pdf_image(image)
+                .inspect_err(|e| log::warn!("Failed to add image {url} to the pdf: {e}"))
+                .ok()
+        });
+        let index = image.map(|image| {
+            self.images.push(image);
+            self.images.len() - 1
+        });
+        self.image_indices.insert(url.to_string(), index);
+
+        index
+    }
+
+    pub fn finish(self, metadata: &Metadata) -> Vec<u8> {
+        let mut next_id = 1;
+        let mut new_ref = || {
+            let id = Ref::new(next_id);
+            next_id += 1;
+            id
+        };
+
+        let catalog_id = new_ref();
+        let page_tree_id = new_ref();
+        let outline_id = new_ref();
+        let info_id = new_ref();
+        let image_ids: Vec<(Ref, Ref)> =
+            self.images.iter().map(|_| (new_ref(), new_ref())).collect();
+        let page_ids: Vec<Ref> = self.pages.iter().map(|_| new_ref()).collect();
+
+        let mut pdf = Pdf::new();
+
+        let mut catalog = pdf.catalog(catalog_id);
+        catalog.pages(page_tree_id);
+        if !self.outline.is_empty() {
+            catalog
+                .outlines(outline_id)
+                .page_mode(pdf_writer::types::PageMode::UseOutlines);
+        }
+        catalog.finish();
+
+        pdf.document_info(info_id)
+            .title(TextStr(&metadata.title))
+            .author(TextStr(&metadata.author))
+            .creator(TextStr("glowpub"));
+
+        pdf.pages(page_tree_id)
+            .kids(page_ids.iter().copied())
+            .count(page_ids.len() as i32);
+
+        if !self.fonts.missing().is_empty() {
+            let missing: String = self.fonts.missing().iter().collect();
+            log::warn!(
+                "No font has the characters {missing:?}, so they were replaced by `?` in the pdf"
+            );
+        }
+        let font_ids = self.fonts.write(&mut pdf, &mut new_ref);
+
+        for (image, (id, mask_id)) in self.images.iter().zip(&image_ids) {
+            match &image.data {
+                PdfImageData::Jpeg(data) => {
+                    let mut xobject = pdf.image_xobject(*id, data);
+                    xobject.filter(Filter::DctDecode);
+                    xobject
+                        .width(image.width as i32)
+                        .height(image.height as i32)
+                        .bits_per_component(8)
+                        .color_space()
+                        .device_rgb();
+                }
+                PdfImageData::Raw { rgb, alpha } => {
+                    let rgb = compress(rgb);
+                    let mut xobject = pdf.image_xobject(*id, &rgb);
+                    xobject.filter(Filter::FlateDecode);
+                    xobject
+                        .width(image.width as i32)
+                        .height(image.height as i32)
+                        .bits_per_component(8)
+                        .color_space()
+                        .device_rgb();
+                    if alpha.is_some() {
+                        xobject.s_mask(*mask_id);
+                    }
+                    xobject.finish();
+
+                    if let Some(alpha) = alpha {
+                        let alpha = compress(alpha);
+                        let mut mask = pdf.image_xobject(*mask_id, &alpha);
+                        mask.filter(Filter::FlateDecode);
+                        mask.width(image.width as i32)
+                            .height(image.height as i32)
+                            .bits_per_component(8)
+                            .color_space()
+                            .device_gray();
+                    }
+                }
+            }
+        }
+
+        let page_count = self.pages.len();
+        for (i, (page, page_id)) in self.pages.into_iter().zip(&page_ids).enumerate() {
+            let Page {
+                mut content,
+                links,
+                numbered,
+            } = page;
+
+            if numbered {
+                let number = (i + 1).to_string().into_bytes();
+                let width = Font::Regular.width(&number, PAGE_NUMBER_SIZE);
+                content
+                    .begin_text()
+                    .set_font(
+                        Name(Font::Regular.resource_name().as_bytes()),
+                        PAGE_NUMBER_SIZE,
+                    )
+                    .set_text_matrix([1.0, 0.0, 0.0, 1.0, (PAGE_WIDTH - width) / 2.0, MARGIN / 2.0])
+                    .show(Str(&number))
+                    .end_text();
+            }
+
+            let content_id = new_ref();
+            pdf.stream(content_id, &compress(&content.finish()))
+                .filter(Filter::FlateDecode);
+
+            let link_ids: Vec<Ref> = links.iter().map(|_| new_ref()).collect();
+            for ((rect, url), id) in links.iter().zip(&link_ids) {
+                let mut annotation = pdf.annotation(*id);
+                annotation
+                    .subtype(AnnotationType::Link)
+                    .rect(*rect)
+                    .border(0.0, 0.0, 0.0, None);
+                annotation
+                    .action()
+                    .action_type(ActionType::Uri)
+                    .uri(Str(url.as_bytes()));
+            }
+
+            let mut pdf_page = pdf.page(*page_id);
+            pdf_page
+                .parent(page_tree_id)
+                .media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+                .contents(content_id);
+            if !link_ids.is_empty() {
+                pdf_page.annotations(link_ids.iter().copied());
+            }
+            let mut resources = pdf_page.resources();
+            resources.fonts().pairs(
+                font_ids
+                    .iter()
+                    .map(|(face, id)| (Name(face.resource_name().as_bytes()), *id)),
+            );
+            let image_names: Vec<String> = (0..image_ids.len()).map(|i| format!("Im{i}")).collect();
+            resources.x_objects().pairs(
+                image_names
+                    .iter()
+                    .zip(&image_ids)
+                    .map(|(name, (id, _))| (Name(name.as_bytes()), *id)),
+            );
+        }
+        debug_assert_eq!(page_count, page_ids.len());
+
+        write_outline(&mut pdf, outline_id, &self.outline, &page_ids, &mut new_ref);
+
+        pdf.finish()
+    }
+}
+
+/// Writes the bookmarks as a tree, based on their levels.
+fn write_outline(
+    pdf: &mut Pdf,
+    outline_id: Ref,
+    entries: &[OutlineEntry],
+    page_ids: &[Ref],
+    new_ref: &mut impl FnMut() -> Ref,
+) {
+    if entries.is_empty() {
+        return;
+    }
+
+    let ids: Vec<Ref> = entries.iter().map(|_| new_ref()).collect();
+
+    let parents = outline_parents(entries);
+    let children = |parent: Option<usize>| -> Vec<usize> {
+        (0..entries.len())
+            .filter(|&i| parents[i] == parent)
+            .collect()
+    };
+
+    let top_level = children(None);
+    pdf.outline(outline_id)
+        .first(ids[top_level[0]])
+        .last(ids[*top_level.last().unwrap()])
+        .count(top_level.len() as i32);
+
+    for (i, entry) in entries.iter().enumerate() {
+        let siblings = children(parents[i]);
+        let position = siblings.iter().position(|&s| s == i).unwrap();
+        let own_children = children(Some(i));
+
+        let mut item = pdf.outline_item(ids[i]);
+        item.title(TextStr(&entry.title))
+            .parent(parents[i].map_or(outline_id, |p| ids[p]));
+        if position > 0 {
+            item.prev(ids[siblings[position - 1]]);
+        }
+        if let Some(next) = siblings.get(position + 1) {
+            item.next(ids[*next]);
+        }
+        if let (Some(first), Some(last)) = (own_children.first(), own_children.last()) {
+            // Negative counts mean the entry starts closed.
+            item.first(ids[*first])
+                .last(ids[*last])
+                .count(-(own_children.len() as i32));
+        }
+        item.dest()
+            .page(page_ids[entry.page])
+            .xyz(0.0, entry.top, None);
+    }
+}
+
+/// The parent of each entry is the closest previous entry with a lower level.
+fn outline_parents(entries: &[OutlineEntry]) -> Vec<Option<usize>> {
+    (0..entries.len())
+        .map(|i| (0..i).rev().find(|&j| entries[j].level < entries[i].level))
+        .collect()
+}
+
+pub(super) fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn pdf_image(image: &InternedImage) -> Result<PdfImage, Box<dyn std::error::Error>> {
+    let reader = ImageReader::new(Cursor::new(&image.data)).with_guessed_format()?;
+
+    if reader.format() == Some(ImageFormat::Jpeg) {
+        let (width, height) = reader.into_dimensions()?;
+        let decoded = image::load_from_memory(&image.data)?;
+        // Only plain rgb jpegs can be embedded as-is.
+        if matches!(decoded, DynamicImage::ImageRgb8(_))
+            && width <= MAX_IMAGE_SIZE
+            && height <= MAX_IMAGE_SIZE
+        {
+            return Ok(PdfImage {
+                width,
+                height,
+                data: PdfImageData::Jpeg(image.data.clone()),
+            });
+        }
+    }
+
+    let mut decoded = image::load_from_memory(&image.data)?;
+    if decoded.width() > MAX_IMAGE_SIZE || decoded.height() > MAX_IMAGE_SIZE {
+        decoded = decoded.resize(
+            MAX_IMAGE_SIZE,
+            MAX_IMAGE_SIZE,
+            image::imageops::FilterType::Lanczos3,
+        );
+    }
+    let (width, height) = decoded.dimensions();
+
+    let alpha = decoded.color().has_alpha().then(|| {
+        decoded
+            .to_rgba8()
+            .pixels()
+            .map(|pixel| pixel.0[3])
+            .collect()
+    });
+
+    Ok(PdfImage {
+        width,
+        height,
+        data: PdfImageData::Raw {
+            rgb: decoded.to_rgb8().into_raw(),
+            alpha,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outline_entries_nest_under_the_previous_lower_level() {
+        let entries: Vec<OutlineEntry> = [0, 1, 1, 0, 1, 2, 0]
+            .into_iter()
+            .map(|level| OutlineEntry {
+                title: String::new(),
+                level,
+                page: 0,
+                top: 0.0,
+            })
+            .collect();
+        assert_eq!(
+            outline_parents(&entries),
+            [None, Some(0), Some(0), None, Some(3), Some(4), None]
+        );
+    }
+
+    #[test]
+    fn bookmarks_make_the_outline() {
+        let mut document = Document::new(HashMap::new());
+        document.bookmark("Board", 0);
+        document.paragraph(&Paragraph::new("Text", Font::Regular, 10.0));
+        document.new_page(true);
+        document.bookmark("Thread", 1);
+        document.bookmark("Copyright", 0);
+        assert_eq!(document.pages.len(), 2);
+        let pages: Vec<usize> = document.outline.iter().map(|entry| entry.page).collect();
+        assert_eq!(pages, [0, 1, 1]);
+
+        let pdf = document.finish(&Metadata {
+            title: "Board".to_string(),
+            author: "alice".to_string(),
+        });
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/Outlines"));
+        assert!(pdf.contains("/Title (Thread)"));
+        // The board starts closed, with its thread in it.
+        assert!(pdf.contains("/Count -1"));
+        // Nothing needed an embedded font.
+        assert!(!pdf.contains("/FontFile2"));
+    }
+
+    #[test]
+    fn paragraphs_flow_onto_new_pages() {
+        let mut document = Document::new(HashMap::new());
+        let text = "word ".repeat(2000);
+        document.paragraph(&Paragraph::new(&text, Font::Regular, 10.0));
+        assert!(document.pages.len() > 1);
+        assert!(document.y >= MARGIN - 10.0 * LINE_HEIGHT);
+    }
+}
@@ -0,0 +1,542 @@
+//! The standard Times fonts every PDF reader has, so that nothing needs to be embedded.
+//! They only support the characters of WinAnsiEncoding (roughly Windows-1252),
+//! so the words that need others are drawn with DejaVu Serif, which is bundled
+//! and embedded with only the glyphs that were used.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use pdf_writer::{
+    types::{CidFontType, FontFlags, SystemInfo, UnicodeCmap},
+    Filter, Finish, Name, Pdf, Rect, Ref, Str,
+};
+
+use super::{document::compress, subset::subset};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum Font {
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+}
+impl Font {
+    pub const ALL: [Font; 4] = [Font::Regular, Font::Bold, Font::Italic, Font::BoldItalic];
+
+    pub fn base_font(self) -> &'static str {
+        match self {
+            Font::Regular => "Times-Roman",
+            Font::Bold => "Times-Bold",
+            Font::Italic => "Times-Italic",
+            Font::BoldItalic => "Times-BoldItalic",
+        }
+    }
+    /// The name of the font in the page resources.
+    pub fn resource_name(self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+            Font::Italic => "F3",
+            Font::BoldItalic => "F4",
+        }
+    }
+
+    pub fn bold(self) -> Font {
+        match self {
+            Font::Regular | Font::Bold => Font::Bold,
+            Font::Italic | Font::BoldItalic => Font::BoldItalic,
+        }
+    }
+    pub fn italic(self) -> Font {
+        match self {
+            Font::Regular | Font::Italic => Font::Italic,
+            Font::Bold | Font::BoldItalic => Font::BoldItalic,
+        }
+    }
+
+    /// The width of the already encoded text at the given size, in points.
+    pub fn width(self, encoded: &[u8], size: f32) -> f32 {
+        let widths = match self {
+            Font::Regular => &REGULAR_WIDTHS,
+            Font::Bold => &BOLD_WIDTHS,
+            Font::Italic => &ITALIC_WIDTHS,
+            Font::BoldItalic => &BOLD_ITALIC_WIDTHS,
+        };
+        let thousandths: u32 = encoded
+            .iter()
+            .map(|&code| match code {
+                32.. => u32::from(widths[usize::from(code - 32)]),
+                _ => 0,
+            })
+            .sum();
+
+        thousandths as f32 * size / 1000.0
+    }
+}
+
+/// How a word is drawn: with a standard font, or with the embedded font of the same style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum Face {
+    /// One byte per character, in WinAnsiEncoding.
+    Standard(Font),
+    /// Two bytes per glyph id.
+    Embedded(Font),
+}
+impl Face {
+    pub fn font(self) -> Font {
+        match self {
+            Face::Standard(font) | Face::Embedded(font) => font,
+        }
+    }
+
+    /// The name of the font in the page resources.
+    pub fn resource_name(self) -> &'static str {
+        match self {
+            Face::Standard(font) => font.resource_name(),
+            Face::Embedded(Font::Regular) => "U1",
+            Face::Embedded(Font::Bold) => "U2",
+            Face::Embedded(Font::Italic) => "U3",
+            Face::Embedded(Font::BoldItalic) => "U4",
+        }
+    }
+
+    /// How many bytes each character takes once encoded, as encoded text can only be cut between characters.
+    pub fn code_len(self) -> usize {
+        match self {
+            Face::Standard(_) => 1,
+            Face::Embedded(_) => 2,
+        }
+    }
+}
+
+/// The fonts of a document.
+///
+/// Words are drawn with the standard fonts whenever they can be.
+/// Those that have characters outside of WinAnsiEncoding use the bundled font of the same style instead,
+/// which then gets embedded, and the characters that no font has are replaced by `?`.
+#[derive(Default)]
+pub(super) struct Fonts {
+    /// Loaded the first time a style needs it.
+    embedded: HashMap<Font, EmbeddedFont>,
+    /// The characters that were replaced by `?`.
+    missing: BTreeSet<char>,
+}
+impl Fonts {
+    pub fn encode(&mut self, text: &str, font: Font) -> (Face, Vec<u8>) {
+        let chars: Vec<char> = text.chars().filter(|&c| !is_invisible(c)).collect();
+        if let Some(codes) = chars.iter().map(|&c| win_ansi_code(c)).collect() {
+            return (Face::Standard(font), codes);
+        }
+
+        let embedded = self
+            .embedded
+            .entry(font)
+            .or_insert_with(|| EmbeddedFont::load(font));
+        (
+            Face::Embedded(font),
+            embedded.encode(&chars, &mut self.missing),
+        )
+    }
+
+    /// The width of the already encoded text at the given size, in points.
+    pub fn width(&self, face: Face, encoded: &[u8], size: f32) -> f32 {
+        match face {
+            Face::Standard(font) => font.width(encoded, size),
+            Face::Embedded(font) => self
+                .embedded
+                .get(&font)
+                .map_or(0.0, |embedded| embedded.width(encoded, size)),
+        }
+    }
+
+    /// The characters that no font has, and were replaced by `?`.
+    pub fn missing(&self) -> &BTreeSet<char> {
+        &self.missing
+    }
+
+    /// Writes the fonts to the pdf, and returns their ids for the page resources.
+    pub fn write(&self, pdf: &mut Pdf, new_ref: &mut impl FnMut() -> Ref) -> Vec<(Face, Ref)> {
+        let mut ids = vec![];
+        for font in Font::ALL {
+            let id = new_ref();
+            pdf.type1_font(id)
+                .base_font(Name(font.base_font().as_bytes()))
+                .encoding_predefined(Name(b"WinAnsiEncoding"));
+            ids.push((Face::Standard(font), id));
+        }
+        for font in Font::ALL {
+            if let Some(embedded) = self.embedded.get(&font) {
+                ids.push((Face::Embedded(font), embedded.write(pdf, new_ref)));
+            }
+        }
+        ids
+    }
+}
+
+/// DejaVu Serif, which has most scripts written with an alphabet.
+const BUNDLED_FONTS: [(Font, &[u8]); 4] = [
+    (
+        Font::Regular,
+        include_bytes!("../../../fonts/DejaVuSerif.ttf"),
+    ),
+    (
+        Font::Bold,
+        include_bytes!("../../../fonts/DejaVuSerif-Bold.ttf"),
+    ),
+    (
+        Font::Italic,
+        include_bytes!("../../../fonts/DejaVuSerif-Italic.ttf"),
+    ),
+    (
+        Font::BoldItalic,
+        include_bytes!("../../../fonts/DejaVuSerif-BoldItalic.ttf"),
+    ),
+];
+
+/// A TrueType font, subset to the glyphs that were used, with the glyph ids as character codes (Identity-H).
+struct EmbeddedFont {
+    /// The PostScript name, with the tag of a subset.
+    name: String,
+    data: &'static [u8],
+    units_per_em: f32,
+    ascent: f32,
+    descent: f32,
+    cap_height: f32,
+    italic_angle: f32,
+    bbox: Rect,
+    /// In thousandths of the font size, for each glyph that was used.
+    widths: BTreeMap<u16, f32>,
+    /// The character each glyph that was used stands for, so that text can be copied out of the pdf.
+    chars: BTreeMap<u16, char>,
+}
+impl EmbeddedFont {
+    fn load(font: Font) -> Self {
+        let (_, data) = BUNDLED_FONTS
+            .iter()
+            .find(|(bundled, _)| *bundled == font)
+            .unwrap();
+        Self::parse(data).expect("the bundled fonts are TrueType fonts")
+    }
+
+    fn parse(data: &'static [u8]) -> Option<Self> {
+        let face = ttf_parser::Face::parse(data, 0).ok()?;
+        // Fonts with CFF outlines would need to be embedded differently.
+        face.tables().glyf?;
+
+        let name = face
+            .names()
+            .into_iter()
+            .filter(|name| name.name_id == ttf_parser::name_id::POST_SCRIPT_NAME)
+            .find_map(|name| name.to_string())?;
+
+        let units_per_em = f32::from(face.units_per_em());
+        let scale = |value: i16| f32::from(value) * 1000.0 / units_per_em;
+        let bbox = face.global_bounding_box();
+
+        Some(Self {
+            // Subsets are named with a tag of six capital letters.
+            name: format!(
+                "GLOWPB+{}",
+                name.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "")
+            ),
+            data,
+            units_per_em,
+            ascent: scale(face.ascender()),
+            descent: scale(face.descender()),
+            cap_height: scale(face.capital_height().unwrap_or(face.ascender())),
+            italic_angle: face.italic_angle().unwrap_or(0.0),
+            bbox: Rect::new(
+                scale(bbox.x_min),
+                scale(bbox.y_min),
+                scale(bbox.x_max),
+                scale(bbox.y_max),
+            ),
+            widths: BTreeMap::new(),
+            chars: BTreeMap::new(),
+        })
+    }
+
+    /// The glyph ids of the characters, with the one of `?` for those the font doesn't have.
+    fn encode(&mut self, chars: &[char], missing: &mut BTreeSet<char>) -> Vec<u8> {
+        let face = ttf_parser::Face::parse(self.data, 0).unwrap();
+
+        let mut encoded = Vec::with_capacity(chars.len() * 2);
+        for &c in chars {
+            let (glyph, c) = match face.glyph_index(c) {
+                Some(glyph) => (glyph, c),
+                None => {
+                    missing.insert(c);
+                    (face.glyph_index('?').unwrap_or_default(), '?')
+                }
+            };
+            self.widths.entry(glyph.0).or_insert_with(|| {
+                f32::from(face.glyph_hor_advance(glyph).unwrap_or_default()) * 1000.0
+                    / self.units_per_em
+            });
+            self.chars.entry(glyph.0).or_insert(c);
+            encoded.extend(glyph.0.to_be_bytes());
+        }
+
+        encoded
+    }
+
+    fn width(&self, encoded: &[u8], size: f32) -> f32 {
+        let thousandths: f32 = encoded
+            .chunks_exact(2)
+            .map(|glyph| u16::from_be_bytes([glyph[0], glyph[1]]))
+            .map(|glyph| self.widths.get(&glyph).copied().unwrap_or_default())
+            .sum();
+
+        thousandths * size / 1000.0
+    }
+
+    /// Writes the font and returns the id of the top level font dictionary.
+    fn write(&self, pdf: &mut Pdf, new_ref: &mut impl FnMut() -> Ref) -> Ref {
+        let id = new_ref();
+        let cid_font_id = new_ref();
+        let descriptor_id = new_ref();
+        let file_id = new_ref();
+        let to_unicode_id = new_ref();
+
+        let name = Name(self.name.as_bytes());
+        let system_info = SystemInfo {
+            registry: Str(b"Adobe"),
+            ordering: Str(b"Identity"),
+            supplement: 0,
+        };
+
+        pdf.type0_font(id)
+            .base_font(name)
+            .encoding_predefined(Name(b"Identity-H"))
+            .descendant_font(cid_font_id)
+            .to_unicode(to_unicode_id);
+
+        let mut cid_font = pdf.cid_font(cid_font_id);
+        cid_font
+            .subtype(CidFontType::Type2)
+            .base_font(name)
+            .system_info(system_info)
+            .font_descriptor(descriptor_id)
+            .cid_to_gid_map_predefined(Name(b"Identity"));
+        let mut widths = cid_font.widths();
+        for (&glyph, &width) in &self.widths {
+            widths.consecutive(glyph, [width]);
+        }
+        widths.finish();
+        cid_font.finish();
+
+        let mut flags = FontFlags::SYMBOLIC;
+        if self.italic_angle != 0.0 {
+            flags |= FontFlags::ITALIC;
+        }
+        pdf.font_descriptor(descriptor_id)
+            .name(name)
+            .flags(flags)
+            .bbox(self.bbox)
+            .italic_angle(self.italic_angle)
+            .ascent(self.ascent)
+            .descent(self.descent)
+            .cap_height(self.cap_height)
+            .stem_v(80.0)
+            .font_file2(file_id);
+
+        let glyphs: BTreeSet<u16> = self.widths.keys().copied().collect();
+        let data = subset(self.data, &glyphs).expect("the bundled fonts can be subset");
+        pdf.stream(file_id, &compress(&data))
+            .filter(Filter::FlateDecode)
+            .pair(Name(b"Length1"), data.len() as i32);
+
+        let mut cmap = UnicodeCmap::new(Name(b"Custom"), system_info);
+        for (&glyph, &c) in &self.chars {
+            cmap.pair(glyph, c);
+        }
+        pdf.cmap(to_unicode_id, &cmap.finish());
+
+        id
+    }
+}
+
+/// Soft hyphens, zero-width spaces and joiners, and variation selectors.
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{ad}' | '\u{200b}'..='\u{200f}' | '\u{fe00}'..='\u{fe0f}' | '\u{feff}')
+}
+
+/// The character's code in WinAnsiEncoding, or a close enough replacement.
+fn win_ansi_code(c: char) -> Option<u8> {
+    let code = match c {
+        ' '..='~' => c as u8,
+        '\u{a0}'..='\u{ff}' => c as u8,
+        '€' => 128,
+        '‚' => 130,
+        'ƒ' => 131,
+        '„' => 132,
+        '…' => 133,
+        '†' => 134,
+        '‡' => 135,
+        'ˆ' => 136,
+        '‰' => 137,
+        'Š' => 138,
+        '‹' => 139,
+        'Œ' => 140,
+        'Ž' => 142,
+        '‘' => 145,
+        '’' => 146,
+        '“' => 147,
+        '”' => 148,
+        '•' => 149,
+        '–' => 150,
+        '—' => 151,
+        '˜' => 152,
+        '™' => 153,
+        'š' => 154,
+        '›' => 155,
+        'œ' => 156,
+        'ž' => 158,
+        'Ÿ' => 159,
+        // Close enough replacements.
+        '\t' | '\u{2000}'..='\u{200a}' | '\u{202f}' => b' ',
+        '\u{2010}' | '\u{2011}' | '\u{2212}' => b'-',
+        '\u{2015}' => 151,
+        '\u{2032}' => b'\'',
+        '\u{2033}' => b'"',
+        '▼' | '►' | '▶' => 187,
+        _ => return None,
+    };
+
+    Some(code)
+}
+
+/// Times-Roman, for the codes 32 to 255.
+const REGULAR_WIDTHS: [u16; 224] = [
+    250, 333, 408, 500, 500, 833, 778, 180, 333, 333, 500, 564, 250, 333, 250, 278, 500, 500, 500,
+    500, 500, 500, 500, 500, 500, 500, 278, 278, 564, 564, 564, 444, 921, 722, 667, 667, 722, 611,
+    556, 722, 722, 333, 389, 722, 611, 889, 722, 722, 556, 722, 667, 556, 611, 722, 722, 944, 722,
+    722, 611, 333, 278, 333, 469, 500, 333, 444, 500, 444, 500, 444, 333, 500, 500, 278, 278, 500,
+    278, 778, 500, 500, 500, 500, 333, 389, 278, 500, 500, 722, 500, 500, 444, 480, 200, 480, 541,
+    0, 500, 0, 333, 500, 444, 1000, 500, 500, 333, 1000, 556, 333, 889, 0, 611, 0, 0, 333, 333,
+    444, 444, 350, 500, 1000, 333, 980, 389, 333, 722, 0, 444, 722, 250, 333, 500, 500, 500, 500,
+    200, 500, 333, 760, 276, 500, 564, 333, 760, 333, 400, 564, 300, 300, 333, 500, 453, 250, 333,
+    300, 310, 500, 750, 750, 750, 444, 722, 722, 722, 722, 722, 722, 889, 667, 611, 611, 611, 611,
+    333, 333, 333, 333, 722, 722, 722, 722, 722, 722, 722, 564, 722, 722, 722, 722, 722, 722, 556,
+    500, 444, 444, 444, 444, 444, 444, 667, 444, 444, 444, 444, 444, 278, 278, 278, 278, 500, 500,
+    500, 500, 500, 500, 500, 564, 500, 500, 500, 500, 500, 500, 500, 500,
+];
+
+/// Times-Bold, for the codes 32 to 255.
+const BOLD_WIDTHS: [u16; 224] = [
+    250, 333, 555, 500, 500, 1000, 833, 278, 333, 333, 500, 570, 250, 333, 250, 278, 500, 500, 500,
+    500, 500, 500, 500, 500, 500, 500, 333, 333, 570, 570, 570, 500, 930, 722, 667, 722, 722, 667,
+    611, 778, 778, 389, 500, 778, 667, 944, 722, 778, 611, 778, 722, 556, 667, 722, 722, 1000, 722,
+    722, 667, 333, 278, 333, 581, 500, 333, 500, 556, 444, 556, 444, 333, 500, 556, 278, 333, 556,
+    278, 833, 556, 500, 556, 556, 444, 389, 333, 556, 500, 722, 500, 500, 444, 394, 220, 394, 520,
+    0, 500, 0, 333, 500, 500, 1000, 500, 500, 333, 1000, 556, 333, 1000, 0, 667, 0, 0, 333, 333,
+    500, 500, 350, 500, 1000, 333, 1000, 389, 333, 722, 0, 444, 722, 250, 333, 500, 500, 500, 500,
+    220, 500, 333, 747, 300, 500, 570, 333, 747, 333, 400, 570, 300, 300, 333, 556, 540, 250, 333,
+    300, 330, 500, 750, 750, 750, 500, 722, 722, 722, 722, 722, 722, 1000, 722, 667, 667, 667, 667,
+    389, 389, 389, 389, 722, 722, 778, 778, 778, 778, 778, 570, 778, 722, 722, 722, 722, 722, 611,
+    556, 500, 500, 500, 500, 500, 500, 722, 444, 444, 444, 444, 444, 278, 278, 278, 278, 500, 556,
+    500, 500, 500, 500, 500, 570, 500, 556, 556, 556, 556, 500, 556, 500,
+];
+
+/// Times-Italic, for the codes 32 to 255.
+const ITALIC_WIDTHS: [u16; 224] = [
+    250, 333, 420, 500, 500, 833, 778, 214, 333, 333, 500, 675, 250, 333, 250, 278, 500, 500, 500,
+    500, 500, 500, 500, 500, 500, 500, 333, 333, 675, 675, 675, 500, 920, 611, 611, 667, 722, 611,
+    611, 722, 722, 333, 444, 667, 556, 833, 667, 722, 611, 722, 611, 500, 556, 722, 611, 833, 611,
+    556, 556, 389, 278, 389, 422, 500, 333, 500, 500, 444, 500, 444, 278, 500, 500, 278, 278, 444,
+    278, 722, 500, 500, 500, 500, 389, 389, 278, 500, 444, 667, 444, 444, 389, 400, 275, 400, 541,
+    0, 500, 0, 333, 500, 556, 889, 500, 500, 333, 1000, 500, 333, 944, 0, 556, 0, 0, 333, 333, 556,
+    556, 350, 500, 889, 333, 980, 389, 333, 667, 0, 389, 556, 250, 389, 500, 500, 500, 500, 275,
+    500, 333, 760, 276, 500, 675, 333, 760, 333, 400, 675, 300, 300, 333, 500, 523, 250, 333, 300,
+    310, 500, 750, 750, 750, 500, 611, 611, 611, 611, 611, 611, 889, 667, 611, 611, 611, 611, 333,
+    333, 333, 333, 722, 667, 722, 722, 722, 722, 722, 675, 722, 722, 722, 722, 722, 556, 611, 500,
+    500, 500, 500, 500, 500, 500, 667, 444, 444, 444, 444, 444, 278, 278, 278, 278, 500, 500, 500,
+    500, 500, 500, 500, 675, 500, 500, 500, 500, 500, 444, 500, 444,
+];
+
+/// Times-BoldItalic, for the codes 32 to 255.
+const BOLD_ITALIC_WIDTHS: [u16; 224] = [
+    250, 389, 555, 500, 500, 833, 778, 278, 333, 333, 500, 570, 250, 333, 250, 278, 500, 500, 500,
+    500, 500, 500, 500, 500, 500, 500, 333, 333, 570, 570, 570, 500, 832, 667, 667, 667, 722, 667,
+    667, 722, 778, 389, 500, 667, 611, 889, 722, 722, 611, 722, 667, 556, 611, 722, 667, 889, 667,
+    611, 611, 333, 278, 333, 570, 500, 333, 500, 500, 444, 500, 444, 333, 500, 556, 278, 278, 500,
+    278, 778, 556, 500, 500, 500, 389, 389, 278, 556, 444, 667, 500, 444, 389, 348, 220, 348, 570,
+    0, 500, 0, 333, 500, 500, 1000, 500, 500, 333, 1000, 556, 333, 944, 0, 611, 0, 0, 333, 333,
+    500, 500, 350, 500, 1000, 333, 1000, 389, 333, 722, 0, 389, 611, 250, 389, 500, 500, 500, 500,
+    220, 500, 333, 747, 266, 500, 606, 333, 747, 333, 400, 570, 300, 300, 333, 576, 500, 250, 333,
+    300, 300, 500, 750, 750, 750, 500, 667, 667, 667, 667, 667, 667, 944, 667, 667, 667, 667, 667,
+    389, 389, 389, 389, 722, 722, 722, 722, 722, 722, 722, 570, 722, 722, 722, 722, 722, 611, 611,
+    500, 500, 500, 500, 500, 500, 500, 722, 444, 444, 444, 444, 444, 278, 278, 278, 278, 500, 556,
+    500, 500, 500, 500, 500, 570, 500, 556, 556, 556, 556, 444, 500, 444,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_text_is_encoded_in_win_ansi() {
+        let mut fonts = Fonts::default();
+        let (face, encoded) = fonts.encode("Café – “ok”\u{ad}", Font::Italic);
+        assert_eq!(face, Face::Standard(Font::Italic));
+        assert_eq!(encoded, b"Caf\xe9 \x96 \x93ok\x94");
+        assert!(fonts.embedded.is_empty());
+    }
+
+    #[test]
+    fn other_text_uses_the_embedded_font() {
+        let mut fonts = Fonts::default();
+        let (face, encoded) = fonts.encode("Жук", Font::Bold);
+        assert_eq!(face, Face::Embedded(Font::Bold));
+        assert_eq!(encoded.len(), 3 * face.code_len());
+
+        let embedded = &fonts.embedded[&Font::Bold];
+        let chars: Vec<char> = embedded.chars.values().copied().collect();
+        assert_eq!(chars.len(), 3);
+        assert!(chars.contains(&'Ж'));
+        assert!(fonts.missing().is_empty());
+    }
+
+    #[test]
+    fn missing_characters_become_question_marks() {
+        let mut fonts = Fonts::default();
+        let (face, encoded) = fonts.encode("Ж漢", Font::Regular);
+        assert_eq!(face, Face::Embedded(Font::Regular));
+        assert_eq!(fonts.missing().iter().collect::<String>(), "漢");
+
+        let question_mark = fonts.encode("Ж?", Font::Regular).1;
+        assert_eq!(encoded, question_mark);
+    }
+
+    #[test]
+    fn widths_are_scaled_to_the_font_size() {
+        assert_eq!(Font::Regular.width(b"A", 10.0), 7.22);
+        assert_eq!(Font::Bold.width(b"AA", 1.0), 1.444);
+        // Control characters have no width.
+        assert_eq!(Font::Regular.width(b"\n", 10.0), 0.0);
+
+        let mut fonts = Fonts::default();
+        let (face, one) = fonts.encode("Ж", Font::Regular);
+        let (_, two) = fonts.encode("ЖЖ", Font::Regular);
+        let width = fonts.width(face, &one, 10.0);
+        assert!(width > 0.0);
+        assert_eq!(fonts.width(face, &two, 10.0), 2.0 * width);
+        assert_eq!(fonts.width(face, &one, 20.0), 2.0 * width);
+    }
+
+    #[test]
+    fn only_used_styles_are_embedded() {
+        let mut fonts = Fonts::default();
+        fonts.encode("Ж", Font::Italic);
+
+        let mut pdf = Pdf::new();
+        let mut next = 0;
+        let ids = fonts.write(&mut pdf, &mut || {
+            next += 1;
+            Ref::new(next)
+        });
+        let faces: Vec<Face> = ids.into_iter().map(|(face, _)| face).collect();
+        assert_eq!(faces.len(), 5);
+        assert_eq!(faces[4], Face::Embedded(Font::Italic));
+        assert!(pdf.finish().len() < 20_000);
+    }
+}
@@ -0,0 +1,576 @@
+//! Turns processed html into paragraphs and images, and paragraphs into lines.
+
+use markup5ever_rcdom::{Handle, NodeData};
+
+use super::{
+    fonts::{Face, Font, Fonts},
+    transform,
+};
+
+/// How much list items and quotes are indented by, in points.
+const INDENT: f32 = 14.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Span {
+    pub text: String,
+    pub font: Font,
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Paragraph {
+    pub spans: Vec<Span>,
+    /// In points.
+    pub size: f32,
+    /// From the left of the text column, in points.
+    pub indent: f32,
+    /// Drawn just left of the first line, like a list bullet.
+    pub marker: Option<String>,
+    pub centered: bool,
+}
+impl Paragraph {
+    pub fn new(text: &str, font: Font, size: f32) -> Self {
+        Self {
+            spans: vec![Span {
+                text: text.to_string(),
+                font,
+                link: None,
+            }],
+            size,
+            indent: 0.0,
+            marker: None,
+            centered: false,
+        }
+    }
+    pub fn centered(self) -> Self {
+        Self {
+            centered: true,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Item {
+    Paragraph(Paragraph),
+    /// A horizontal line.
+    Rule,
+    /// The url of an image, which gets its own line.
+    Image(String),
+}
+
+/// Lays out the processed html with the given base font size.
+pub(super) fn html_to_items(content: &str, size: f32) -> Vec<Item> {
+    let dom = transform::parse_html(content);
+
+    let mut builder = Builder::default();
+    let context = Context {
+        font: Font::Regular,
+        size,
+        indent: 0.0,
+        link: None,
+    };
+    builder.walk(&dom.document, &context);
+    builder.flush(&context);
+
+    builder.items
+}
+
+#[derive(Debug, Clone)]
+struct Context {
+    font: Font,
+    size: f32,
+    indent: f32,
+    link: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Builder {
+    items: Vec<Item>,
+    spans: Vec<Span>,
+    /// For the next paragraph, set by list items.
+    marker: Option<String>,
+}
+impl Builder {
+    fn walk(&mut self, node: &Handle, context: &Context) {
+        match &node.data {
+            NodeData::Document => self.walk_children(node, context),
+            NodeData::Text { contents } => {
+                let text = collapse_whitespace(&contents.borrow());
+                if !text.is_empty() {
+                    self.spans.push(Span {
+                        text,
+                        font: context.font,
+                        link: context.link.clone(),
+                    });
+                }
+            }
+            NodeData::Element { name, attrs, .. } => {
+                let attribute = |attribute: &str| {
+                    attrs
+                        .borrow()
+                        .iter()
+                        .find(|attr| &*attr.name.local == attribute)
+                        .map(|attr| attr.value.to_string())
+                };
+
+                let tag = &*name.local;
+                match tag {
+                    "br" => self.spans.push(Span {
+                        text: "\n".to_string(),
+                        font: context.font,
+                        link: None,
+                    }),
+                    "em" | "i" | "cite" | "var" => {
+                        let context = Context {
+                            font: context.font.italic(),
+                            ..context.clone()
+                        };
+                        self.walk_children(node, &context);
+                    }
+                    "strong" | "b" => {
+                        let context = Context {
+                            font: context.font.bold(),
+                            ..context.clone()
+                        };
+                        self.walk_children(node, &context);
+                    }
+                    "a" => {
+                        let context = Context {
+                            link: attribute("href").or(context.link.clone()),
+                            ..context.clone()
+                        };
+                        self.walk_children(node, &context);
+                    }
+                    "img" => {
+                        if let Some(src) = attribute("src") {
+                            self.flush(context);
+                            self.items.push(Item::Image(src));
+                        }
+                    }
+                    "hr" => {
+                        self.flush(context);
+                        self.items.push(Item::Rule);
+                    }
+                    "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                        self.flush(context);
+                        let scale = match tag {
+                            "h1" => 1.6,
+                            "h2" => 1.4,
+                            "h3" => 1.2,
+                            _ => 1.0,
+                        };
+                        let context = Context {
+                            font: context.font.bold(),
+                            size: context.size * scale,
+                            ..context.clone()
+                        };
+                        self.walk_children(node, &context);
+                        self.flush(&context);
+                    }
+                    "summary" | "dt" | "th" => {
+                        self.flush(context);
+                        let context = Context {
+                            font: context.font.bold(),
+                            ..context.clone()
+                        };
+                        self.walk_children(node, &context);
+                        self.flush(&context);
+                    }
+                    "blockquote" | "details" | "dd" => {
+                        self.flush(context);
+                        let context = Context {
+                            indent: context.indent + INDENT,
+                            ..context.clone()
+                        };
+                        self.walk_children(node, &context);
+                        self.flush(&context);
+                    }
+                    "ul" | "ol" => {
+                        self.flush(context);
+                        let context = Context {
+                            indent: context.indent + INDENT,
+                            ..context.clone()
+                        };
+                        let mut number: usize = attribute("start")
+                            .and_then(|start| start.parse().ok())
+                            .unwrap_or(1);
+                        for child in node.children.borrow().iter() {
+                            if matches!(&child.data, NodeData::Element { name, .. } if &*name.local == "li")
+                            {
+                                self.marker = Some(match tag {
+                                    "ol" => format!("{number}."),
+                                    _ => "•".to_string(),
+                                });
+                                number += 1;
+                            }
+                            self.walk(child, &context);
+                            self.flush(&context);
+                        }
+                    }
+                    "tr" => {
+                        self.flush(context);
+                        let mut first = true;
+                        for cell in node.children.borrow().iter() {
+                            if !matches!(&cell.data, NodeData::Element { .. }) {
+                                continue;
+                            }
+                            if !first {
+                                self.spans.push(Span {
+                                    text: "  |  ".to_string(),
+                                    font: context.font,
+                                    link: None,
+                                });
+                            }
+                            first = false;
+                            self.walk_children(cell, context);
+                        }
+                        self.flush(context);
+                    }
+                    "script" | "style" | "head" => {}
+                    _ if BLOCK_ELEMENTS.contains(&tag) => {
+                        self.flush(context);
+                        self.walk_children(node, context);
+                        self.flush(context);
+                    }
+                    _ => self.walk_children(node, context),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn walk_children(&mut self, node: &Handle, context: &Context) {
+        for child in node.children.borrow().iter() {
+            self.walk(child, context);
+        }
+    }
+
+    /// Ends the current paragraph, if it has any text.
+    fn flush(&mut self, context: &Context) {
+        let spans = std::mem::take(&mut self.spans);
+        if spans.iter().all(|span| span.text.trim().is_empty()) {
+            return;
+        }
+
+        self.items.push(Item::Paragraph(Paragraph {
+            spans,
+            size: context.size,
+            indent: context.indent,
+            marker: self.marker.take(),
+            centered: false,
+        }));
+    }
+}
+
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "dl",
+    "div",
+    "figure",
+    "figcaption",
+    "footer",
+    "header",
+    "li",
+    "nav",
+    "p",
+    "pre",
+    "section",
+    "table",
+];
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut last_was_space = false;
+    for c in text.chars() {
+        if c.is_whitespace() && c != '\u{a0}' {
+            if !last_was_space {
+                collapsed.push(' ');
+            }
+            last_was_space = true;
+        } else {
+            collapsed.push(c);
+            last_was_space = false;
+        }
+    }
+    collapsed
+}
+
+/// A piece of text that is drawn in one go.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Word {
+    /// Encoded for the face.
+    pub text: Vec<u8>,
+    pub face: Face,
+    pub link: Option<String>,
+    /// From the start of the line, in points.
+    pub x: f32,
+    pub width: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(super) struct Line {
+    pub words: Vec<Word>,
+    pub width: f32,
+}
+
+#[derive(Debug, Clone)]
+enum Token {
+    Word {
+        text: Vec<u8>,
+        face: Face,
+        link: Option<String>,
+        /// Lines can only be broken where there is a space.
+        space_before: bool,
+    },
+    Break,
+}
+
+/// Breaks a paragraph into lines, one at a time, as the available width can change between lines.
+pub(super) struct LineBreaker {
+    tokens: Vec<Token>,
+    position: usize,
+    size: f32,
+}
+impl LineBreaker {
+    pub fn new(paragraph: &Paragraph, fonts: &mut Fonts) -> Self {
+        let mut tokens = vec![];
+        let mut space = false;
+        for span in &paragraph.spans {
+            for (i, part) in span.text.split('\n').enumerate() {
+                if i > 0 {
+                    tokens.push(Token::Break);
+                    space = false;
+                }
+                for (j, word) in part.split(' ').enumerate() {
+                    if j > 0 {
+                        space = true;
+                    }
+                    if word.is_empty() {
+                        continue;
+                    }
+                    let (face, text) = fonts.encode(word, span.font);
+                    tokens.push(Token::Word {
+                        text,
+                        face,
+                        link: span.link.clone(),
+                        space_before: space,
+                    });
+                    space = false;
+                }
+            }
+        }
+
+        Self {
+            tokens,
+            position: 0,
+            size: paragraph.size,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    /// The next line that fits in the width, or [None] once all the text is used.
+    pub fn next_line(&mut self, max_width: f32, fonts: &Fonts) -> Option<Line> {
+        if self.is_done() {
+            return None;
+        }
+
+        let mut line = Line::default();
+        while let Some(token) = self.tokens.get(self.position) {
+            let Token::Word {
+                text,
+                face,
+                link,
+                space_before,
+            } = token
+            else {
+                self.position += 1;
+                break;
+            };
+
+            // Words that are glued together (like "<em>a</em>b") have to move to the next line together.
+            let glued_end = self.tokens[self.position + 1..]
+                .iter()
+                .position(|t| {
+                    !matches!(
+                        t,
+                        Token::Word {
+                            space_before: false,
+                            ..
+                        }
+                    )
+                })
+                .map_or(self.tokens.len(), |i| self.position + 1 + i);
+            let glued_width: f32 = self.tokens[self.position..glued_end]
+                .iter()
+                .map(|t| match t {
+                    Token::Word { text, face, .. } => fonts.width(*face, text, self.size),
+                    Token::Break => 0.0,
+                })
+                .sum();
+
+            let space = if *space_before && !line.words.is_empty() {
+                face.font().width(b" ", self.size)
+            } else {
+                0.0
+            };
+            let starts_glued_group = *space_before || line.words.is_empty();
+            if starts_glued_group
+                && !line.words.is_empty()
+                && line.width + space + glued_width > max_width
+            {
+                break;
+            }
+
+            let width = fonts.width(*face, text, self.size);
+            if line.words.is_empty() && width > max_width {
+                // Too long to fit on any line, so it is cut wherever it needs to be.
+                let step = face.code_len();
+                let mut cut = text.len();
+                while cut > step && fonts.width(*face, &text[..cut], self.size) > max_width {
+                    cut -= step;
+                }
+                let (head, tail) = text.split_at(cut);
+                line.words.push(Word {
+                    text: head.to_vec(),
+                    face: *face,
+                    link: link.clone(),
+                    x: 0.0,
+                    width: fonts.width(*face, head, self.size),
+                });
+                line.width = fonts.width(*face, head, self.size);
+                let tail = tail.to_vec();
+                if tail.is_empty() {
+                    self.position += 1;
+                } else if let Token::Word { text, .. } = &mut self.tokens[self.position] {
+                    *text = tail;
+                }
+                break;
+            }
+
+            line.words.push(Word {
+                text: text.clone(),
+                face: *face,
+                link: link.clone(),
+                x: line.width + space,
+                width,
+            });
+            line.width += space + width;
+            self.position += 1;
+        }
+
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text of each line, with the standard fonts.
+    fn lines(paragraph: &Paragraph, max_width: f32) -> Vec<String> {
+        let mut fonts = Fonts::default();
+        let mut breaker = LineBreaker::new(paragraph, &mut fonts);
+        let mut lines = vec![];
+        while let Some(line) = breaker.next_line(max_width, &fonts) {
+            let mut text = String::new();
+            let mut end = 0.0;
+            for word in &line.words {
+                if word.x > end {
+                    text.push(' ');
+                }
+                text.push_str(std::str::from_utf8(&word.text).unwrap());
+                end = word.x + word.width;
+            }
+            lines.push(text);
+        }
+        lines
+    }
+
+    #[test]
+    fn lines_are_broken_between_words() {
+        // "aaa" is 13.32 points wide at this size, "bbb" 15, and a space 2.5.
+        let paragraph = Paragraph::new("aaa bbb ccc", Font::Regular, 10.0);
+        assert_eq!(lines(&paragraph, 31.0), ["aaa bbb", "ccc"]);
+        assert_eq!(lines(&paragraph, 30.0), ["aaa", "bbb", "ccc"]);
+        assert_eq!(lines(&paragraph, 100.0), ["aaa bbb ccc"]);
+
+        let mut fonts = Fonts::default();
+        let line = LineBreaker::new(&paragraph, &mut fonts)
+            .next_line(31.0, &fonts)
+            .unwrap();
+        assert_eq!(line.words[1].x, 15.82);
+        assert_eq!(line.width, line.words[1].x + line.words[1].width);
+    }
+
+    #[test]
+    fn line_breaks_start_new_lines() {
+        let paragraph = Paragraph::new("a\nb c", Font::Regular, 10.0);
+        assert_eq!(lines(&paragraph, 100.0), ["a", "b c"]);
+    }
+
+    #[test]
+    fn glued_words_move_to_the_next_line_together() {
+        let paragraph = Paragraph {
+            spans: vec![
+                Span {
+                    text: "aaa b".to_string(),
+                    font: Font::Regular,
+                    link: None,
+                },
+                Span {
+                    text: "bb".to_string(),
+                    font: Font::Italic,
+                    link: None,
+                },
+            ],
+            ..Paragraph::new("", Font::Regular, 10.0)
+        };
+        assert_eq!(lines(&paragraph, 25.0), ["aaa", "bbb"]);
+        assert_eq!(lines(&paragraph, 31.0), ["aaa bbb"]);
+    }
+
+    #[test]
+    fn overlong_words_are_cut() {
+        let paragraph = Paragraph::new("aaaaaaaaaa", Font::Regular, 10.0);
+        assert_eq!(lines(&paragraph, 20.0), ["aaaa", "aaaa", "aa"]);
+
+        // Embedded text is cut between glyphs, not in the middle of one.
+        let paragraph = Paragraph::new("жжжжжжжжжж", Font::Regular, 10.0);
+        let mut fonts = Fonts::default();
+        let mut breaker = LineBreaker::new(&paragraph, &mut fonts);
+        let mut glyphs = 0;
+        while let Some(line) = breaker.next_line(20.0, &fonts) {
+            assert_eq!(line.words[0].text.len() % 2, 0);
+            glyphs += line.words[0].text.len() / 2;
+        }
+        assert_eq!(glyphs, 10);
+    }
+
+    #[test]
+    fn html_becomes_paragraphs_rules_and_images() {
+        let items = html_to_items(
+            r#"<p>Some <em>text</em></p><hr><img src="image.png"><ul><li>One</li></ul>"#,
+            10.0,
+        );
+        assert_eq!(items.len(), 4);
+
+        let Item::Paragraph(paragraph) = &items[0] else {
+            panic!("{:?} is not a paragraph", items[0]);
+        };
+        let fonts: Vec<Font> = paragraph.spans.iter().map(|span| span.font).collect();
+        assert_eq!(fonts, [Font::Regular, Font::Italic]);
+        assert_eq!(items[1], Item::Rule);
+        assert_eq!(items[2], Item::Image("image.png".to_string()));
+        let Item::Paragraph(item) = &items[3] else {
+            panic!("{:?} is not a paragraph", items[3]);
+        };
+        assert_eq!(item.marker.as_deref(), Some("•"));
+        assert_eq!(item.indent, INDENT);
+    }
+}
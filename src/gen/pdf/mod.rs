@@ -0,0 +1,261 @@
+mod document;
+mod fonts;
+mod layout;
+mod subset;
+
+use std::{collections::HashMap, error::Error};
+
+use crate::{
+    intern_images::{placeholder_url, process_images},
+    types::{Continuity, Section},
+    Thread,
+};
+
+use self::{
+    document::{Document, Metadata},
+    fonts::Font,
+    layout::{html_to_items, Item, Paragraph},
+};
+
+use super::{plain_author_names, process_content, transform, Block, Options, SECTIONLESS_NAME};
+
+const BODY_SIZE: f32 = 10.5;
+const ICON_SIZE: f32 = 48.0;
+
+impl Thread {
+    /// A paginated pdf of the thread, with the images embedded.
+    ///
+    /// Text the standard PDF fonts can't show is drawn with an embedded copy of DejaVu Serif,
+    /// and the characters no font has are replaced by `?` (and logged).
+    pub async fn to_pdf(&self, options: Options) -> Result<Vec<u8>, Box<dyn Error>> {
+        let images = process_images(self.images_to_intern(options).await?, options).await?;
+        let mut document = Document::new(images);
+
+        document.new_page(false);
+        document.bookmark(&self.post.subject, 0);
+        self.pdf_title(&mut document, options, true);
+
+        document.new_page(true);
+        self.pdf_content(&mut document, options);
+
+        pdf_copyright_page(
+            &mut document,
+            &self.post.subject,
+            &plain_author_names(&self.post.authors),
+        );
+
+        Ok(document.finish(&Metadata {
+            title: self.post.subject.clone(),
+            author: plain_author_names(&self.post.authors),
+        }))
+    }
+
+    /// The subject, authors, status, and description.
+    /// On a page of its own for a single thread, or as a chapter heading in a board.
+    fn pdf_title(&self, document: &mut Document, options: Options, full_page: bool) {
+        let post = &self.post;
+
+        document.vertical_space(if full_page { 120.0 } else { 20.0 });
+        document.paragraph(&Paragraph::new(&post.subject, Font::Bold, 20.0).centered());
+        document.vertical_space(6.0);
+        document.paragraph(
+            &Paragraph::new(
+                &format!("by {}", plain_author_names(&post.authors)),
+                Font::Italic,
+                12.0,
+            )
+            .centered(),
+        );
+        document.paragraph(
+            &Paragraph::new(&format!("in {}", post.board.name), Font::Regular, 11.0).centered(),
+        );
+        let reply_count = self.replies.len();
+        document.paragraph(
+            &Paragraph::new(
+                &format!("[Status: {}] [{reply_count} replies]", post.status),
+                Font::Regular,
+                BODY_SIZE,
+            )
+            .centered(),
+        );
+
+        if let Some(description) = &post.description {
+            document.vertical_space(6.0);
+            let description = process_content(description, options, &HashMap::new());
+            for item in html_to_items(&description, BODY_SIZE) {
+                match item {
+                    Item::Paragraph(paragraph) => document.paragraph(&paragraph.centered()),
+                    item => pdf_item(document, &item),
+                }
+            }
+        }
+        document.vertical_space(12.0);
+    }
+
+    /// The post and all the replies, separated by rules.
+    fn pdf_content(&self, document: &mut Document, options: Options) {
+        for (i, block) in self.blocks().enumerate() {
+            if i > 0 {
+                document.rule();
+            }
+            pdf_block(document, &block, options);
+        }
+    }
+}
+
+impl Continuity {
+    /// A paginated pdf of the entire board, with the images embedded,
+    /// and bookmarks for each section and thread.
+    pub async fn to_pdf(&self, options: Options) -> Result<Vec<u8>, Box<dyn Error>> {
+        let images = process_images(self.images_to_intern(options).await?, options).await?;
+        let mut document = Document::new(images);
+
+        let authors = plain_author_names(&self.authors());
+        let name = &self.board.name;
+
+        // Title
+        document.new_page(false);
+        document.bookmark(name, 0);
+        document.vertical_space(120.0);
+        document.paragraph(&Paragraph::new(name, Font::Bold, 24.0).centered());
+        document.vertical_space(6.0);
+        document
+            .paragraph(&Paragraph::new(&format!("by {authors}"), Font::Italic, 12.0).centered());
+        let thread_count = self.threads.len();
+        document.paragraph(
+            &Paragraph::new(
+                &format!("[{thread_count} threads]"),
+                Font::Regular,
+                BODY_SIZE,
+            )
+            .centered(),
+        );
+
+        // Same hierarchy as the epub table of contents.
+        let (sections, sectionless_threads) = self.sections();
+        let section_iter = sections
+            .iter()
+            .map(|(section, threads)| (Some(section), threads))
+            .chain((!sectionless_threads.is_empty()).then_some((None, &sectionless_threads)));
+        for (section, threads) in section_iter {
+            let thread_level = if sections.is_empty() {
+                0
+            } else {
+                let name = match section {
+                    Some(Section { name, .. }) => name,
+                    None => SECTIONLESS_NAME,
+                };
+                document.new_page(false);
+                document.bookmark(name, 0);
+                document.vertical_space(160.0);
+                document.paragraph(&Paragraph::new(name, Font::Bold, 20.0).centered());
+                document.paragraph(
+                    &Paragraph::new(
+                        &format!("[{} threads]", threads.len()),
+                        Font::Regular,
+                        BODY_SIZE,
+                    )
+                    .centered(),
+                );
+                1
+            };
+
+            for thread in threads {
+                document.new_page(true);
+                document.bookmark(&thread.post.subject, thread_level);
+                thread.pdf_title(&mut document, options, false);
+                thread.pdf_content(&mut document, options);
+            }
+        }
+
+        pdf_copyright_page(&mut document, name, &authors);
+
+        Ok(document.finish(&Metadata {
+            title: name.clone(),
+            author: authors,
+        }))
+    }
+}
+
+/// The icon, with the speaker and the content flowing around it.
+fn pdf_block(document: &mut Document, block: &Block, options: Options) {
+    // Keep the speaker with the start of the content.
+    document.ensure_space(ICON_SIZE);
+
+    let icon_url = block.icon.and_then(|icon| icon.url.clone()).or_else(|| {
+        options
+            .placeholder_icons
+            .then_some(placeholder_url(block.character_id?))
+    });
+    if let Some(url) = icon_url {
+        document.float_image(&url, ICON_SIZE);
+    }
+
+    if let Some(speaker) = block.character_name.or(block.author) {
+        let mut caption = Paragraph::new(speaker, Font::Bold, BODY_SIZE);
+        if let (Some(_), Some(author)) = (block.character_name, block.author) {
+            caption = Paragraph {
+                spans: [
+                    caption.spans,
+                    Paragraph::new(&format!(" ({author})"), Font::Italic, BODY_SIZE).spans,
+                ]
+                .concat(),
+                ..caption
+            };
+        }
+        document.paragraph(&caption);
+    }
+
+    let content = process_content(block.content, options, &HashMap::new());
+    for item in html_to_items(&content, BODY_SIZE) {
+        pdf_item(document, &item);
+    }
+
+    document.clear_float();
+}
+
+fn pdf_item(document: &mut Document, item: &Item) {
+    match item {
+        Item::Paragraph(paragraph) => document.paragraph(paragraph),
+        Item::Rule => document.rule(),
+        Item::Image(url) => {
+            if !document.image(url) {
+                log::debug!("Image {url} is not in the pdf");
+            }
+        }
+    }
+}
+
+fn pdf_copyright_page(document: &mut Document, title: &str, authors: &str) {
+    document.new_page(true);
+    document.bookmark("Copyright", 0);
+    document.vertical_space(160.0);
+    document.paragraph(&Paragraph::new("This was", Font::Italic, 12.0).centered());
+    document.paragraph(&Paragraph::new(title, Font::Bold, 18.0).centered());
+    document.paragraph(&Paragraph::new(&format!("by {authors}"), Font::Italic, 12.0).centered());
+    document.vertical_space(12.0);
+    document
+        .paragraph(&Paragraph::new(&format!("© {authors}"), Font::Regular, BODY_SIZE).centered());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_copyright_page_is_bookmarked() {
+        let mut document = Document::new(HashMap::new());
+        pdf_copyright_page(&mut document, "Жизнь", "alice");
+        let pdf = document.finish(&Metadata {
+            title: "Жизнь".to_string(),
+            author: "alice".to_string(),
+        });
+
+        let pdf = String::from_utf8_lossy(&pdf);
+        assert!(pdf.contains("/Title (Copyright)"));
+        // The title needs the embedded font, subset to a few glyphs.
+        assert!(pdf.contains("/BaseFont /GLOWPB+DejaVuSerif-Bold"));
+        assert!(!pdf.contains("DejaVuSerif-Italic"));
+        assert!(pdf.len() < 50_000);
+    }
+}
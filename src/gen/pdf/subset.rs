@@ -0,0 +1,261 @@
+//! Cuts a TrueType font down to the glyphs a document uses.
+//!
+//! The other glyphs are left empty rather than removed, so that the glyph ids stay the same
+//! and can still be used as character codes. Only the tables a pdf reader needs are kept.
+
+use std::collections::BTreeSet;
+
+/// The tables of the font program of a CIDFontType2 font, in the order of their tags.
+const KEPT_TABLES: [&[u8; 4]; 9] = [
+    b"cvt ", b"fpgm", b"glyf", b"head", b"hhea", b"hmtx", b"loca", b"maxp", b"prep",
+];
+
+/// Flags of the components of composite glyphs.
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+/// The font with only the given glyphs, the `.notdef` glyph, and the glyphs they are made of.
+/// [None] if the font can't be read.
+pub(super) fn subset(data: &[u8], glyphs: &BTreeSet<u16>) -> Option<Vec<u8>> {
+    let tables = table_directory(data)?;
+    let table = |tag: &[u8; 4]| {
+        tables
+            .iter()
+            .find(|(t, _)| t == tag)
+            .map(|(_, table)| *table)
+    };
+
+    let head = table(b"head")?;
+    let long_offsets = read_u16(head, 50)? == 1;
+    let glyph_count = usize::from(read_u16(table(b"maxp")?, 4)?);
+    let loca = table(b"loca")?;
+    let glyf = table(b"glyf")?;
+    let glyph_data = |glyph: u16| -> Option<&[u8]> {
+        let i = usize::from(glyph);
+        let (start, end) = if long_offsets {
+            (
+                read_u32(loca, i * 4)? as usize,
+                read_u32(loca, i * 4 + 4)? as usize,
+            )
+        } else {
+            (
+                usize::from(read_u16(loca, i * 2)?) * 2,
+                usize::from(read_u16(loca, i * 2 + 2)?) * 2,
+            )
+        };
+        glyf.get(start..end)
+    };
+
+    // The glyphs used, and the ones composite glyphs are made of.
+    let mut kept = BTreeSet::new();
+    let mut pending: Vec<u16> = [0].into_iter().chain(glyphs.iter().copied()).collect();
+    while let Some(glyph) = pending.pop() {
+        if usize::from(glyph) >= glyph_count || !kept.insert(glyph) {
+            continue;
+        }
+        pending.extend(components(glyph_data(glyph)?)?);
+    }
+
+    // Always with long offsets, so that `head` only needs one change.
+    let mut new_glyf = vec![];
+    let mut new_loca = vec![];
+    for glyph in 0..glyph_count as u16 {
+        new_loca.extend((new_glyf.len() as u32).to_be_bytes());
+        if kept.contains(&glyph) {
+            new_glyf.extend(glyph_data(glyph)?);
+            new_glyf.resize(new_glyf.len().next_multiple_of(4), 0);
+        }
+    }
+    new_loca.extend((new_glyf.len() as u32).to_be_bytes());
+
+    let mut new_head = head.to_vec();
+    new_head.get_mut(8..12)?.fill(0); // The checksum adjustment, set below.
+    new_head
+        .get_mut(50..52)?
+        .copy_from_slice(&1u16.to_be_bytes());
+
+    let new_tables: Vec<(&[u8; 4], &[u8])> = KEPT_TABLES
+        .iter()
+        .filter_map(|&tag| match tag {
+            b"glyf" => Some((tag, &new_glyf[..])),
+            b"loca" => Some((tag, &new_loca[..])),
+            b"head" => Some((tag, &new_head[..])),
+            _ => Some((tag, table(tag)?)),
+        })
+        .collect();
+
+    let mut font = write_font(&new_tables);
+    let adjustment = 0xB1B0_AFBA_u32.wrapping_sub(checksum(&font));
+    let head_offset = font_table_offset(&font, b"head")?;
+    font[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+
+    Some(font)
+}
+
+/// The tables of the font, by their tag. Collections (`.ttc` files) aren't supported.
+fn table_directory(data: &[u8]) -> Option<Vec<([u8; 4], &[u8])>> {
+    let count = usize::from(read_u16(data, 4)?);
+    (0..count)
+        .map(|i| {
+            let record = 12 + i * 16;
+            let tag: [u8; 4] = data.get(record..record + 4)?.try_into().ok()?;
+            let offset = read_u32(data, record + 8)? as usize;
+            let length = read_u32(data, record + 12)? as usize;
+            Some((tag, data.get(offset..offset + length)?))
+        })
+        .collect()
+}
+
+/// The glyphs a composite glyph is made of, and none for a simple glyph.
+fn components(glyph: &[u8]) -> Option<Vec<u16>> {
+    // Empty glyphs, like spaces, have no data at all.
+    if glyph.is_empty() || read_u16(glyph, 0)? as i16 >= 0 {
+        return Some(vec![]);
+    }
+
+    let mut components = vec![];
+    let mut offset = 10;
+    loop {
+        let flags = read_u16(glyph, offset)?;
+        components.push(read_u16(glyph, offset + 2)?);
+        offset += 4;
+        offset += if flags & ARG_1_AND_2_ARE_WORDS != 0 {
+            4
+        } else {
+            2
+        };
+        offset += if flags & WE_HAVE_A_SCALE != 0 {
+            2
+        } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+            4
+        } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+            8
+        } else {
+            0
+        };
+        if flags & MORE_COMPONENTS == 0 {
+            break Some(components);
+        }
+    }
+}
+
+/// A font file with the tables, which must be sorted by their tags.
+fn write_font(tables: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let count = tables.len() as u16;
+    let entry_selector = count.ilog2() as u16;
+    let search_range: u16 = (1 << entry_selector) * 16;
+
+    let mut font = vec![];
+    font.extend(0x0001_0000_u32.to_be_bytes());
+    font.extend(count.to_be_bytes());
+    font.extend(search_range.to_be_bytes());
+    font.extend(entry_selector.to_be_bytes());
+    font.extend((count * 16 - search_range).to_be_bytes());
+
+    let mut offset = 12 + tables.len() * 16;
+    for (tag, table) in tables {
+        font.extend(*tag);
+        font.extend(checksum(table).to_be_bytes());
+        font.extend((offset as u32).to_be_bytes());
+        font.extend((table.len() as u32).to_be_bytes());
+        offset += table.len().next_multiple_of(4);
+    }
+    for (_, table) in tables {
+        font.extend(*table);
+        font.resize(font.len().next_multiple_of(4), 0);
+    }
+
+    font
+}
+
+fn font_table_offset(font: &[u8], tag: &[u8; 4]) -> Option<usize> {
+    let count = usize::from(read_u16(font, 4)?);
+    (0..count)
+        .map(|i| 12 + i * 16)
+        .find(|&record| font.get(record..record + 4) == Some(&tag[..]))
+        .and_then(|record| read_u32(font, record + 8))
+        .map(|offset| offset as usize)
+}
+
+/// The sum of the data as big-endian u32s, padded with zeros.
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0, |sum, chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FONT: &[u8] = include_bytes!("../../../fonts/DejaVuSerif.ttf");
+
+    fn glyph(face: &ttf_parser::Face, c: char) -> ttf_parser::GlyphId {
+        face.glyph_index(c).unwrap()
+    }
+
+    fn has_outline(face: &ttf_parser::Face, glyph: ttf_parser::GlyphId) -> bool {
+        struct Ignore;
+        impl ttf_parser::OutlineBuilder for Ignore {
+            fn move_to(&mut self, _: f32, _: f32) {}
+            fn line_to(&mut self, _: f32, _: f32) {}
+            fn quad_to(&mut self, _: f32, _: f32, _: f32, _: f32) {}
+            fn curve_to(&mut self, _: f32, _: f32, _: f32, _: f32, _: f32, _: f32) {}
+            fn close(&mut self) {}
+        }
+        face.outline_glyph(glyph, &mut Ignore).is_some()
+    }
+
+    #[test]
+    fn only_the_used_glyphs_are_kept() {
+        let original = ttf_parser::Face::parse(FONT, 0).unwrap();
+        let used = BTreeSet::from([glyph(&original, 'Ж').0, glyph(&original, 'Ё').0]);
+
+        let data = subset(FONT, &used).unwrap();
+        assert!(data.len() < FONT.len() / 4);
+
+        let face = ttf_parser::Face::parse(&data, 0).unwrap();
+        assert_eq!(face.number_of_glyphs(), original.number_of_glyphs());
+        // The glyph ids stay the same, and composite glyphs keep what they are made of.
+        assert!(has_outline(&face, glyph(&original, 'Ж')));
+        assert!(has_outline(&face, glyph(&original, 'Ё')));
+        assert!(has_outline(&face, ttf_parser::GlyphId(0)));
+        assert!(!has_outline(&face, glyph(&original, 'Z')));
+        assert_eq!(
+            face.glyph_hor_advance(glyph(&original, 'Z')),
+            original.glyph_hor_advance(glyph(&original, 'Z'))
+        );
+    }
+
+    #[test]
+    fn the_font_checksum_is_adjusted() {
+        let data = subset(FONT, &BTreeSet::from([1, 2, 3])).unwrap();
+        assert_eq!(checksum(&data), 0xB1B0_AFBA);
+
+        let tables = table_directory(&data).unwrap();
+        let tags: Vec<&[u8]> = tables.iter().map(|(tag, _)| &tag[..]).collect();
+        assert_eq!(tags, KEPT_TABLES.map(|tag| &tag[..]));
+    }
+
+    #[test]
+    fn unreadable_fonts_are_not_subset() {
+        assert!(subset(b"not a font", &BTreeSet::new()).is_none());
+        assert!(subset(&FONT[..1000], &BTreeSet::new()).is_none());
+    }
+}
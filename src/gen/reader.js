@@ -0,0 +1,270 @@
+// Optional reader features for the html output, see `Options::interactive_html`.
+// Everything is keyed on the `post-id`, `board-id`, and `reply-id` attributes of the generated content.
+(function () {
+    "use strict";
+
+    var COLLAPSE_HEIGHT = 800; // px
+    var storage = (function () {
+        try {
+            var key = "glowpub-test";
+            window.localStorage.setItem(key, key);
+            window.localStorage.removeItem(key);
+            return window.localStorage;
+        } catch (e) {
+            return null;
+        }
+    })();
+
+    function pageKey() {
+        var board = document.querySelector("h1[board-id]");
+        if (board) {
+            return "board-" + board.getAttribute("board-id");
+        }
+        var post = document.querySelector("h1[post-id]");
+        if (post) {
+            return "post-" + post.getAttribute("post-id");
+        }
+        return null;
+    }
+
+    function blockKey(block) {
+        var replyId = block.getAttribute("reply-id");
+        if (replyId) {
+            return "reply-" + replyId;
+        }
+        var index = Array.prototype.indexOf.call(blocks(), block);
+        return "block-" + index;
+    }
+
+    function findBlock(key) {
+        var match = /^reply-(\d+)$/.exec(key);
+        if (match) {
+            return document.querySelector('.content-block[reply-id="' + match[1] + '"]');
+        }
+        match = /^block-(\d+)$/.exec(key);
+        if (match) {
+            return blocks()[Number(match[1])] || null;
+        }
+        return null;
+    }
+
+    function blocks() {
+        return document.querySelectorAll(".content-block");
+    }
+
+    // The `<article>` of the thread being read on board pages, or the whole page of a thread.
+    function currentThread() {
+        var articles = document.querySelectorAll("article");
+        for (var i = 0; i < articles.length; i++) {
+            if (articles[i].getBoundingClientRect().bottom > 0) {
+                return articles[i];
+            }
+        }
+        return articles.length ? articles[articles.length - 1] : document;
+    }
+
+    function scrollToBlock(block) {
+        if (block.classList.contains("collapsed")) {
+            expand(block);
+        }
+        block.scrollIntoView();
+    }
+
+    // Theme
+
+    function setTheme(theme) {
+        document.documentElement.setAttribute("data-theme", theme);
+    }
+
+    function toggleTheme() {
+        var current = document.documentElement.getAttribute("data-theme");
+        var theme = current === "dark" ? "light" : "dark";
+        setTheme(theme);
+        if (storage) {
+            storage.setItem("glowpub-theme", theme);
+        }
+    }
+
+    // Table of contents
+
+    function buildToc() {
+        var toc = document.createElement("nav");
+        toc.className = "reader-toc";
+        toc.hidden = true;
+
+        var list = document.createElement("ol");
+        var headings = document.querySelectorAll(".title-page h1[post-id]");
+        Array.prototype.forEach.call(headings, function (heading) {
+            var item = document.createElement("li");
+            var link = document.createElement("a");
+            link.textContent = heading.textContent;
+            link.href = "#";
+            link.addEventListener("click", function (event) {
+                event.preventDefault();
+                toc.hidden = true;
+                heading.scrollIntoView();
+            });
+            item.appendChild(link);
+            list.appendChild(item);
+        });
+        toc.appendChild(list);
+
+        return toc;
+    }
+
+    // Jump to reply
+
+    function jumpTo(value) {
+        value = value.trim();
+        if (!value) {
+            return;
+        }
+        var block = null;
+        // `#12345` is a reply id, a plain number counts the replies of the current thread
+        // (1 is the first reply, 0 the post).
+        if (value.charAt(0) === "#") {
+            block = findBlock("reply-" + value.slice(1).trim());
+        } else {
+            var number = Number(value);
+            var all = currentThread().querySelectorAll(".content-block");
+            if (Number.isInteger(number) && number >= 0 && number < all.length) {
+                block = all[number];
+            }
+        }
+        if (block) {
+            scrollToBlock(block);
+        }
+    }
+
+    // Collapsible replies
+
+    function expand(block) {
+        block.classList.remove("collapsed");
+        var button = block.nextElementSibling;
+        if (button && button.classList.contains("reader-expand")) {
+            button.textContent = "Show less";
+        }
+    }
+
+    function collapseLongReplies() {
+        Array.prototype.forEach.call(blocks(), function (block) {
+            if (block.scrollHeight <= COLLAPSE_HEIGHT) {
+                return;
+            }
+            block.classList.add("collapsed");
+
+            var button = document.createElement("button");
+            button.className = "reader-expand";
+            button.type = "button";
+            button.textContent = "Show more";
+            button.addEventListener("click", function () {
+                if (block.classList.contains("collapsed")) {
+                    expand(block);
+                } else {
+                    block.classList.add("collapsed");
+                    button.textContent = "Show more";
+                }
+            });
+            block.parentNode.insertBefore(button, block.nextSibling);
+        });
+    }
+
+    // Reading position
+
+    function currentBlock() {
+        var all = blocks();
+        for (var i = 0; i < all.length; i++) {
+            if (all[i].getBoundingClientRect().bottom > 0) {
+                return all[i];
+            }
+        }
+        return null;
+    }
+
+    function trackPosition(key) {
+        var pending = false;
+        window.addEventListener("scroll", function () {
+            if (pending) {
+                return;
+            }
+            pending = true;
+            window.setTimeout(function () {
+                pending = false;
+                var block = currentBlock();
+                if (block) {
+                    storage.setItem("glowpub-position-" + key, blockKey(block));
+                }
+            }, 500);
+        });
+    }
+
+    function restorePosition(key) {
+        var hash = window.location.hash.replace(/^#/, "");
+        var target = hash ? findBlock(hash) : null;
+        if (!target && !hash) {
+            var saved = storage.getItem("glowpub-position-" + key);
+            target = saved ? findBlock(saved) : null;
+        }
+        if (target) {
+            scrollToBlock(target);
+        }
+    }
+
+    // Setup
+
+    function controls(toc) {
+        var container = document.createElement("div");
+        container.className = "reader-controls";
+
+        var tocButton = document.createElement("button");
+        tocButton.type = "button";
+        tocButton.textContent = "Contents";
+        tocButton.addEventListener("click", function () {
+            toc.hidden = !toc.hidden;
+        });
+
+        var jump = document.createElement("input");
+        jump.type = "text";
+        jump.placeholder = "Reply #";
+        jump.title = "Jump to a reply of this thread by number (0 is the post), or by id with a # (like #12345).";
+        jump.addEventListener("keydown", function (event) {
+            if (event.key === "Enter") {
+                jumpTo(jump.value);
+            }
+        });
+
+        var themeButton = document.createElement("button");
+        themeButton.type = "button";
+        themeButton.textContent = "Theme";
+        themeButton.addEventListener("click", toggleTheme);
+
+        container.appendChild(tocButton);
+        container.appendChild(jump);
+        container.appendChild(themeButton);
+        return container;
+    }
+
+    function init() {
+        var savedTheme = storage && storage.getItem("glowpub-theme");
+        var prefersDark = window.matchMedia && window.matchMedia("(prefers-color-scheme: dark)").matches;
+        setTheme(savedTheme || (prefersDark ? "dark" : "light"));
+
+        var toc = buildToc();
+        document.body.appendChild(controls(toc));
+        document.body.appendChild(toc);
+
+        collapseLongReplies();
+
+        var key = pageKey();
+        if (storage && key) {
+            restorePosition(key);
+            trackPosition(key);
+        }
+    }
+
+    if (document.readyState === "loading") {
+        document.addEventListener("DOMContentLoaded", init);
+    } else {
+        init();
+    }
+})();
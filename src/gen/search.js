@@ -0,0 +1,174 @@
+// Search for the html site, see `search.rs` for the format of the index.
+// Every word of the query has to match, and the last one also matches as a prefix, so results update while typing.
+(function () {
+    "use strict";
+
+    var INDEX_VERSION = 1;
+    var MAX_RESULTS = 200;
+
+    var index = window.GLOWPUB_SEARCH_INDEX;
+    var input = document.querySelector(".search-input");
+    var status = document.querySelector(".search-status");
+    var results = document.querySelector(".search-results");
+
+    if (!index || index.version !== INDEX_VERSION) {
+        status.textContent = "The search index could not be loaded.";
+        return;
+    }
+
+    var words = Object.keys(index.words).sort();
+    // Without a prototype, so that words like "constructor" are not found on every object.
+    var decoded = Object.create(null);
+
+    function isWord(word) {
+        return Object.prototype.hasOwnProperty.call(index.words, word);
+    }
+
+    function splitWords(text) {
+        return text
+            .toLowerCase()
+            .split(/[^\p{L}\p{M}\p{N}]+/u)
+            .filter(function (word) {
+                return Array.from(word).length >= 2;
+            });
+    }
+
+    // The postings are delta-encoded, decode them on first use.
+    function docsFor(word) {
+        if (!isWord(word)) {
+            return [];
+        }
+        if (!decoded[word]) {
+            var docs = [];
+            var previous = 0;
+            index.words[word].forEach(function (delta) {
+                previous += delta;
+                docs.push(previous);
+            });
+            decoded[word] = docs;
+        }
+        return decoded[word];
+    }
+
+    function prefixDocs(prefix) {
+        var docs = [];
+        var start = lowerBound(prefix);
+        for (var i = start; i < words.length && words[i].indexOf(prefix) === 0; i++) {
+            docs = docs.concat(docsFor(words[i]));
+        }
+        return unique(docs);
+    }
+
+    function lowerBound(word) {
+        var low = 0;
+        var high = words.length;
+        while (low < high) {
+            var mid = (low + high) >> 1;
+            if (words[mid] < word) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        return low;
+    }
+
+    function unique(docs) {
+        docs.sort(function (a, b) {
+            return a - b;
+        });
+        return docs.filter(function (doc, i) {
+            return i === 0 || docs[i - 1] !== doc;
+        });
+    }
+
+    function intersect(a, b) {
+        var result = [];
+        var i = 0;
+        var j = 0;
+        while (i < a.length && j < b.length) {
+            if (a[i] === b[j]) {
+                result.push(a[i]);
+                i++;
+                j++;
+            } else if (a[i] < b[j]) {
+                i++;
+            } else {
+                j++;
+            }
+        }
+        return result;
+    }
+
+    function search(query) {
+        var terms = splitWords(query);
+        if (terms.length === 0) {
+            return null;
+        }
+        var last = terms.pop();
+        var docs = prefixDocs(last);
+        terms.forEach(function (term) {
+            docs = intersect(docs, docsFor(term));
+        });
+        return docs;
+    }
+
+    function resultItem(doc) {
+        var entry = index.docs[doc];
+        var thread = index.threads[entry[0]];
+        var replyId = entry[1];
+        var speaker = entry[2];
+
+        var link = document.createElement("a");
+        link.href = replyId === null ? thread[2] : thread[2] + "#reply-" + replyId;
+        link.textContent = thread[1];
+
+        var item = document.createElement("li");
+        item.appendChild(link);
+        if (speaker) {
+            item.appendChild(document.createTextNode(" — " + speaker));
+        }
+        return item;
+    }
+
+    function update() {
+        var query = input.value;
+        var docs = search(query);
+        results.textContent = "";
+
+        if (docs === null) {
+            status.textContent = "";
+            return;
+        }
+        status.textContent =
+            docs.length === 0
+                ? "No results."
+                : docs.length > MAX_RESULTS
+                ? "Showing the first " + MAX_RESULTS + " of " + docs.length + " results."
+                : docs.length + (docs.length === 1 ? " result." : " results.");
+
+        docs.slice(0, MAX_RESULTS).forEach(function (doc) {
+            results.appendChild(resultItem(doc));
+        });
+
+        // Keep the query in the url so going back to the results works.
+        try {
+            var url = query ? "?q=" + encodeURIComponent(query) : window.location.pathname;
+            window.history.replaceState(null, "", url);
+        } catch (e) {
+            // Some browsers don't allow this for pages opened from the file system.
+        }
+    }
+
+    input.addEventListener("input", update);
+    input.form.addEventListener("submit", function (event) {
+        event.preventDefault();
+        update();
+    });
+
+    var match = /[?&]q=([^&]*)/.exec(window.location.search);
+    if (match) {
+        input.value = decodeURIComponent(match[1].replace(/\+/g, " "));
+        update();
+    }
+})();
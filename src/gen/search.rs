@@ -0,0 +1,117 @@
+//! The search index for the html site, see [crate::types::Continuity::to_html_site].
+
+use std::{collections::BTreeMap, sync::OnceLock};
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::types::Thread;
+
+use super::transform;
+
+pub(super) const SEARCH_SCRIPT: &str = include_str!("search.js");
+
+/// Bumped whenever the layout of the index changes, so that `search.js` can refuse stale indexes.
+const SEARCH_INDEX_VERSION: u32 = 1;
+
+/// The search index, in the format `search.js` expects.
+#[derive(Debug, Serialize)]
+struct SearchIndex<'a> {
+    version: u32,
+    /// `[post id, subject, page path]` for each thread.
+    threads: Vec<(u64, &'a str, String)>,
+    /// `[index in threads, reply id, speaker]` for each content block.
+    /// The reply id is `null` for the post itself.
+    docs: Vec<(usize, Option<u64>, &'a str)>,
+    /// The indexes in `docs` of the blocks containing each word, delta-encoded.
+    words: BTreeMap<String, Vec<usize>>,
+}
+
+/// Builds a script defining `window.GLOWPUB_SEARCH_INDEX`.
+///
+/// A script is used rather than plain json so that the site also works from the file system,
+/// where browsers don't allow fetching other files.
+pub(super) fn search_index_script(
+    threads: &[&Thread],
+    page_path: impl Fn(&Thread) -> String,
+) -> String {
+    let mut index = SearchIndex {
+        version: SEARCH_INDEX_VERSION,
+        threads: vec![],
+        docs: vec![],
+        words: BTreeMap::new(),
+    };
+
+    for (thread_index, thread) in threads.iter().enumerate() {
+        index
+            .threads
+            .push((thread.post.id, &thread.post.subject, page_path(thread)));
+
+        for block in thread.blocks() {
+            let doc = index.docs.len();
+            let speaker = block.character_name.or(block.author).unwrap_or_default();
+            index.docs.push((thread_index, block.reply_id, speaker));
+
+            let text = transform::html_to_text(block.content);
+            let mut words: Vec<String> = [block.character_name, block.author, Some(&*text)]
+                .into_iter()
+                .flatten()
+                .flat_map(words)
+                .collect();
+            words.sort_unstable();
+            words.dedup();
+
+            for word in words {
+                index.words.entry(word).or_default().push(doc);
+            }
+        }
+    }
+
+    for docs in index.words.values_mut() {
+        let mut previous = 0;
+        for doc in docs.iter_mut() {
+            (*doc, previous) = (*doc - previous, *doc);
+        }
+    }
+
+    let json = serde_json::to_string(&index).unwrap();
+    format!("window.GLOWPUB_SEARCH_INDEX = {json};\n")
+}
+
+/// Splits the text into lowercase words, the same way `search.js` splits queries:
+/// letters, combining marks, and numbers, so that accents stay in their word.
+fn words(text: &str) -> Vec<String> {
+    static WORD: OnceLock<Regex> = OnceLock::new();
+    let word = WORD.get_or_init(|| Regex::new(r"[\p{L}\p{M}\p{N}]+").unwrap());
+
+    word.find_iter(&text.to_lowercase())
+        .map(|word| word.as_str())
+        .filter(|word| word.chars().count() >= 2)
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_are_lowercase_and_at_least_two_characters() {
+        assert_eq!(
+            words("Hello, WORLD! I'm 42 x-ray"),
+            ["hello", "world", "42", "ray"]
+        );
+    }
+
+    #[test]
+    fn accents_stay_in_their_word() {
+        // Precomposed, then with combining marks.
+        assert_eq!(words("Café déjà-vu"), ["café", "déjà", "vu"]);
+        assert_eq!(
+            words("Cafe\u{301} de\u{301}ja\u{300}"),
+            ["cafe\u{301}", "de\u{301}ja\u{300}"]
+        );
+        // Vowel signs are marks too.
+        assert_eq!(words("हिन्दी भाषा"), ["हिन्दी", "भाषा"]);
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+
+use crate::types::{Continuity, Section};
+
+use super::{plain_author_names, process_content, transform, Options, Thread, SECTIONLESS_NAME};
+
+/// Lines are wrapped to this many columns.
+const TEXT_WIDTH: usize = 80;
+/// Goes between the title page and each post or reply, like the `<hr>` in other formats.
+const SEPARATOR: &str = "\n\n* * *\n\n";
+
+impl Thread {
+    /// The thread as plain text, with each post and reply starting with a `Character (Author):` line.
+    pub fn to_text(&self, options: Options) -> String {
+        let mut parts = vec![self.text_title_page(options, '=')];
+        parts.extend(self.text_blocks(options));
+
+        parts.join(SEPARATOR) + "\n"
+    }
+
+    /// The subject is underlined with `underline`, so that it can be nested under other headings.
+    fn text_title_page(&self, options: Options, underline: char) -> String {
+        let post = &self.post;
+        let reply_count = self.replies.len();
+
+        let mut lines = heading(&post.subject, underline);
+        lines.push(String::new());
+        lines.push(format!("by {}", plain_author_names(&post.authors)));
+        lines.push(format!("in {}", post.board.name));
+        lines.push(format!(
+            "[Status: {}] https://glowfic.com/posts/{}",
+            post.status, post.id
+        ));
+        lines.push(format!("[{reply_count} replies]"));
+
+        if let Some(description) = &post.description {
+            let description = text_content(description, options);
+            if !description.is_empty() {
+                lines.push(String::new());
+                lines.push(description);
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    fn text_blocks(&self, options: Options) -> Vec<String> {
+        self.blocks()
+            .map(|block| {
+                let content = text_content(block.content, options);
+                match block.speaker() {
+                    Some(speaker) => format!("{speaker}:\n{content}"),
+                    None => content,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Continuity {
+    /// The entire board as plain text, with a heading for each section and thread.
+    pub fn to_text(&self, options: Options) -> String {
+        let board = &self.board;
+        let authors = self.authors();
+        let thread_count = self.threads.len();
+
+        let mut title = vec!["=".repeat(board.name.chars().count())];
+        title.extend(heading(&board.name, '='));
+        title.push(String::new());
+        title.push(format!("by {}", plain_author_names(&authors)));
+        title.push(format!("[{thread_count} threads]"));
+
+        let mut parts = vec![title.join("\n")];
+
+        let (sections, sectionless_threads) = self.sections();
+        let section_iter = sections
+            .iter()
+            .map(|(section, threads)| (Some(section), threads))
+            .chain((!sectionless_threads.is_empty()).then_some((None, &sectionless_threads)));
+        for (section, threads) in section_iter {
+            let name: &str = match section {
+                Some(Section { name, .. }) => name,
+                None => SECTIONLESS_NAME,
+            };
+            parts.push(heading(name, '=').join("\n"));
+
+            for thread in threads {
+                let mut thread_parts = vec![thread.text_title_page(options, '-')];
+                thread_parts.extend(thread.text_blocks(options));
+                parts.push(thread_parts.join(SEPARATOR));
+            }
+        }
+
+        parts.join("\n\n\n") + "\n"
+    }
+}
+
+/// The text, underlined.
+fn heading(text: &str, underline: char) -> Vec<String> {
+    let width = text.chars().count();
+    vec![text.to_string(), underline.to_string().repeat(width)]
+}
+
+/// The processed content as wrapped text.
+fn text_content(content: &str, options: Options) -> String {
+    let content = process_content(content, options, &HashMap::new());
+    let text = transform::html_to_text(&content);
+
+    let lines: Vec<String> = text.lines().map(wrap).collect();
+    lines.join("\n")
+}
+
+/// Wraps the line, indenting the continuation lines of list items.
+fn wrap(line: &str) -> String {
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let marker_width = if line.starts_with("- ") {
+        2
+    } else if digits > 0 && line[digits..].starts_with(". ") {
+        digits + 2
+    } else {
+        0
+    };
+
+    let indent = " ".repeat(marker_width);
+    let options = textwrap::Options::new(TEXT_WIDTH).subsequent_indent(&indent);
+    textwrap::fill(line, options)
+}
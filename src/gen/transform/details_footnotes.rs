@@ -0,0 +1,185 @@
+use html5ever::{
+    interface::{ElementFlags, NodeOrText},
+    serialize::{SerializeOpts, TraversalScope},
+    tree_builder::TreeSink,
+    Attribute,
+};
+use markup5ever::{LocalName, Namespace, QualName};
+use markup5ever_rcdom::{Handle, NodeData, RcDom, SerializableHandle};
+
+use super::html_to_xml::parse_html;
+
+/// Replaces each `<details>` with a link showing its summary, and moves the rest of its content
+/// to a note at the end, so that readers with pop-up footnotes keep it hidden until the link is tapped.
+///
+/// Details in details become notes too, listed before the note that links to them,
+/// as notes can't be nested. The notes have ids, so this needs to run after sanitization,
+/// on a whole page.
+pub fn details_to_footnotes(content: &str) -> String {
+    let mut dom = parse_html(content);
+    let Some(root) = dom.document.children.borrow().first().cloned() else {
+        return content.to_string();
+    };
+
+    let mut notes = vec![];
+    walk(&mut dom, &root, &mut notes);
+    if notes.is_empty() {
+        return content.to_string();
+    }
+
+    let container = element(&mut dom, "div", &[("class", "details-footnotes")]);
+    let rule = element(&mut dom, "hr", &[]);
+    dom.append(&container, NodeOrText::AppendNode(rule));
+    for note in notes {
+        dom.append(&container, NodeOrText::AppendNode(note));
+    }
+    dom.append(&root, NodeOrText::AppendNode(container));
+
+    serialize_html(&root)
+}
+
+fn walk(dom: &mut RcDom, node: &Handle, notes: &mut Vec<Handle>) {
+    let children: Vec<Handle> = node.children.borrow().clone();
+    for child in children {
+        // The details inside are turned into notes first, so that their links move with the content.
+        walk(dom, &child, notes);
+        if is_element(&child, "details") {
+            let note = to_footnote(dom, &child, notes.len() + 1);
+            notes.push(note);
+        }
+    }
+}
+
+/// Replaces the details with the link to its note, and returns the note.
+fn to_footnote(dom: &mut RcDom, details: &Handle, n: usize) -> Handle {
+    let id = format!("details-{n}");
+    let ref_id = format!("details-ref-{n}");
+
+    let summary = details
+        .children
+        .borrow()
+        .iter()
+        .find(|child| is_element(child, "summary"))
+        .cloned();
+
+    let link = element(dom, "a", &[("href", &format!("#{id}")), ("id", &ref_id)]);
+    match &summary {
+        Some(summary) => {
+            dom.reparent_children(summary, &link);
+            dom.remove_from_parent(summary);
+        }
+        None => dom.append(&link, NodeOrText::AppendText("Details".into())),
+    }
+    dom.append(&link, NodeOrText::AppendText(" ▶".into()));
+    let paragraph = element(dom, "p", &[("class", "details-ref")]);
+    dom.append(&paragraph, NodeOrText::AppendNode(link));
+
+    let note = element(dom, "div", &[("class", "details-note"), ("id", &id)]);
+    dom.reparent_children(details, &note);
+    let back = element(dom, "a", &[("href", &format!("#{ref_id}"))]);
+    dom.append(&back, NodeOrText::AppendText("↑".into()));
+    let back_paragraph = element(dom, "p", &[]);
+    dom.append(&back_paragraph, NodeOrText::AppendNode(back));
+    dom.append(&note, NodeOrText::AppendNode(back_paragraph));
+
+    dom.append_before_sibling(details, NodeOrText::AppendNode(paragraph));
+    dom.remove_from_parent(details);
+
+    note
+}
+
+fn is_element(node: &Handle, tag: &str) -> bool {
+    matches!(&node.data, NodeData::Element { name, .. } if &*name.local == tag)
+}
+
+fn element(dom: &mut RcDom, tag: &str, attributes: &[(&str, &str)]) -> Handle {
+    dom.create_element(
+        QualName::new(
+            None,
+            Namespace::from("http://www.w3.org/1999/xhtml"),
+            LocalName::from(tag),
+        ),
+        attributes
+            .iter()
+            .map(|(name, value)| Attribute {
+                name: QualName::new(None, Namespace::from(""), LocalName::from(*name)),
+                value: (*value).into(),
+            })
+            .collect(),
+        ElementFlags::default(),
+    )
+}
+
+/// The content of the node, as html.
+fn serialize_html(node: &Handle) -> String {
+    let node: SerializableHandle = node.clone().into();
+
+    let mut bytes = vec![];
+    html5ever::serialize(
+        &mut bytes,
+        &node,
+        SerializeOpts {
+            traversal_scope: TraversalScope::ChildrenOnly(None),
+            ..SerializeOpts::default()
+        },
+    )
+    .unwrap();
+
+    String::from_utf8(bytes).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn details_become_linked_notes() {
+        assert_eq!(
+            details_to_footnotes(
+                "<p>Before</p><details><summary>Spoiler <em>here</em></summary><p>Hidden</p></details><p>After</p>"
+            ),
+            concat!(
+                "<p>Before</p>",
+                r##"<p class="details-ref"><a href="#details-1" id="details-ref-1">Spoiler <em>here</em> ▶</a></p>"##,
+                "<p>After</p>",
+                r#"<div class="details-footnotes"><hr>"#,
+                r##"<div class="details-note" id="details-1"><p>Hidden</p><p><a href="#details-ref-1">↑</a></p></div>"##,
+                "</div>",
+            )
+        );
+    }
+
+    #[test]
+    fn details_without_a_summary_get_a_default_link() {
+        let notes = details_to_footnotes("<details><p>Hidden</p></details>");
+        assert!(notes.starts_with(
+            r##"<p class="details-ref"><a href="#details-1" id="details-ref-1">Details ▶</a></p>"##
+        ));
+    }
+
+    #[test]
+    fn nested_details_are_notes_linked_from_their_note() {
+        let notes = details_to_footnotes(
+            "<details><summary>Outer</summary><p>One</p><details><summary>Inner</summary><p>Two</p></details></details>",
+        );
+        assert_eq!(
+            notes,
+            concat!(
+                r##"<p class="details-ref"><a href="#details-2" id="details-ref-2">Outer ▶</a></p>"##,
+                r#"<div class="details-footnotes"><hr>"#,
+                r##"<div class="details-note" id="details-1"><p>Two</p><p><a href="#details-ref-1">↑</a></p></div>"##,
+                r##"<div class="details-note" id="details-2"><p>One</p>"##,
+                r##"<p class="details-ref"><a href="#details-1" id="details-ref-1">Inner ▶</a></p>"##,
+                r##"<p><a href="#details-ref-2">↑</a></p></div>"##,
+                "</div>",
+            )
+        );
+    }
+
+    #[test]
+    fn content_without_details_is_unchanged() {
+        let content = "<p>Some <b>text</b> &amp; more</p>";
+        assert_eq!(details_to_footnotes(content), content);
+        assert_eq!(details_to_footnotes(""), "");
+    }
+}
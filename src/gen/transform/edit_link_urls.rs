@@ -0,0 +1,25 @@
+use std::borrow::Cow;
+
+use lol_html::{rewrite_str, ElementContentHandlers, RewriteStrSettings};
+
+/// Edits the links of posts, replies, and descriptions (in a `.content-block` or a `.description`),
+/// but not the ones around them, like the status link of title pages, which should keep leading to glowfic.
+pub fn edit_link_urls(content: &str, mut f: impl FnMut(String) -> String) -> String {
+    rewrite_str(
+        content,
+        RewriteStrSettings {
+            element_content_handlers: vec![(
+                Cow::Owned(".content-block a, .description a".parse().unwrap()),
+                ElementContentHandlers::default().element(|el| {
+                    if let Some(url) = el.get_attribute("href") {
+                        let new_url = f(url);
+                        el.set_attribute("href", &new_url).unwrap();
+                    }
+                    Ok(())
+                }),
+            )],
+            ..RewriteStrSettings::default()
+        },
+    )
+    .unwrap()
+}
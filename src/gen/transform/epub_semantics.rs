@@ -0,0 +1,52 @@
+use std::borrow::Cow;
+
+use lol_html::{rewrite_str, ElementContentHandlers, RewriteStrSettings, Selector};
+
+/// Turns the pages of the book into EPUB3 structure: title and copyright pages, and pages of content,
+/// become `<section>`s and each post or reply an `<article>`, with their `epub:type`.
+/// The footnotes (see [super::add_link_footnotes] and [super::details_to_footnotes]) are marked too,
+/// so that readers can show them as popups.
+///
+/// The resulting document needs the `epub` namespace (`xmlns:epub="http://www.idpf.org/2007/ops"`).
+pub fn add_epub_semantics(content: &str) -> String {
+    let structure = |selector: &str, tag: &'static str, epub_type: &'static str| {
+        (
+            Cow::Owned(selector.parse::<Selector>().unwrap()),
+            ElementContentHandlers::default().element(move |el| {
+                el.set_tag_name(tag)?;
+                if !epub_type.is_empty() {
+                    el.set_attribute("epub:type", epub_type)?;
+                }
+                Ok(())
+            }),
+        )
+    };
+    let semantics = |selector: &str, epub_type: &'static str| {
+        (
+            Cow::Owned(selector.parse::<Selector>().unwrap()),
+            ElementContentHandlers::default().element(move |el| {
+                el.set_attribute("epub:type", epub_type)?;
+                Ok(())
+            }),
+        )
+    };
+
+    rewrite_str(
+        content,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                structure("div.title-page", "section", "titlepage"),
+                structure("div.copyright-page", "section", "copyright-page"),
+                structure("div.content", "section", "chapter"),
+                structure("div.content-block", "article", ""),
+                structure("div.footnotes", "section", "footnotes"),
+                semantics(r#"li[id^="footnote-"]"#, "footnote"),
+                semantics(r#"a[id^="footnote-ref-"]"#, "noteref"),
+                structure("div.details-note", "aside", "footnote"),
+                semantics(r#"a[id^="details-ref-"]"#, "noteref"),
+            ],
+            ..RewriteStrSettings::default()
+        },
+    )
+    .unwrap()
+}
@@ -0,0 +1,423 @@
+use markup5ever_rcdom::{Handle, NodeData};
+
+use super::html_to_xml::parse_html;
+
+/// Converts the html into CommonMark, with GitHub-flavoured tables and strikethrough.
+///
+/// `<details>` are rendered as blockquotes starting with their bold summary,
+/// and anything that can't be represented is reduced to its text.
+pub fn html_to_markdown(content: &str) -> String {
+    let dom = parse_html(content);
+    let children = dom.document.children.borrow();
+
+    // The fragment parser wraps everything in an `<html>` element.
+    let nodes: Vec<Handle> = match &**children {
+        [html] if element_name(html).as_deref() == Some("html") => {
+            html.children.borrow().iter().cloned().collect()
+        }
+        _ => children.iter().cloned().collect(),
+    };
+
+    render_blocks(&nodes).join("\n\n")
+}
+
+fn element_name(node: &Handle) -> Option<String> {
+    match &node.data {
+        NodeData::Element { name, .. } => Some(name.local.to_string()),
+        _ => None,
+    }
+}
+
+fn attribute(node: &Handle, attribute: &str) -> Option<String> {
+    match &node.data {
+        NodeData::Element { attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .find(|attr| &*attr.name.local == attribute)
+            .map(|attr| attr.value.to_string()),
+        _ => None,
+    }
+}
+
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "details",
+    "div",
+    "dl",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "ul",
+];
+
+fn is_block(node: &Handle) -> bool {
+    element_name(node).is_some_and(|name| BLOCK_ELEMENTS.contains(&&*name))
+}
+
+/// Renders the nodes as markdown blocks, which should be separated by blank lines.
+fn render_blocks(nodes: &[Handle]) -> Vec<String> {
+    let mut blocks = vec![];
+    let mut paragraph = String::new();
+
+    let flush = |paragraph: &mut String, blocks: &mut Vec<String>| {
+        let text = escape_block_starts(paragraph.trim());
+        if !text.is_empty() {
+            blocks.push(text);
+        }
+        paragraph.clear();
+    };
+
+    for node in nodes {
+        if !is_block(node) {
+            paragraph.push_str(&render_inline(node));
+            continue;
+        }
+        flush(&mut paragraph, &mut blocks);
+
+        let children: Vec<Handle> = node.children.borrow().iter().cloned().collect();
+        let name = element_name(node).unwrap_or_default();
+        match &*name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = name[1..].parse().unwrap_or(1);
+                let text = single_line(&render_inlines(&children));
+                if !text.is_empty() {
+                    blocks.push(format!("{} {text}", "#".repeat(level)));
+                }
+            }
+            "hr" => blocks.push("---".to_string()),
+            "pre" => {
+                let text = super::html_to_text::node_text(node);
+                let fence = if text.contains("```") { "~~~" } else { "```" };
+                blocks.push(format!("{fence}\n{text}\n{fence}"));
+            }
+            "blockquote" => {
+                let inner = render_blocks(&children);
+                if !inner.is_empty() {
+                    blocks.push(quote(&inner.join("\n\n")));
+                }
+            }
+            "details" => {
+                let mut inner = vec![];
+                for child in &children {
+                    if element_name(child).as_deref() == Some("summary") {
+                        let summary_children: Vec<Handle> =
+                            child.children.borrow().iter().cloned().collect();
+                        let summary = single_line(&render_inlines(&summary_children));
+                        if !summary.is_empty() {
+                            inner.push(format!("**{summary}**"));
+                        }
+                    } else {
+                        inner.extend(render_blocks(std::slice::from_ref(child)));
+                    }
+                }
+                if !inner.is_empty() {
+                    blocks.push(quote(&inner.join("\n\n")));
+                }
+            }
+            "ul" | "ol" => {
+                let list = render_list(node, name == "ol");
+                if !list.is_empty() {
+                    blocks.push(list);
+                }
+            }
+            "table" => {
+                let table = render_table(node);
+                if !table.is_empty() {
+                    blocks.push(table);
+                }
+            }
+            _ => blocks.extend(render_blocks(&children)),
+        }
+    }
+    flush(&mut paragraph, &mut blocks);
+
+    blocks
+}
+
+fn render_list(list: &Handle, ordered: bool) -> String {
+    let start: usize = attribute(list, "start")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(1);
+
+    let items: Vec<Handle> = list
+        .children
+        .borrow()
+        .iter()
+        .filter(|child| element_name(child).as_deref() == Some("li"))
+        .cloned()
+        .collect();
+
+    let rendered: Vec<Vec<String>> = items
+        .iter()
+        .map(|item| render_blocks(&item.children.borrow()))
+        .collect();
+    // A list is loose (with blank lines between items) as soon as one item has several blocks.
+    let loose = rendered.iter().any(|blocks| blocks.len() > 1);
+
+    let items: Vec<String> = rendered
+        .into_iter()
+        .enumerate()
+        .map(|(i, blocks)| {
+            let marker = if ordered {
+                format!("{}. ", start + i)
+            } else {
+                "- ".to_string()
+            };
+            let indent = " ".repeat(marker.len());
+            let content = blocks.join("\n\n");
+
+            let lines: Vec<String> = content
+                .lines()
+                .enumerate()
+                .map(|(j, line)| match (j, line.is_empty()) {
+                    (0, _) => format!("{marker}{line}"),
+                    (_, true) => String::new(),
+                    (_, false) => format!("{indent}{line}"),
+                })
+                .collect();
+            if lines.is_empty() {
+                marker.trim_end().to_string()
+            } else {
+                lines.join("\n")
+            }
+        })
+        .collect();
+
+    items.join(if loose { "\n\n" } else { "\n" })
+}
+
+fn render_table(table: &Handle) -> String {
+    fn collect_rows(node: &Handle, rows: &mut Vec<Handle>) {
+        for child in node.children.borrow().iter() {
+            match element_name(child).as_deref() {
+                Some("tr") => rows.push(child.clone()),
+                Some("thead" | "tbody" | "tfoot") => collect_rows(child, rows),
+                _ => {}
+            }
+        }
+    }
+    let mut table_rows = vec![];
+    collect_rows(table, &mut table_rows);
+
+    let mut cells: Vec<Vec<String>> = table_rows
+        .iter()
+        .map(|row| {
+            row.children
+                .borrow()
+                .iter()
+                .filter(|cell| matches!(element_name(cell).as_deref(), Some("td" | "th")))
+                .map(|cell| {
+                    let children: Vec<Handle> = cell.children.borrow().iter().cloned().collect();
+                    single_line(&render_inlines(&children)).replace('|', "\\|")
+                })
+                .collect()
+        })
+        .collect();
+
+    let width = cells.iter().map(Vec::len).max().unwrap_or_default();
+    if width == 0 {
+        return String::new();
+    }
+    for row in &mut cells {
+        row.resize(width, String::new());
+    }
+
+    let line = |row: &[String]| format!("| {} |", row.join(" | "));
+    let mut lines = vec![line(&cells[0]), line(&vec!["---".to_string(); width])];
+    lines.extend(cells[1..].iter().map(|row| line(row)));
+
+    lines.join("\n")
+}
+
+fn render_inlines(nodes: &[Handle]) -> String {
+    nodes.iter().map(render_inline).collect()
+}
+
+fn render_inline(node: &Handle) -> String {
+    match &node.data {
+        NodeData::Text { contents } => {
+            let contents = contents.borrow();
+            let mut text = String::new();
+            if contents.starts_with(char::is_whitespace) {
+                text.push(' ');
+            }
+            text.push_str(&escape(
+                &contents.split_whitespace().collect::<Vec<_>>().join(" "),
+            ));
+            if contents.ends_with(char::is_whitespace) && !text.ends_with(' ') {
+                text.push(' ');
+            }
+            text
+        }
+        NodeData::Element { name, .. } => {
+            let children: Vec<Handle> = node.children.borrow().iter().cloned().collect();
+            let inner = || render_inlines(&children);
+
+            match &*name.local {
+                "br" => "\\\n".to_string(),
+                "em" | "i" | "cite" => wrap_inline(&inner(), "*"),
+                "strong" | "b" => wrap_inline(&inner(), "**"),
+                "s" | "del" | "strike" => wrap_inline(&inner(), "~~"),
+                "code" => {
+                    let text = super::html_to_text::node_text(node);
+                    let fence = if text.contains('`') { "`` " } else { "`" };
+                    format!("{fence}{text}{}", fence.chars().rev().collect::<String>())
+                }
+                "a" => {
+                    let text = inner();
+                    match attribute(node, "href") {
+                        Some(href) if !text.trim().is_empty() => {
+                            format!("[{}]({})", text.trim(), escape_url(&href))
+                        }
+                        _ => text,
+                    }
+                }
+                "img" => {
+                    let alt = attribute(node, "alt").unwrap_or_default();
+                    match attribute(node, "src") {
+                        Some(src) => format!("![{}]({})", escape(&alt), escape_url(&src)),
+                        None => String::new(),
+                    }
+                }
+                // Block elements nested inside inline ones can't be represented, so they become line breaks.
+                name if BLOCK_ELEMENTS.contains(&name) || name == "li" => {
+                    format!("\\\n{}\\\n", inner().trim())
+                }
+                _ => inner(),
+            }
+        }
+        _ => String::new(),
+    }
+}
+
+/// Emphasis markers must touch the text they wrap, so surrounding whitespace is moved outside of them.
+fn wrap_inline(text: &str, marker: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return text.to_string();
+    }
+    let leading = if text.starts_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+    let trailing = if text.ends_with(char::is_whitespace) {
+        " "
+    } else {
+        ""
+    };
+
+    format!("{leading}{marker}{trimmed}{marker}{trailing}")
+}
+
+fn single_line(text: &str) -> String {
+    text.replace("\\\n", " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn quote(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.is_empty() {
+                ">".to_string()
+            } else {
+                format!("> {line}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '~') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn escape_url(url: &str) -> String {
+    url.replace(' ', "%20")
+        .replace('(', "%28")
+        .replace(')', "%29")
+}
+
+/// Escapes what would otherwise start a heading, a list, a blockquote or a thematic break
+/// at the beginning of each line, since lines after a hard break can start new blocks too.
+fn escape_block_starts(text: &str) -> String {
+    text.split('\n')
+        .map(|line| escape_block_start(line.trim_start()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn escape_block_start(text: &str) -> String {
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    let after_digits = text[digits..].chars().next();
+
+    match text.chars().next() {
+        Some('#' | '-' | '+' | '=' | '|' | '>') => format!("\\{text}"),
+        Some(_) if digits > 0 && matches!(after_digits, Some('.' | ')')) => {
+            format!("{}\\{}", &text[..digits], &text[digits..])
+        }
+        _ => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_starts_are_escaped() {
+        assert_eq!(
+            html_to_markdown("<p># not a heading</p>"),
+            "\\# not a heading"
+        );
+        assert_eq!(html_to_markdown("<p>- not a list</p>"), "\\- not a list");
+        assert_eq!(html_to_markdown("<p>1999. A year</p>"), "1999\\. A year");
+        assert_eq!(html_to_markdown("<p>1) not a list</p>"), "1\\) not a list");
+    }
+
+    #[test]
+    fn block_starts_are_escaped_after_line_breaks() {
+        assert_eq!(
+            html_to_markdown("<p>one<br># two<br>- three<br> 4. four<br>&gt; five</p>"),
+            "one\\\n\\# two\\\n\\- three\\\n4\\. four\\\n\\> five"
+        );
+    }
+
+    #[test]
+    fn other_lines_are_left_alone() {
+        assert_eq!(
+            html_to_markdown("<p>A line<br>and 2 more - or # so</p>"),
+            "A line\\\nand 2 more - or # so"
+        );
+        assert_eq!(
+            html_to_markdown("<p>2024 was a year</p>"),
+            "2024 was a year"
+        );
+    }
+}
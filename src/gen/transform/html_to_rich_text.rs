@@ -0,0 +1,357 @@
+use markup5ever_rcdom::{Handle, NodeData};
+
+use super::html_to_xml::parse_html;
+
+/// How a run of text looks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RunStyle {
+    pub strong: bool,
+    pub emphasis: bool,
+    pub strikethrough: bool,
+    pub code: bool,
+    pub superscript: bool,
+    pub subscript: bool,
+}
+
+/// Text in a single style. Line breaks are kept as `\n`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub text: String,
+    pub style: RunStyle,
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParagraphKind {
+    Normal,
+    /// From `<h1>` to `<h6>`.
+    Heading(u8),
+    /// Like a `<summary>` or a `<dt>`.
+    Label,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RichBlock {
+    Paragraph {
+        runs: Vec<Run>,
+        kind: ParagraphKind,
+        /// Like a list bullet or number.
+        marker: Option<String>,
+        /// How many blockquotes, details, or definitions it is in.
+        quote_depth: usize,
+        /// How many lists it is in.
+        list_depth: usize,
+    },
+    Image {
+        src: String,
+        alt: Option<String>,
+        quote_depth: usize,
+    },
+    /// A horizontal line.
+    Rule,
+    /// Rows of cells.
+    Table(Vec<Vec<Vec<Run>>>),
+}
+
+/// Splits the html into paragraphs of styled runs, images, rules, and tables,
+/// for document formats that don't have the nesting of html.
+pub fn html_to_rich_text(content: &str) -> Vec<RichBlock> {
+    let dom = parse_html(content);
+
+    let mut builder = Builder::default();
+    let context = Context::default();
+    builder.walk(&dom.document, &context);
+    builder.flush(&context, ParagraphKind::Normal);
+
+    builder.blocks
+}
+
+#[derive(Debug, Clone, Default)]
+struct Context {
+    style: RunStyle,
+    link: Option<String>,
+    quote_depth: usize,
+    list_depth: usize,
+}
+
+#[derive(Debug, Default)]
+struct Builder {
+    blocks: Vec<RichBlock>,
+    runs: Vec<Run>,
+    /// For the next paragraph, set by list items.
+    marker: Option<String>,
+    /// Table cells can only hold text, so blocks in them are joined by spaces.
+    in_cell: bool,
+}
+impl Builder {
+    fn walk(&mut self, node: &Handle, context: &Context) {
+        match &node.data {
+            NodeData::Document => self.walk_children(node, context),
+            NodeData::Text { contents } => {
+                let text = collapse_whitespace(&contents.borrow());
+                if !text.is_empty() {
+                    self.push_text(text, context);
+                }
+            }
+            NodeData::Element { name, attrs, .. } => {
+                let attribute = |attribute: &str| {
+                    attrs
+                        .borrow()
+                        .iter()
+                        .find(|attr| &*attr.name.local == attribute)
+                        .map(|attr| attr.value.to_string())
+                };
+                let styled = |change: fn(&mut RunStyle)| {
+                    let mut context = context.clone();
+                    change(&mut context.style);
+                    context
+                };
+
+                let tag = &*name.local;
+                match tag {
+                    "br" => self.push_text("\n".to_string(), context),
+                    "em" | "i" | "cite" | "var" => {
+                        self.walk_children(node, &styled(|s| s.emphasis = true));
+                    }
+                    "strong" | "b" => self.walk_children(node, &styled(|s| s.strong = true)),
+                    "s" | "strike" | "del" => {
+                        self.walk_children(node, &styled(|s| s.strikethrough = true));
+                    }
+                    "code" | "kbd" | "samp" | "tt" => {
+                        self.walk_children(node, &styled(|s| s.code = true));
+                    }
+                    "sup" => self.walk_children(node, &styled(|s| s.superscript = true)),
+                    "sub" => self.walk_children(node, &styled(|s| s.subscript = true)),
+                    "a" => {
+                        let context = Context {
+                            link: attribute("href").or(context.link.clone()),
+                            ..context.clone()
+                        };
+                        self.walk_children(node, &context);
+                    }
+                    "img" => {
+                        let Some(src) = attribute("src") else {
+                            return;
+                        };
+                        let alt = attribute("alt").filter(|alt| !alt.is_empty());
+                        if self.in_cell {
+                            let text = format!("[{}]", alt.as_deref().unwrap_or("image"));
+                            self.push_text(text, context);
+                        } else {
+                            self.flush(context, ParagraphKind::Normal);
+                            self.blocks.push(RichBlock::Image {
+                                src,
+                                alt,
+                                quote_depth: context.quote_depth,
+                            });
+                        }
+                    }
+                    "hr" => {
+                        self.flush(context, ParagraphKind::Normal);
+                        if !self.in_cell {
+                            self.blocks.push(RichBlock::Rule);
+                        }
+                    }
+                    "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                        let level = tag[1..].parse().unwrap();
+                        self.flush(context, ParagraphKind::Normal);
+                        self.walk_children(node, context);
+                        self.flush(context, ParagraphKind::Heading(level));
+                    }
+                    "summary" | "dt" => {
+                        self.flush(context, ParagraphKind::Normal);
+                        self.walk_children(node, context);
+                        self.flush(context, ParagraphKind::Label);
+                    }
+                    "blockquote" | "details" | "dd" => {
+                        self.flush(context, ParagraphKind::Normal);
+                        let context = Context {
+                            quote_depth: context.quote_depth + 1,
+                            ..context.clone()
+                        };
+                        self.walk_children(node, &context);
+                        self.flush(&context, ParagraphKind::Normal);
+                    }
+                    "ul" | "ol" => {
+                        self.flush(context, ParagraphKind::Normal);
+                        let context = Context {
+                            list_depth: context.list_depth + 1,
+                            ..context.clone()
+                        };
+                        let mut number: usize = attribute("start")
+                            .and_then(|start| start.parse().ok())
+                            .unwrap_or(1);
+                        for child in node.children.borrow().iter() {
+                            if matches!(&child.data, NodeData::Element { name, .. } if &*name.local == "li")
+                            {
+                                self.marker = Some(match tag {
+                                    "ol" => format!("{number}."),
+                                    _ => "•".to_string(),
+                                });
+                                number += 1;
+                            }
+                            self.walk(child, &context);
+                            self.flush(&context, ParagraphKind::Normal);
+                        }
+                    }
+                    "table" if !self.in_cell => {
+                        self.flush(context, ParagraphKind::Normal);
+                        let mut rows = vec![];
+                        self.collect_rows(node, context, &mut rows);
+                        if !rows.is_empty() {
+                            self.blocks.push(RichBlock::Table(rows));
+                        }
+                    }
+                    "script" | "style" | "head" => {}
+                    _ if BLOCK_ELEMENTS.contains(&tag) => {
+                        self.flush(context, ParagraphKind::Normal);
+                        self.walk_children(node, context);
+                        self.flush(context, ParagraphKind::Normal);
+                    }
+                    _ => self.walk_children(node, context),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn walk_children(&mut self, node: &Handle, context: &Context) {
+        for child in node.children.borrow().iter() {
+            self.walk(child, context);
+        }
+    }
+
+    /// Adds the cells of the rows in `node` (which can be in a `thead`, `tbody`, or `tfoot`) to `rows`.
+    fn collect_rows(&mut self, node: &Handle, context: &Context, rows: &mut Vec<Vec<Vec<Run>>>) {
+        for child in node.children.borrow().iter() {
+            let NodeData::Element { name, .. } = &child.data else {
+                continue;
+            };
+            match &*name.local {
+                "tr" => {
+                    let mut cells = vec![];
+                    for cell in child.children.borrow().iter() {
+                        let NodeData::Element { name, .. } = &cell.data else {
+                            continue;
+                        };
+                        let context = Context {
+                            style: RunStyle {
+                                strong: context.style.strong || &*name.local == "th",
+                                ..context.style
+                            },
+                            ..context.clone()
+                        };
+                        self.in_cell = true;
+                        self.walk_children(cell, &context);
+                        self.in_cell = false;
+                        cells.push(trim_runs(std::mem::take(&mut self.runs)));
+                    }
+                    rows.push(cells);
+                }
+                "thead" | "tbody" | "tfoot" => self.collect_rows(child, context, rows),
+                _ => {}
+            }
+        }
+    }
+
+    fn push_text(&mut self, text: String, context: &Context) {
+        match self.runs.last_mut() {
+            Some(run) if run.style == context.style && run.link == context.link => {
+                run.text.push_str(&text);
+            }
+            _ => self.runs.push(Run {
+                text,
+                style: context.style,
+                link: context.link.clone(),
+            }),
+        }
+    }
+
+    /// Ends the current paragraph, if it has any text.
+    fn flush(&mut self, context: &Context, kind: ParagraphKind) {
+        if self.in_cell {
+            if !self.runs.is_empty() {
+                self.push_text(" ".to_string(), context);
+            }
+            return;
+        }
+
+        let runs = trim_runs(std::mem::take(&mut self.runs));
+        if runs.is_empty() {
+            return;
+        }
+
+        self.blocks.push(RichBlock::Paragraph {
+            runs,
+            kind,
+            marker: self.marker.take(),
+            quote_depth: context.quote_depth,
+            list_depth: context.list_depth,
+        });
+    }
+}
+
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "dl",
+    "div",
+    "figure",
+    "figcaption",
+    "footer",
+    "header",
+    "li",
+    "nav",
+    "p",
+    "pre",
+    "section",
+    "table",
+];
+
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut last_was_space = false;
+    for c in text.chars() {
+        if c.is_whitespace() && c != '\u{a0}' {
+            if !last_was_space {
+                collapsed.push(' ');
+            }
+            last_was_space = true;
+        } else {
+            collapsed.push(c);
+            last_was_space = false;
+        }
+    }
+    collapsed
+}
+
+/// Removes the spaces at the start and the end of the runs (and of each line),
+/// and the runs left empty.
+fn trim_runs(runs: Vec<Run>) -> Vec<Run> {
+    let mut runs: Vec<Run> = runs
+        .into_iter()
+        .map(|run| Run {
+            text: run.text.replace(" \n", "\n").replace("\n ", "\n"),
+            ..run
+        })
+        .collect();
+
+    while let Some(first) = runs.first_mut() {
+        first.text = first.text.trim_start().to_string();
+        if !first.text.is_empty() {
+            break;
+        }
+        runs.remove(0);
+    }
+    while let Some(last) = runs.last_mut() {
+        last.text = last.text.trim_end().to_string();
+        if !last.text.is_empty() {
+            break;
+        }
+        runs.pop();
+    }
+    runs.retain(|run| !run.text.is_empty());
+
+    runs
+}
@@ -0,0 +1,153 @@
+use markup5ever_rcdom::{Handle, NodeData};
+
+use super::html_to_xml::parse_html;
+
+/// Extracts the text from the html.
+///
+/// Whitespace is collapsed like a browser would, blocks like paragraphs are separated by blank lines,
+/// list items start with `- ` or their number, and table cells are separated by tabs.
+pub fn html_to_text(content: &str) -> String {
+    html_to_text_with_details(content, true, true)
+}
+
+/// Like [html_to_text], but the summaries and the rest of `<details>` can be left out.
+pub fn html_to_text_with_details(content: &str, summaries: bool, details_content: bool) -> String {
+    let dom = parse_html(content);
+
+    let mut text = String::new();
+    let details = DetailsText {
+        summaries,
+        details_content,
+    };
+    push_text(&dom.document, &mut text, details);
+
+    let mut lines: Vec<&str> = vec![];
+    for line in text.lines().map(str::trim) {
+        let previous_empty = lines.last().is_none_or(|l| l.is_empty());
+        if !line.is_empty() || !previous_empty {
+            lines.push(line);
+        }
+    }
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+
+    lines.join("\n")
+}
+
+/// The raw text of the node, with its whitespace untouched.
+pub(super) fn node_text(node: &Handle) -> String {
+    let mut text = String::new();
+    if let NodeData::Text { contents } = &node.data {
+        text.push_str(&contents.borrow());
+    }
+    for child in node.children.borrow().iter() {
+        text.push_str(&node_text(child));
+    }
+    text
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DetailsText {
+    summaries: bool,
+    details_content: bool,
+}
+impl DetailsText {
+    /// Whether this child of a `<details>` is kept.
+    fn includes(self, child: &Handle) -> bool {
+        match &child.data {
+            NodeData::Element { name, .. } if &*name.local == "summary" => self.summaries,
+            _ => self.details_content,
+        }
+    }
+}
+
+fn push_text(node: &Handle, text: &mut String, details: DetailsText) {
+    match &node.data {
+        NodeData::Text { contents } => {
+            let contents = contents.borrow();
+            for (i, word) in contents.split_whitespace().enumerate() {
+                let needs_space = i > 0 || contents.starts_with(char::is_whitespace);
+                if needs_space && !text.ends_with([' ', '\n', '\t']) && !text.is_empty() {
+                    text.push(' ');
+                }
+                text.push_str(word);
+            }
+            if contents.ends_with(char::is_whitespace) && !text.ends_with([' ', '\n', '\t']) {
+                text.push(' ');
+            }
+        }
+        NodeData::Element { name, .. } => {
+            let tag = &*name.local;
+            if tag == "br" {
+                text.push('\n');
+                return;
+            }
+
+            let separator = if BLOCK_ELEMENTS.contains(&tag) {
+                "\n\n"
+            } else if LINE_ELEMENTS.contains(&tag) && !text.ends_with('\n') {
+                "\n"
+            } else {
+                ""
+            };
+            text.push_str(separator);
+            let mut item_number = 0;
+            for child in node.children.borrow().iter() {
+                if tag == "details" && !details.includes(child) {
+                    continue;
+                }
+                if let NodeData::Element { name, .. } = &child.data {
+                    if &*name.local == "li" {
+                        item_number += 1;
+                        match tag {
+                            "ol" => text.push_str(&format!("\n{item_number}. ")),
+                            _ => text.push_str("\n- "),
+                        }
+                    }
+                }
+                push_text(child, text, details);
+            }
+            text.push_str(separator);
+            if matches!(tag, "td" | "th") {
+                text.push('\t');
+            }
+        }
+        NodeData::Document => {
+            for child in node.children.borrow().iter() {
+                push_text(child, text, details);
+            }
+        }
+        _ => {}
+    }
+}
+
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "details",
+    "div",
+    "dl",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "ul",
+];
+
+/// Elements that go on their own line, but without blank lines around them.
+const LINE_ELEMENTS: &[&str] = &["dd", "dt", "figcaption", "summary", "tr"];
@@ -12,7 +12,7 @@ use markup5ever_rcdom::{RcDom, SerializableHandle};
 pub fn html_to_xml(content: &str) -> String {
     serialize_xml(parse_html(content))
 }
-fn parse_html(content: &str) -> RcDom {
+pub(crate) fn parse_html(content: &str) -> RcDom {
     let qual_name = QualName::new(
         None,
         Namespace::from("http://www.w3.org/1999/xhtml"),
@@ -28,7 +28,7 @@ fn parse_html(content: &str) -> RcDom {
     .one(content)
     .finish()
 }
-fn serialize_xml(content: RcDom) -> String {
+pub(super) fn serialize_xml(content: RcDom) -> String {
     let document: SerializableHandle = content.document.into();
 
     let mut bytes = vec![];
@@ -0,0 +1,236 @@
+use html5ever::{
+    interface::{ElementFlags, NodeOrText},
+    tree_builder::TreeSink,
+    Attribute,
+};
+use markup5ever::{LocalName, Namespace, QualName};
+use markup5ever_rcdom::{Handle, NodeData, RcDom};
+
+use super::html_to_xml::{parse_html, serialize_xml};
+
+/// Like [super::html_to_xml], but every sentence (and image) is wrapped in a
+/// `<span class="koboSpan" id="kobo.{paragraph}.{segment}">`, as in kepub files.
+///
+/// Kobo readers use these spans for their page statistics, highlights, and reading position.
+pub fn html_to_kepub_xml(content: &str) -> String {
+    let mut dom = parse_html(content);
+
+    let document = dom.document.clone();
+    KoboSpans::default().walk(&mut dom, &document);
+
+    serialize_xml(dom)
+}
+
+/// Each of these starts a new paragraph.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "blockquote",
+    "caption",
+    "dd",
+    "div",
+    "dt",
+    "figcaption",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "li",
+    "p",
+    "summary",
+    "td",
+    "th",
+];
+
+/// The content of these is left alone.
+const SKIPPED_ELEMENTS: &[&str] = &["math", "pre", "script", "style", "svg"];
+
+struct KoboSpans {
+    paragraph: usize,
+    segment: usize,
+    new_paragraph: bool,
+}
+impl Default for KoboSpans {
+    fn default() -> Self {
+        Self {
+            paragraph: 0,
+            segment: 0,
+            new_paragraph: true,
+        }
+    }
+}
+impl KoboSpans {
+    fn walk(&mut self, dom: &mut RcDom, node: &Handle) {
+        let children: Vec<Handle> = node.children.borrow().clone();
+        for child in children {
+            match &child.data {
+                NodeData::Text { contents } => {
+                    let text = contents.borrow().to_string();
+                    if text.trim().is_empty() {
+                        continue;
+                    }
+
+                    for sentence in sentences(&text) {
+                        let span = self.span(dom);
+                        dom.append(&span, NodeOrText::AppendText(sentence.into()));
+                        dom.append_before_sibling(&child, NodeOrText::AppendNode(span));
+                    }
+                    dom.remove_from_parent(&child);
+                }
+                NodeData::Element { name, .. } => {
+                    let tag = &*name.local;
+                    if SKIPPED_ELEMENTS.contains(&tag) {
+                        continue;
+                    }
+
+                    if tag == "img" {
+                        let span = self.span(dom);
+                        dom.append_before_sibling(&child, NodeOrText::AppendNode(span.clone()));
+                        dom.remove_from_parent(&child);
+                        dom.append(&span, NodeOrText::AppendNode(child.clone()));
+                        continue;
+                    }
+
+                    let block = BLOCK_ELEMENTS.contains(&tag);
+                    if block {
+                        self.new_paragraph = true;
+                    }
+                    self.walk(dom, &child);
+                    if block {
+                        self.new_paragraph = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// An empty span with the id of the next segment.
+    fn span(&mut self, dom: &mut RcDom) -> Handle {
+        if self.new_paragraph {
+            self.paragraph += 1;
+            self.segment = 0;
+            self.new_paragraph = false;
+        }
+        self.segment += 1;
+
+        let attribute = |name: &str, value: String| Attribute {
+            name: QualName::new(None, Namespace::from(""), LocalName::from(name)),
+            value: value.into(),
+        };
+        dom.create_element(
+            QualName::new(
+                None,
+                Namespace::from("http://www.w3.org/1999/xhtml"),
+                LocalName::from("span"),
+            ),
+            vec![
+                attribute("class", "koboSpan".to_string()),
+                attribute("id", format!("kobo.{}.{}", self.paragraph, self.segment)),
+            ],
+            ElementFlags::default(),
+        )
+    }
+}
+
+/// Splits the text after each sentence-ending punctuation (and any closing quotes or brackets)
+/// that is followed by whitespace. The whitespace stays with the sentence it follows.
+fn sentences(text: &str) -> Vec<&str> {
+    let mut sentences = vec![];
+    let mut start = 0;
+
+    let mut chars = text.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        if !matches!(c, '.' | '!' | '?' | '…') {
+            continue;
+        }
+        while chars.next_if(|(_, c)| ".!?…\"'”’)]".contains(*c)).is_some() {}
+
+        let mut end = None;
+        while let Some((i, c)) = chars.next_if(|(_, c)| c.is_whitespace()) {
+            end = Some(i + c.len_utf8());
+        }
+        if let Some(end) = end.filter(|end| *end < text.len()) {
+            sentences.push(&text[start..end]);
+            start = end;
+        }
+    }
+    if start < text.len() {
+        sentences.push(&text[start..]);
+    }
+
+    sentences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sentences_keep_their_punctuation_and_spaces() {
+        assert_eq!(
+            sentences("One. Two?! \"Three.\" Four… five"),
+            ["One. ", "Two?! ", "\"Three.\" ", "Four… ", "five"]
+        );
+        // Trailing spaces, and punctuation inside words, don't start a new sentence.
+        assert_eq!(sentences("3.14 is pi. "), ["3.14 is pi. "]);
+    }
+
+    #[test]
+    fn spans_are_numbered_by_paragraph_and_sentence() {
+        assert_eq!(
+            html_to_kepub_xml("<p>One. Two.</p><p>Three.</p>"),
+            concat!(
+                r#"<p><span class="koboSpan" id="kobo.1.1">One. </span><span class="koboSpan" id="kobo.1.2">Two.</span></p>"#,
+                r#"<p><span class="koboSpan" id="kobo.2.1">Three.</span></p>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn inline_elements_are_wrapped_inside() {
+        assert_eq!(
+            html_to_kepub_xml("<p>One <em>two. Three</em> four.</p>"),
+            concat!(
+                r#"<p><span class="koboSpan" id="kobo.1.1">One </span>"#,
+                r#"<em><span class="koboSpan" id="kobo.1.2">two. </span><span class="koboSpan" id="kobo.1.3">Three</span></em>"#,
+                r#"<span class="koboSpan" id="kobo.1.4"> four.</span></p>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn blocks_inside_blocks_start_new_paragraphs() {
+        assert_eq!(
+            html_to_kepub_xml("<blockquote>A<p>B</p>C</blockquote>"),
+            concat!(
+                r#"<blockquote><span class="koboSpan" id="kobo.1.1">A</span>"#,
+                r#"<p><span class="koboSpan" id="kobo.2.1">B</span></p>"#,
+                r#"<span class="koboSpan" id="kobo.3.1">C</span></blockquote>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn images_get_their_own_span() {
+        assert_eq!(
+            html_to_kepub_xml(r#"<p>Look: <img src="a.png" alt="A"></p>"#),
+            concat!(
+                r#"<p><span class="koboSpan" id="kobo.1.1">Look: </span>"#,
+                r#"<span class="koboSpan" id="kobo.1.2"><img src="a.png" alt="A"></img></span></p>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn whitespace_and_skipped_elements_are_left_alone() {
+        assert_eq!(
+            html_to_kepub_xml("<p>One.</p>\n<pre>Code. More.</pre>\n<p>Two.</p>"),
+            concat!(
+                r#"<p><span class="koboSpan" id="kobo.1.1">One.</span></p>"#,
+                "\n<pre>Code. More.</pre>\n",
+                r#"<p><span class="koboSpan" id="kobo.2.1">Two.</span></p>"#,
+            )
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use std::borrow::Cow;
+
+use lol_html::{
+    html_content::ContentType, rewrite_str, ElementContentHandlers, RewriteStrSettings,
+};
+
+use super::escape_html;
+
+/// Numbers the links of posts, replies, and descriptions that lead to websites,
+/// and lists their urls in footnotes at the end of the content.
+///
+/// The footnotes have ids, so this needs to run after sanitization, on a whole page.
+pub fn add_link_footnotes(content: &str) -> String {
+    let mut urls: Vec<String> = vec![];
+
+    let content = rewrite_str(
+        content,
+        RewriteStrSettings {
+            element_content_handlers: vec![(
+                Cow::Owned(".content-block a[href], .description a[href]".parse().unwrap()),
+                ElementContentHandlers::default().element(|el| {
+                    let Some(url) = el.get_attribute("href") else {
+                        return Ok(());
+                    };
+                    if !(url.starts_with("http://") || url.starts_with("https://")) {
+                        return Ok(());
+                    }
+
+                    urls.push(url);
+                    let n = urls.len();
+                    el.after(
+                        &format!(
+                            r##"<sup><a href="#footnote-{n}" id="footnote-ref-{n}">[{n}]</a></sup>"##
+                        ),
+                        ContentType::Html,
+                    );
+                    Ok(())
+                }),
+            )],
+            ..RewriteStrSettings::default()
+        },
+    )
+    .unwrap();
+
+    if urls.is_empty() {
+        return content;
+    }
+
+    let notes: Vec<String> = urls
+        .iter()
+        .enumerate()
+        .map(|(i, url)| {
+            let n = i + 1;
+            let url = escape_html(url);
+            format!(
+                r##"<li id="footnote-{n}"><a href="#footnote-ref-{n}">↑</a> <a href="{url}">{url}</a></li>"##
+            )
+        })
+        .collect();
+
+    format!(
+        r##"{content}<div class="footnotes"><hr/><ol>{}</ol></div>"##,
+        notes.concat()
+    )
+}
@@ -1,13 +1,32 @@
+mod details_footnotes;
 mod edit_image_urls;
+mod edit_link_urls;
+mod epub_semantics;
 mod flatten_details;
+mod html_to_markdown;
+mod html_to_rich_text;
+mod html_to_text;
 mod html_to_xml;
+mod kobo_spans;
+mod link_footnotes;
 mod named_entities;
+mod reply_anchors;
 mod sanitize;
 
+pub use details_footnotes::details_to_footnotes;
 pub use edit_image_urls::edit_image_urls;
+pub use edit_link_urls::edit_link_urls;
+pub use epub_semantics::add_epub_semantics;
 pub use flatten_details::flatten_details;
+pub use html_to_markdown::html_to_markdown;
+pub use html_to_rich_text::{html_to_rich_text, ParagraphKind, RichBlock, Run, RunStyle};
+pub use html_to_text::{html_to_text, html_to_text_with_details};
 pub use html_to_xml::html_to_xml;
+pub(crate) use html_to_xml::parse_html;
+pub use kobo_spans::html_to_kepub_xml;
+pub use link_footnotes::add_link_footnotes;
 pub use named_entities::decode_named_entities;
+pub use reply_anchors::add_reply_anchors;
 pub use sanitize::repair_and_sanitize;
 
 /// The following characters (HTML reserved characters) are escaped:
@@ -0,0 +1,26 @@
+use std::borrow::Cow;
+
+use lol_html::{rewrite_str, ElementContentHandlers, RewriteStrSettings};
+
+/// Gives every element with a `reply-id` attribute an `id="reply-{id}"`,
+/// so that replies can be linked to.
+///
+/// This needs to run after sanitization, as the sanitizer removes ids.
+pub fn add_reply_anchors(content: &str) -> String {
+    rewrite_str(
+        content,
+        RewriteStrSettings {
+            element_content_handlers: vec![(
+                Cow::Owned("[reply-id]".parse().unwrap()),
+                ElementContentHandlers::default().element(|el| {
+                    if let Some(id) = el.get_attribute("reply-id") {
+                        el.set_attribute("id", &format!("reply-{id}")).unwrap();
+                    }
+                    Ok(())
+                }),
+            )],
+            ..RewriteStrSettings::default()
+        },
+    )
+    .unwrap()
+}
@@ -344,4 +344,14 @@ const CLASSES: &[&str] = &[
     "character",
     "icon",
     "icon-caption",
+    "site-nav",
+    "toc",
+    "search",
+    "search-input",
+    "search-status",
+    "search-results",
+    "footnotes",
+    "details-footnotes",
+    "details-note",
+    "details-ref",
 ];
@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{plain_author_names, process_content, transform, Block, Options, Thread};
+
+/// Options for the text-to-speech exports, [Thread::to_ssml] and [Thread::to_tts_script].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TtsOptions {
+    pub voices: VoiceMap,
+    /// The silence after the title and after each post or reply, in milliseconds.
+    pub pause_ms: u32,
+    pub details: TtsDetails,
+    /// Have the narrator say who is speaking before each post or reply.
+    pub announce_speakers: bool,
+}
+impl Default for TtsOptions {
+    fn default() -> Self {
+        Self {
+            voices: VoiceMap::default(),
+            pause_ms: 800,
+            details: TtsDetails::default(),
+            announce_speakers: false,
+        }
+    }
+}
+
+/// Which voice reads what, usually loaded from a json file like:
+///
+/// ```json
+/// {
+///     "narrator": "en-GB-1",
+///     "default": "en-US-2",
+///     "characters": { "Keltham": "en-US-3", "12345": "en-US-4" },
+///     "authors": { "alice": "en-US-5" }
+/// }
+/// ```
+///
+/// Characters can be given by name (or alias) or by id.
+/// Replies use the voice of their character, or else of their author, or else the default voice.
+/// Missing voices are left to the speech engine.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoiceMap {
+    /// Reads the title and the speaker announcements.
+    pub narrator: Option<String>,
+    pub default: Option<String>,
+    pub characters: HashMap<String, String>,
+    pub authors: HashMap<String, String>,
+}
+impl VoiceMap {
+    fn voice(&self, block: &Block) -> Option<&str> {
+        let character = block
+            .character_id
+            .and_then(|id| self.characters.get(&id.to_string()))
+            .or_else(|| self.characters.get(block.character_name?));
+        let author = || self.authors.get(block.author?);
+
+        character
+            .or_else(author)
+            .or(self.default.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// What to do with the content of `<details>` tags, which are often spoilers or asides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TtsDetails {
+    /// Read both the summary and the content.
+    #[default]
+    Read,
+    /// Only read the summary.
+    SummaryOnly,
+    /// Leave out the whole tag.
+    Skip,
+}
+
+/// A piece of text read by a single voice.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TtsSegment {
+    /// `Character (Author)`, or [None] for the narrator.
+    pub speaker: Option<String>,
+    pub voice: Option<String>,
+    /// [None] for the title and the post itself.
+    pub reply_id: Option<u64>,
+    /// Paragraphs are separated by blank lines.
+    pub text: String,
+    /// The silence after this segment, in milliseconds.
+    pub pause_after_ms: u32,
+}
+
+/// The format of [Thread::to_tts_script].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TtsScript {
+    /// Bumped whenever the format changes.
+    pub version: u32,
+    pub post_id: u64,
+    pub title: String,
+    pub segments: Vec<TtsSegment>,
+}
+
+impl Thread {
+    /// The thread as [SSML](https://www.w3.org/TR/speech-synthesis11/),
+    /// with the voices, pauses, and details handling of the [TtsOptions].
+    pub fn to_ssml(&self, options: Options, tts: &TtsOptions) -> String {
+        let segments: Vec<String> = self
+            .tts_segments(options, tts)
+            .iter()
+            .map(|segment| {
+                let paragraphs: Vec<String> = segment
+                    .text
+                    .split("\n\n")
+                    .map(|paragraph| format!("<p>{}</p>", transform::escape_html(paragraph)))
+                    .collect();
+                let paragraphs = paragraphs.join("\n");
+
+                let content = match &segment.voice {
+                    Some(voice) => {
+                        let voice = transform::escape_html(voice);
+                        format!(r##"<voice name="{voice}">{paragraphs}</voice>"##)
+                    }
+                    None => paragraphs,
+                };
+                match segment.pause_after_ms {
+                    0 => content,
+                    pause => format!(
+                        r##"{content}
+<break time="{pause}ms"/>"##
+                    ),
+                }
+            })
+            .collect();
+        let segments = segments.join("\n");
+        let language = options.language;
+
+        format!(
+            r##"<?xml version="1.0" encoding="UTF-8"?>
+<speak version="1.1" xmlns="http://www.w3.org/2001/10/synthesis" xml:lang="{language}">
+{segments}
+</speak>
+"##
+        )
+    }
+
+    /// The thread as a json [TtsScript], for speech engines that don't support SSML.
+    pub fn to_tts_script(&self, options: Options, tts: &TtsOptions) -> String {
+        let script = TtsScript {
+            version: 1,
+            post_id: self.post.id,
+            title: self.post.subject.clone(),
+            segments: self.tts_segments(options, tts),
+        };
+
+        serde_json::to_string_pretty(&script).unwrap()
+    }
+
+    pub fn tts_segments(&self, options: Options, tts: &TtsOptions) -> Vec<TtsSegment> {
+        let narrator = |text: String| TtsSegment {
+            speaker: None,
+            voice: tts.voices.narrator.clone(),
+            reply_id: None,
+            text,
+            pause_after_ms: tts.pause_ms,
+        };
+
+        let post = &self.post;
+        let authors = plain_author_names(&post.authors);
+        let mut segments = vec![narrator(format!("{}.\n\nBy {authors}.", post.subject))];
+
+        let (summaries, details_content) = match tts.details {
+            TtsDetails::Read => (true, true),
+            TtsDetails::SummaryOnly => (true, false),
+            TtsDetails::Skip => (false, false),
+        };
+
+        for block in self.blocks() {
+            let content = process_content(block.content, options, &HashMap::new());
+            let text = transform::html_to_text_with_details(&content, summaries, details_content);
+            if text.is_empty() {
+                continue;
+            }
+
+            let speaker = block.speaker();
+            if tts.announce_speakers {
+                if let Some(name) = block.character_name.or(block.author) {
+                    segments.push(TtsSegment {
+                        reply_id: block.reply_id,
+                        pause_after_ms: 0,
+                        ..narrator(format!("{name}."))
+                    });
+                }
+            }
+
+            segments.push(TtsSegment {
+                speaker,
+                voice: tts.voices.voice(&block).map(str::to_string),
+                reply_id: block.reply_id,
+                text,
+                pause_after_ms: tts.pause_ms,
+            });
+        }
+
+        segments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use crate::types::{BoardInPost, Character, Post, Reply, User};
+
+    use super::*;
+
+    fn thread() -> Thread {
+        let alice = User {
+            id: 1,
+            username: "alice".to_string(),
+        };
+        let bob = User {
+            id: 2,
+            username: "bob".to_string(),
+        };
+        let reply = |id, user: &User, character: Option<Character>, content: &str| Reply {
+            id,
+            character,
+            character_name: None,
+            content: content.to_string(),
+            created_at: DateTime::default(),
+            icon: None,
+            updated_at: DateTime::default(),
+            user: user.clone(),
+        };
+        Thread {
+            post: Post {
+                id: 10,
+                authors: vec![alice.clone(), bob.clone()],
+                board: BoardInPost {
+                    id: 1,
+                    name: "Board".to_string(),
+                },
+                character: Some(Character {
+                    id: 7,
+                    name: "Eve".to_string(),
+                    screenname: None,
+                }),
+                content: "<p>First &amp; <em>second</em>.</p><p>Third.</p>".to_string(),
+                created_at: DateTime::default(),
+                description: None,
+                icon: None,
+                num_replies: 3,
+                section: None,
+                section_order: 0,
+                status: "active".to_string(),
+                subject: "Cats & <Dogs>".to_string(),
+                tagged_at: DateTime::default(),
+            },
+            replies: vec![
+                reply(100, &bob, None, "<p>Reply</p>"),
+                reply(101, &alice, None, "<p><img src=\"image.png\"></p>"),
+                reply(
+                    102,
+                    &alice,
+                    None,
+                    "<p>Before</p><details><summary>Spoiler</summary><p>Hidden</p></details>",
+                ),
+            ],
+        }
+    }
+
+    fn block<'a>(
+        character_id: Option<u64>,
+        character_name: Option<&'a str>,
+        author: Option<&'a str>,
+    ) -> Block<'a> {
+        Block {
+            reply_id: None,
+            character_id,
+            character_name,
+            author,
+            icon: None,
+            content: "",
+        }
+    }
+
+    #[test]
+    fn voices_fall_back_from_characters_to_authors_to_the_default() {
+        let voices = VoiceMap {
+            narrator: Some("narrator".to_string()),
+            default: Some("default".to_string()),
+            characters: HashMap::from([
+                ("7".to_string(), "by id".to_string()),
+                ("Eve".to_string(), "by name".to_string()),
+            ]),
+            authors: HashMap::from([("alice".to_string(), "alice".to_string())]),
+        };
+
+        assert_eq!(
+            voices.voice(&block(Some(7), Some("Eve"), Some("alice"))),
+            Some("by id")
+        );
+        assert_eq!(
+            voices.voice(&block(Some(8), Some("Eve"), Some("alice"))),
+            Some("by name")
+        );
+        assert_eq!(
+            voices.voice(&block(Some(8), Some("Evie"), Some("alice"))),
+            Some("alice")
+        );
+        assert_eq!(
+            voices.voice(&block(None, None, Some("bob"))),
+            Some("default")
+        );
+        assert_eq!(VoiceMap::default().voice(&block(Some(7), None, None)), None);
+    }
+
+    #[test]
+    fn segments_follow_the_post_and_replies() {
+        let segments = thread().tts_segments(Options::default(), &TtsOptions::default());
+        let summary: Vec<(Option<u64>, Option<&str>, &str)> = segments
+            .iter()
+            .map(|segment| (segment.reply_id, segment.speaker.as_deref(), &*segment.text))
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                (None, None, "Cats & <Dogs>.\n\nBy alice & bob."),
+                (None, Some("Eve (alice)"), "First & second.\n\nThird."),
+                (Some(100), Some("bob"), "Reply"),
+                // The reply with only an image has nothing to read.
+                (Some(102), Some("alice"), "Before\n\nSpoiler\n\nHidden"),
+            ]
+        );
+        assert!(segments.iter().all(|segment| segment.pause_after_ms == 800));
+    }
+
+    #[test]
+    fn speakers_can_be_announced() {
+        let tts = TtsOptions {
+            announce_speakers: true,
+            ..TtsOptions::default()
+        };
+        let segments = thread().tts_segments(Options::default(), &tts);
+
+        assert_eq!(segments.len(), 7);
+        assert_eq!(
+            segments[1],
+            TtsSegment {
+                speaker: None,
+                voice: None,
+                reply_id: None,
+                text: "Eve.".to_string(),
+                pause_after_ms: 0,
+            }
+        );
+        assert_eq!(segments[3].text, "bob.");
+        assert_eq!(segments[3].reply_id, Some(100));
+    }
+
+    #[test]
+    fn details_can_be_left_out() {
+        let last_text = |details| {
+            let tts = TtsOptions {
+                details,
+                ..TtsOptions::default()
+            };
+            let segments = thread().tts_segments(Options::default(), &tts);
+            segments.last().unwrap().text.clone()
+        };
+
+        assert_eq!(last_text(TtsDetails::SummaryOnly), "Before\n\nSpoiler");
+        assert_eq!(last_text(TtsDetails::Skip), "Before");
+    }
+
+    #[test]
+    fn ssml_is_escaped_and_paused() {
+        let tts = TtsOptions {
+            voices: VoiceMap {
+                narrator: Some("a \"quoted\" & <odd> voice".to_string()),
+                ..VoiceMap::default()
+            },
+            pause_ms: 500,
+            ..TtsOptions::default()
+        };
+        let ssml = thread().to_ssml(Options::default(), &tts);
+
+        assert!(ssml.contains(
+            r#"<speak version="1.1" xmlns="http://www.w3.org/2001/10/synthesis" xml:lang="en">"#
+        ));
+        assert!(ssml.contains(
+            "<voice name=\"a &#34;quoted&#34; &#38; &#60;odd&#62; voice\"><p>Cats &#38; &#60;Dogs&#62;.</p>\n<p>By alice &#38; bob.</p></voice>\n<break time=\"500ms\"/>"
+        ));
+        // Segments without a voice are left to the speech engine.
+        assert!(ssml.contains("<p>First &#38; second.</p>\n<p>Third.</p>\n<break time=\"500ms\"/>"));
+        assert!(!ssml.contains("<Dogs>"));
+    }
+
+    #[test]
+    fn scripts_list_the_segments() {
+        let thread = thread();
+        let script: TtsScript =
+            serde_json::from_str(&thread.to_tts_script(Options::default(), &TtsOptions::default()))
+                .unwrap();
+        assert_eq!(script.version, 1);
+        assert_eq!(script.post_id, 10);
+        assert_eq!(
+            script.segments,
+            thread.tts_segments(Options::default(), &TtsOptions::default())
+        );
+    }
+}
@@ -1,16 +1,21 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     error::Error,
     io::Cursor,
+    num::NonZeroUsize,
+    sync::Arc,
 };
 
-use image::imageops::FilterType;
+use image::{codecs::avif::AvifEncoder, imageops::FilterType};
 use mime::Mime;
+use sha2::{Digest, Sha256};
+use tokio::{sync::Semaphore, task::JoinSet};
 
 use crate::{
     cached::download_cached_image,
+    gen::{cover, ImageFormat, Options},
     types::{Continuity, Icon, Thread},
-    utils::{mime_to_image_extension, url_hash},
+    utils::{extension_to_image_mime, mime_to_image_extension, url_hash},
 };
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -40,19 +45,23 @@ impl InternedImage {
         }
     }
     /// Converts some image formats into more widely supported ones for epub compatibility.
-    pub fn into_common_format(self) -> Self {
-        match (self.mime.type_(), self.mime.subtype()) {
+    /// [None] for images that can't be, like AVIF (which we can't decode) or broken WebP.
+    pub fn into_common_format(self) -> Option<Self> {
+        let image = match (self.mime.type_(), self.mime.subtype()) {
             (mime::IMAGE, mime::BMP)
             | (mime::IMAGE, mime::GIF)
             | (mime::IMAGE, mime::JPEG)
             | (mime::IMAGE, mime::PNG)
             | (mime::IMAGE, mime::SVG) => self,
             (mime::IMAGE, subtype) if subtype.as_str() == "webp" => self.into_png(),
+            (mime::IMAGE, subtype) if subtype.as_str() == "avif" => self,
             _ => unreachable!(),
-        }
+        };
+        image.is_common_format().then_some(image)
     }
-    pub fn try_into_jpeg(self) -> Self {
-        match (self.mime.type_(), self.mime.subtype()) {
+    /// [None] for images left in a format that not all e-readers support, like AVIF (which we can't decode).
+    pub fn try_into_jpeg(self) -> Option<Self> {
+        let image = match (self.mime.type_(), self.mime.subtype()) {
             (mime::IMAGE, mime::BMP)
             | (mime::IMAGE, mime::GIF)
             | (mime::IMAGE, mime::JPEG)
@@ -60,21 +69,49 @@ impl InternedImage {
             (mime::IMAGE, subtype) if subtype.as_str() == "webp" => self.into_jpeg(),
 
             (mime::IMAGE, mime::SVG) => self,
+            (mime::IMAGE, subtype) if subtype.as_str() == "avif" => self,
             _ => unreachable!(),
-        }
+        };
+        image.is_common_format().then_some(image)
     }
-    pub fn resize_down(self, width: u32) -> Result<Self, Box<dyn Error>> {
-        let img = self.to_dynamic_image()?;
+    /// Transcodes bmp and png images to lossless WebP.
+    /// Jpegs are left alone, as lossless WebP photos are usually bigger than the original,
+    /// and so are gifs, as they might be animated and we only decode the first frame.
+    pub fn try_into_webp(self) -> Self {
+        match (self.mime.type_(), self.mime.subtype()) {
+            (mime::IMAGE, mime::BMP) | (mime::IMAGE, mime::PNG) => self.into_webp(),
 
-        if img.width() < width {
-            return Ok(self);
+            (mime::IMAGE, mime::GIF) | (mime::IMAGE, mime::JPEG) | (mime::IMAGE, mime::SVG) => self,
+            (mime::IMAGE, subtype) if matches!(subtype.as_str(), "webp" | "avif") => self,
+            _ => unreachable!(),
         }
+    }
+    /// Transcodes raster images to AVIF.
+    /// Gifs are left alone as they might be animated, and we only decode the first frame.
+    pub fn try_into_avif(self) -> Self {
+        match (self.mime.type_(), self.mime.subtype()) {
+            (mime::IMAGE, mime::BMP) | (mime::IMAGE, mime::JPEG) | (mime::IMAGE, mime::PNG) => {
+                self.into_avif()
+            }
+            (mime::IMAGE, subtype) if subtype.as_str() == "webp" => self.into_avif(),
 
+            (mime::IMAGE, mime::GIF) | (mime::IMAGE, mime::SVG) => self,
+            (mime::IMAGE, subtype) if subtype.as_str() == "avif" => self,
+            _ => unreachable!(),
+        }
+    }
+    pub fn resize_down(self, width: u32) -> Result<Self, Box<dyn Error>> {
         let Ok(format) = self.image_format() else {
             // Avoid resizing unsupported formats.
             return Ok(self);
         };
 
+        let img = self.to_dynamic_image()?;
+
+        if img.width() < width {
+            return Ok(self);
+        }
+
         let img = img.resize(width, img.height(), FilterType::Lanczos3);
 
         let mut data = Vec::with_capacity(self.data.len());
@@ -87,6 +124,28 @@ impl InternedImage {
             data,
         })
     }
+    /// Applies all the conversions requested in the [Options].
+    /// [None] if the image can't be kept in the requested formats.
+    pub fn process(self, options: Options) -> Result<Option<Self>, Box<dyn Error>> {
+        let mut image = self;
+
+        if let Some(size) = options.resize_icons {
+            if image.is_icon() {
+                image = image.resize_down(size)?;
+            }
+        }
+
+        if options.jpeg {
+            return Ok(image.try_into_jpeg());
+        }
+
+        Ok(match options.image_format {
+            ImageFormat::Compatible => image.into_common_format(),
+            ImageFormat::Original => Some(image),
+            ImageFormat::WebP => Some(image.try_into_webp()),
+            ImageFormat::Avif => Some(image.try_into_avif()),
+        })
+    }
 }
 impl InternedImage {
     fn to_dynamic_image(&self) -> Result<image::DynamicImage, Box<dyn Error>> {
@@ -100,50 +159,196 @@ impl InternedImage {
             (mime::IMAGE, mime::PNG) => image::ImageFormat::Png,
             (mime::IMAGE, mime::SVG) => Err("svg not supported")?,
             (mime::IMAGE, subtype) if subtype.as_str() == "webp" => image::ImageFormat::WebP,
+            (mime::IMAGE, subtype) if subtype.as_str() == "avif" => Err("avif not supported")?,
             _ => unreachable!(),
         })
     }
-    fn into_png(self) -> Self {
-        let id = self.id;
-        let original_url = self.original_url.clone();
-
-        let mut data = Vec::with_capacity(self.data.len());
-
-        self.to_dynamic_image()
-            .unwrap()
-            .write_to(&mut Cursor::new(&mut data), image::ImageFormat::Png)
-            .unwrap();
-
-        Self {
-            id,
-            original_url,
-            mime: mime::IMAGE_PNG,
-            data,
+    /// A hash of the decoded pixels, so that the same picture re-encoded or served from
+    /// different urls is recognised as identical.
+    /// Falls back to hashing the raw data for formats we can't decode (like svg).
+    fn content_hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        match self.to_dynamic_image() {
+            Ok(img) => {
+                let img = img.into_rgba8();
+                hasher.update(img.width().to_be_bytes());
+                hasher.update(img.height().to_be_bytes());
+                hasher.update(img.as_raw());
+            }
+            Err(_) => hasher.update(&self.data),
         }
+        hasher.finalize().into()
+    }
+    /// A 64-bit difference hash (dHash): each bit records whether a pixel of a 9x8 grayscale
+    /// thumbnail is brighter than its right neighbour.
+    /// Similar looking images have hashes with a small hamming distance.
+    fn perceptual_hash(&self) -> Option<u64> {
+        let img = self.to_dynamic_image().ok()?;
+        let img = img.resize_exact(9, 8, FilterType::Triangle).into_luma8();
+
+        let mut hash = 0u64;
+        for y in 0..8 {
+            for x in 0..8 {
+                let left = img.get_pixel(x, y).0[0];
+                let right = img.get_pixel(x + 1, y).0[0];
+                hash = (hash << 1) | u64::from(left > right);
+            }
+        }
+        Some(hash)
+    }
+    /// Whether EPUB2 and all e-readers support the format.
+    fn is_common_format(&self) -> bool {
+        matches!(
+            (self.mime.type_(), self.mime.subtype()),
+            (mime::IMAGE, mime::BMP)
+                | (mime::IMAGE, mime::GIF)
+                | (mime::IMAGE, mime::JPEG)
+                | (mime::IMAGE, mime::PNG)
+                | (mime::IMAGE, mime::SVG)
+        )
+    }
+    fn into_png(self) -> Self {
+        self.transcode(mime::IMAGE_PNG, |img, data| {
+            img.write_to(&mut Cursor::new(data), image::ImageFormat::Png)?;
+            Ok(())
+        })
     }
     fn into_jpeg(self) -> Self {
-        let id = self.id;
-        let original_url = self.original_url.clone();
-
+        self.transcode(mime::IMAGE_JPEG, |img, data| {
+            img.into_rgb8()
+                .write_to(&mut Cursor::new(data), image::ImageFormat::Jpeg)?;
+            Ok(())
+        })
+    }
+    fn into_webp(self) -> Self {
+        self.transcode(extension_to_image_mime("webp").unwrap(), |img, data| {
+            img.into_rgba8()
+                .write_to(&mut Cursor::new(data), image::ImageFormat::WebP)?;
+            Ok(())
+        })
+    }
+    fn into_avif(self) -> Self {
+        self.transcode(extension_to_image_mime("avif").unwrap(), |img, data| {
+            // The default speed (4) is very slow for the hundreds of icons of a board.
+            let encoder = AvifEncoder::new_with_speed_quality(data, 8, 80);
+            img.into_rgba8().write_with_encoder(encoder)?;
+            Ok(())
+        })
+    }
+    /// Decodes the image and encodes it again as `mime`.
+    /// Images that fail either step are kept as they are.
+    fn transcode(
+        self,
+        mime: Mime,
+        encode: impl FnOnce(image::DynamicImage, &mut Vec<u8>) -> Result<(), Box<dyn Error>>,
+    ) -> Self {
         let mut data = Vec::with_capacity(self.data.len());
+        match self
+            .to_dynamic_image()
+            .and_then(|img| encode(img, &mut data))
+        {
+            Ok(()) => Self { mime, data, ..self },
+            Err(e) => {
+                log::info!("Could not convert {} to {mime}: {e}", self.original_url);
+                self
+            }
+        }
+    }
+}
 
-        self.to_dynamic_image()
-            .unwrap()
-            .into_rgb8()
-            .write_to(&mut Cursor::new(&mut data), image::ImageFormat::Jpeg)
-            .unwrap();
+/// What [deduplicate] managed to merge.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    /// The number of distinct images before deduplication.
+    pub images: usize,
+    /// The number of distinct images left after deduplication.
+    pub unique: usize,
+    /// The total size of the images that were dropped.
+    pub bytes_saved: usize,
+}
 
-        Self {
-            id,
-            original_url,
-            mime: mime::IMAGE_JPEG,
-            data,
+/// Points all urls whose images have the same content at a single image.
+///
+/// Images are considered the same if their decoded pixels match exactly or, if a
+/// `perceptual_tolerance` is provided, if their perceptual hashes are within that hamming distance.
+///
+/// Icons are only merged with icons, and inline images with inline images, as icons can be resized
+/// (see [Options::resize_icons]). The image that is kept is chosen deterministically (by id, then url),
+/// so that the output doesn't change between runs.
+pub fn deduplicate(
+    images: HashMap<String, InternedImage>,
+    perceptual_tolerance: Option<u32>,
+) -> (HashMap<String, InternedImage>, DedupStats) {
+    let mut entries: Vec<(String, InternedImage)> = images.into_iter().collect();
+    entries.sort_by(|(url_a, a), (url_b, b)| {
+        (a.id.is_none(), a.id, &a.original_url, url_a).cmp(&(
+            b.id.is_none(),
+            b.id,
+            &b.original_url,
+            url_b,
+        ))
+    });
+
+    let mut stats = DedupStats::default();
+
+    let mut canonical: Vec<InternedImage> = vec![];
+    let mut by_content_hash: HashMap<(bool, [u8; 32]), usize> = HashMap::new();
+    let mut perceptual_hashes: Vec<(bool, u64, usize)> = vec![];
+    let mut seen_names: HashSet<String> = HashSet::new();
+
+    let mut deduplicated = HashMap::with_capacity(entries.len());
+    for (url, image) in entries {
+        let is_new_name = seen_names.insert(image.name());
+        if is_new_name {
+            stats.images += 1;
         }
+
+        let is_icon = image.is_icon();
+        let content_hash = (is_icon, image.content_hash());
+        let perceptual_hash = perceptual_tolerance.and_then(|_| image.perceptual_hash());
+
+        let existing = by_content_hash.get(&content_hash).copied().or_else(|| {
+            let tolerance = perceptual_tolerance?;
+            let hash = perceptual_hash?;
+            perceptual_hashes
+                .iter()
+                .find(|(icon, other, _)| {
+                    *icon == is_icon && (hash ^ other).count_ones() <= tolerance
+                })
+                .map(|(_, _, i)| *i)
+        });
+
+        let index = match existing {
+            Some(index) => {
+                if is_new_name && canonical[index].name() != image.name() {
+                    stats.bytes_saved += image.data.len();
+                }
+                index
+            }
+            None => {
+                let index = canonical.len();
+                by_content_hash.insert(content_hash, index);
+                if let Some(hash) = perceptual_hash {
+                    perceptual_hashes.push((is_icon, hash, index));
+                }
+                canonical.push(image);
+                index
+            }
+        };
+
+        deduplicated.insert(url, canonical[index].clone());
     }
+
+    stats.unique = canonical.len();
+
+    (deduplicated, stats)
 }
 
 impl Continuity {
-    pub async fn images_to_intern(&self) -> Result<HashMap<String, InternedImage>, Box<dyn Error>> {
+    pub async fn images_to_intern(
+        &self,
+        options: Options,
+    ) -> Result<HashMap<String, InternedImage>, Box<dyn Error>> {
         let mut interned_images: HashMap<String, InternedImage> = HashMap::new();
         let mut skip: HashSet<String> = HashSet::default();
 
@@ -155,14 +360,21 @@ impl Continuity {
             thread
                 .image_tags_to_intern(&mut interned_images, &mut skip)
                 .await?;
+
+            if options.placeholder_icons {
+                thread.placeholders_to_intern(&mut interned_images, &skip);
+            }
         }
 
-        Ok(interned_images)
+        Ok(deduplicate_and_log(interned_images, options))
     }
 }
 
 impl Thread {
-    pub async fn images_to_intern(&self) -> Result<HashMap<String, InternedImage>, Box<dyn Error>> {
+    pub async fn images_to_intern(
+        &self,
+        options: Options,
+    ) -> Result<HashMap<String, InternedImage>, Box<dyn Error>> {
         let mut interned_images: HashMap<String, InternedImage> = HashMap::new();
         let mut skip: HashSet<String> = HashSet::default();
 
@@ -172,7 +384,11 @@ impl Thread {
         self.image_tags_to_intern(&mut interned_images, &mut skip)
             .await?;
 
-        Ok(interned_images)
+        if options.placeholder_icons {
+            self.placeholders_to_intern(&mut interned_images, &skip);
+        }
+
+        Ok(deduplicate_and_log(interned_images, options))
     }
 
     async fn icons_to_intern(
@@ -190,7 +406,7 @@ impl Thread {
             }
 
             match icon.intern().await {
-                Ok(interned) => interned_images.insert(url, interned.into_common_format()),
+                Ok(interned) => interned_images.insert(url, interned),
                 Err(e) => {
                     let id = icon.id;
                     log::info!(
@@ -224,8 +440,7 @@ impl Thread {
                             original_url: url,
                             mime,
                             data,
-                        }
-                        .into_common_format(),
+                        },
                     );
                 }
                 Err(e) => {
@@ -242,6 +457,132 @@ impl Thread {
     }
 }
 
+/// Runs [InternedImage::process] on all images.
+///
+/// Decoding, resizing, and encoding are CPU-heavy, so they are done on tokio's blocking thread pool
+/// with at most [Options::image_workers] images in flight.
+/// Images shared by several urls (see [deduplicate]) are only processed once.
+pub async fn process_images(
+    images: HashMap<String, InternedImage>,
+    options: Options,
+) -> Result<HashMap<String, InternedImage>, Box<dyn Error>> {
+    let workers = options
+        .image_workers
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, NonZeroUsize::get))
+        .max(1);
+
+    let mut urls_by_name: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut unique: BTreeMap<String, InternedImage> = BTreeMap::new();
+    for (url, image) in images {
+        let name = image.name();
+        urls_by_name.entry(name.clone()).or_default().push(url);
+        unique.entry(name).or_insert(image);
+    }
+
+    let semaphore = Arc::new(Semaphore::new(workers));
+    let mut tasks = JoinSet::new();
+    for (name, image) in unique {
+        let permit = semaphore.clone().acquire_owned().await?;
+        tasks.spawn_blocking(move || {
+            let _permit = permit;
+            // `Box<dyn Error>` is not `Send`.
+            (name, image.process(options).map_err(|e| e.to_string()))
+        });
+    }
+
+    let mut processed = HashMap::new();
+    while let Some(result) = tasks.join_next().await {
+        let (name, image) = result?;
+        let Some(image) = image? else {
+            // Left out, like images that failed to download.
+            log::info!("Leaving out {name}, which can't be converted to a supported format");
+            continue;
+        };
+        for url in urls_by_name.remove(&name).unwrap_or_default() {
+            processed.insert(url, image.clone());
+        }
+    }
+
+    Ok(processed)
+}
+
+fn deduplicate_and_log(
+    images: HashMap<String, InternedImage>,
+    options: Options,
+) -> HashMap<String, InternedImage> {
+    let (images, stats) = deduplicate(images, options.perceptual_dedup);
+
+    let DedupStats {
+        images: before,
+        unique,
+        bytes_saved,
+    } = stats;
+    if unique < before {
+        log::info!(
+            "Deduplicated {before} images into {unique}, saving {} KiB.",
+            bytes_saved / 1024
+        );
+    }
+
+    images
+}
+
+impl Thread {
+    /// Generates avatars for characters without an icon, and for icons that failed to download.
+    /// Must run after [Thread::icons_to_intern] so that `skip` contains the failed icons.
+    fn placeholders_to_intern(
+        &self,
+        interned_images: &mut HashMap<String, InternedImage>,
+        skip: &HashSet<String>,
+    ) {
+        for (character, icon) in self.avatars() {
+            let (url, name, seed) = match (icon, character) {
+                (Some(Icon { url: Some(url), .. }), _) if !skip.contains(url) => continue,
+                (Some(Icon { url: Some(url), .. }), Some(character)) => {
+                    (url.clone(), &*character.name, character.id)
+                }
+                (
+                    Some(Icon {
+                        id,
+                        keyword,
+                        url: Some(url),
+                    }),
+                    None,
+                ) => (url.clone(), keyword.as_deref().unwrap_or("?"), *id),
+                (_, Some(character)) => (
+                    placeholder_url(character.id),
+                    &*character.name,
+                    character.id,
+                ),
+                (_, None) => continue,
+            };
+
+            if interned_images.contains_key(&url) {
+                continue;
+            }
+
+            log::info!("Generating placeholder icon for {name} (url: {url}).");
+            interned_images.insert(
+                url.clone(),
+                InternedImage {
+                    id: None,
+                    original_url: url,
+                    mime: mime::IMAGE_PNG,
+                    data: cover::placeholder_icon(name, seed),
+                },
+            );
+        }
+    }
+}
+
+/// The url used to refer to the placeholder avatar of a character without an icon.
+///
+/// It points to the character page so that it stays meaningful if it ends up being inlined,
+/// but it is only ever resolved to a generated image (see [Options::placeholder_icons]).
+pub fn placeholder_url(character_id: u64) -> String {
+    format!("https://glowfic.com/characters/{character_id}#placeholder-icon")
+}
+
 impl Icon {
     async fn intern(&self) -> Result<InternedImage, Box<dyn Error>> {
         let (mime, data) = self.download_cached(false).await?;
@@ -254,3 +595,157 @@ impl Icon {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    /// A 9x8 image whose brightness changes by `step` from each column to the next.
+    fn gradient(step: i16) -> GrayImage {
+        GrayImage::from_fn(9, 8, |x, _| Luma([(128 + step * x as i16) as u8]))
+    }
+
+    fn interned(url: &str, image: &GrayImage, format: image::ImageFormat) -> InternedImage {
+        let mut data = vec![];
+        image.write_to(&mut Cursor::new(&mut data), format).unwrap();
+        InternedImage {
+            id: None,
+            original_url: url.to_string(),
+            mime: format.to_mime_type().parse().unwrap(),
+            data,
+        }
+    }
+
+    fn images(images: &[InternedImage]) -> HashMap<String, InternedImage> {
+        images
+            .iter()
+            .map(|image| (image.original_url.clone(), image.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn perceptual_hash_compares_neighbours() {
+        let darkening = interned("a", &gradient(-10), image::ImageFormat::Png);
+        let brightening = interned("b", &gradient(10), image::ImageFormat::Png);
+
+        assert_eq!(darkening.perceptual_hash(), Some(u64::MAX));
+        assert_eq!(brightening.perceptual_hash(), Some(0));
+    }
+
+    #[test]
+    fn same_pixels_are_merged() {
+        let png = interned("a", &gradient(10), image::ImageFormat::Png);
+        let bmp = interned("b", &gradient(10), image::ImageFormat::Bmp);
+        let other = interned("c", &gradient(-10), image::ImageFormat::Png);
+
+        let (deduplicated, stats) = deduplicate(images(&[png.clone(), bmp.clone(), other]), None);
+
+        assert_eq!(deduplicated["a"], deduplicated["b"]);
+        assert_ne!(deduplicated["a"], deduplicated["c"]);
+        assert_eq!(stats.images, 3);
+        assert_eq!(stats.unique, 2);
+        // The first by url is kept.
+        assert_eq!(deduplicated["b"].original_url, "a");
+        assert_eq!(stats.bytes_saved, bmp.data.len());
+    }
+
+    #[test]
+    fn perceptual_tolerance_is_inclusive() {
+        // Their hashes differ in all 64 bits.
+        let darkening = interned("a", &gradient(-10), image::ImageFormat::Png);
+        let brightening = interned("b", &gradient(10), image::ImageFormat::Png);
+        let both = images(&[darkening, brightening]);
+
+        let (_, stats) = deduplicate(both.clone(), None);
+        assert_eq!(stats.unique, 2);
+        let (_, stats) = deduplicate(both.clone(), Some(63));
+        assert_eq!(stats.unique, 2);
+        let (deduplicated, stats) = deduplicate(both, Some(64));
+        assert_eq!(stats.unique, 1);
+        assert_eq!(deduplicated["a"], deduplicated["b"]);
+    }
+
+    #[test]
+    fn similar_images_need_a_tolerance() {
+        let image = gradient(10);
+        let mut retouched = image.clone();
+        retouched.put_pixel(0, 0, Luma([129]));
+        let a = interned("a", &image, image::ImageFormat::Png);
+        let b = interned("b", &retouched, image::ImageFormat::Png);
+        assert_eq!(a.perceptual_hash(), b.perceptual_hash());
+
+        let (_, stats) = deduplicate(images(&[a.clone(), b.clone()]), None);
+        assert_eq!(stats.unique, 2);
+        let (_, stats) = deduplicate(images(&[a, b]), Some(0));
+        assert_eq!(stats.unique, 1);
+    }
+
+    #[test]
+    fn icons_are_not_merged_with_inline_images() {
+        let inline = interned("a", &gradient(10), image::ImageFormat::Png);
+        let icon = InternedImage {
+            id: Some(1),
+            ..interned("b", &gradient(10), image::ImageFormat::Png)
+        };
+        let other_icon = InternedImage {
+            id: Some(2),
+            ..interned("c", &gradient(10), image::ImageFormat::Bmp)
+        };
+
+        let (deduplicated, stats) = deduplicate(
+            images(&[inline.clone(), icon.clone(), other_icon]),
+            Some(64),
+        );
+        assert_eq!(stats.unique, 2);
+        assert_eq!(deduplicated["a"], inline);
+        assert_eq!(deduplicated["b"], icon);
+        assert_eq!(deduplicated["c"], icon);
+    }
+
+    #[test]
+    fn undecodable_images_are_compared_by_data() {
+        let svg = |url: &str, data: &str| InternedImage {
+            id: None,
+            original_url: url.to_string(),
+            mime: mime::IMAGE_SVG,
+            data: data.as_bytes().to_vec(),
+        };
+
+        let (_, stats) = deduplicate(
+            images(&[
+                svg("a", "<svg/>"),
+                svg("b", "<svg/>"),
+                svg("c", "<svg></svg>"),
+            ]),
+            Some(4),
+        );
+        assert_eq!(stats.unique, 2);
+    }
+
+    #[test]
+    fn conversions_never_panic() {
+        let broken = |extension: &str| InternedImage {
+            id: None,
+            original_url: format!("broken.{extension}"),
+            mime: extension_to_image_mime(extension).unwrap(),
+            data: b"not an image".to_vec(),
+        };
+
+        assert_eq!(broken("png").try_into_webp(), broken("png"));
+        assert_eq!(broken("png").try_into_avif(), broken("png"));
+        assert_eq!(broken("png").try_into_jpeg(), Some(broken("png")));
+        assert_eq!(broken("webp").into_common_format(), None);
+        assert_eq!(broken("avif").into_common_format(), None);
+    }
+
+    #[test]
+    fn jpegs_are_not_made_lossless() {
+        let jpeg = interned("a", &gradient(10), image::ImageFormat::Jpeg);
+        assert_eq!(jpeg.clone().try_into_webp(), jpeg);
+
+        let png = interned("a", &gradient(10), image::ImageFormat::Png);
+        assert_eq!(png.try_into_webp().mime.subtype().as_str(), "webp");
+    }
+}
@@ -1,16 +1,26 @@
+use chrono::{DateTime, Utc};
 use clap::Parser;
 use std::{
+    error::Error,
     fmt::Display,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
 use glowpub::{
     api::BoardPosts,
     cached::write_if_changed,
-    gen::Options,
+    gen::{
+        epub,
+        html::{HtmlPage, HtmlSite},
+        json::JsonExport,
+        tts::{TtsDetails, TtsOptions, VoiceMap},
+        DetailsMode, LanguageTag, Options,
+    },
     types::{Continuity, Section},
     Board, Thread,
 };
+use uuid::Uuid;
 
 const DEFAULT_OUTPUT_DIR: &str = "./books";
 
@@ -25,6 +35,11 @@ enum Command {
 
         #[command(flatten)]
         options: CliOptions,
+
+        /// Make the epub out of only the replies after this point, with a recap of the last reply read:
+        /// a reply id, a date like `2024-01-31T18:00:00Z`, or `last` for the replies posted since the last epub of the post.
+        #[clap(long)]
+        since: Option<Since>,
     },
     /// Download and process an entire board.
     Board {
@@ -35,9 +50,25 @@ enum Command {
         #[command(flatten)]
         options: CliOptions,
 
-        /// If enabled, the board will be processed into a single epub file instead of being split by post.
+        /// If enabled, the board will be processed into a single file per format instead of being split by post.
         #[clap(long)]
         single_file: bool,
+
+        /// If enabled, the html output will be a static website in a folder named after the board,
+        /// with an index, a page per section and a page per post, all linked together.
+        /// Ignored with `--single-file`.
+        #[clap(long)]
+        html_site: bool,
+
+        /// With `--single-file`, split the epub and kepub files into numbered volumes,
+        /// for boards too big for some readers to open.
+        #[clap(long)]
+        volume_split: Option<VolumeSplit>,
+
+        /// The most threads, replies, or bytes per volume, depending on `--volume-split`.
+        /// Defaults to "25" threads, "5000" replies, or "20000000" bytes.
+        #[clap(long)]
+        volume_split_limit: Option<usize>,
     },
 }
 impl Command {
@@ -58,7 +89,8 @@ struct CliOptions {
     #[clap(long)]
     text_to_speech: bool,
 
-    /// <details> tags can be hard to use on e-readers, this option forces them to always seem open.
+    /// <details> tags can be hard to use on e-readers, this option forces them to always seem open,
+    /// or turns them into pop-up footnotes in epubs.
     ///
     /// (Under the hood, it replaces the <details> tag with a <blockquote>, and <summary> with <p>,
     /// it also preprends `▼ ` to the <summary> tag to make it similar to an open <details> tag.)
@@ -71,15 +103,103 @@ struct CliOptions {
     #[clap(long)]
     jpeg: bool,
 
+    /// When inlining images, store them in this format.
+    /// Defaults to "compatible" for epubs, and to "original" for html files.
+    /// (Ignored if `--jpeg` is set.)
+    #[clap(long)]
+    image_format: Option<ImageFormat>,
+
     /// When inlining icons into the epub file, this will scale all icon images above the provided width down to that width.
     /// Defaults to "100" if no value is provided.
     /// (Does not affect SVGs or non-icon images.)
     #[clap(long)]
     resize_icons: Option<Option<u32>>,
 
+    /// When inlining images into the epub file, images that look the same will be merged into one.
+    /// The value is how many bits (out of 64) their perceptual hashes may differ by.
+    /// Defaults to "4" if no value is provided.
+    /// (Images with identical content are always merged.)
+    #[clap(long)]
+    perceptual_dedup: Option<Option<u32>>,
+
+    /// How many images to convert or resize in parallel.
+    /// Defaults to the number of available cores.
+    #[clap(long)]
+    image_workers: Option<usize>,
+
+    /// When inlining images into the epub file (or into html files, see `--html-images`),
+    /// generate an avatar with the character's initials for characters without an icon,
+    /// and for icons that could not be downloaded.
+    #[clap(long)]
+    placeholder_icons: bool,
+
+    /// How html (and json) files refer to images.
+    #[clap(long, default_value_t = HtmlImages::default())]
+    html_images: HtmlImages,
+
+    /// Add reader features to html files: a floating table of contents, jump-to-reply,
+    /// a dark theme toggle, collapsible long replies, and remembering the reading position.
+    /// (Uses javascript.)
+    #[clap(long)]
+    interactive_html: bool,
+
+    /// How the replies of a post are split into the pages (files) of epubs and the other paged formats.
+    /// Replies are never split themselves.
+    #[clap(long, default_value_t = PageSplit::default())]
+    page_split: PageSplit,
+
+    /// The most replies, bytes, or words per page, depending on `--page-split`.
+    /// Defaults to "30" replies, "250000" bytes, or "10000" words.
+    #[clap(long)]
+    page_split_limit: Option<usize>,
+
+    /// What the pages of replies are called in the table of contents of epubs and mobi files.
+    #[clap(long, default_value_t = PartLabels::default())]
+    part_labels: PartLabels,
+
+    /// List the pages of replies in the table of contents of board epubs and mobi files too,
+    /// nested under their post so that readers can collapse them.
+    #[clap(long)]
+    nested_toc: bool,
+
+    /// Number the links of epubs that lead to websites,
+    /// and list their urls at the end of each page, for readers that can't open links.
+    #[clap(long)]
+    link_footnotes: bool,
+
+    /// The version of epub (and kepub) files.
+    #[clap(long, default_value_t = EpubVersion::default())]
+    epub_version: EpubVersion,
+
+    /// The language of the generated files, as a BCP 47 tag like `en` or `pt-BR`.
+    #[clap(long, default_value_t = LanguageTag::default())]
+    language: LanguageTag,
+
+    /// A uuid to derive the identifiers of the books from, instead of the url namespace.
+    /// The identifiers stay the same as long as it does, so that readers treat updates as the same book.
+    #[clap(long)]
+    uuid_namespace: Option<Uuid>,
+
+    /// A json file assigning voices to characters for the `ssml` and `tts-script` output formats.
+    /// See the README for the format.
+    #[clap(long)]
+    tts_voices: Option<PathBuf>,
+
+    /// The pause after each post or reply in the `ssml` and `tts-script` output formats, in milliseconds.
+    #[clap(long, default_value_t = TtsOptions::default().pause_ms)]
+    tts_pause: u32,
+
+    /// What the `ssml` and `tts-script` output formats do with <details> tags (often spoilers).
+    #[clap(long, default_value_t = TtsDetailsOption::default())]
+    tts_details: TtsDetailsOption,
+
+    /// Have the narrator announce the speaker of each post or reply in the `ssml` and `tts-script` output formats.
+    #[clap(long)]
+    tts_announce_speakers: bool,
+
     /// Output files in this directory (e.g. `--output-dir=~/glowfic`).
     /// Note that this can flood the directory if used with `board` but without `--single-file`.
-    /// Files will be placed in format-specific subdirectories if this option is not set, or if `--output-format` is `both` (the default).
+    /// Files will be placed in format-specific subdirectories if this option is not set, or if several output formats are selected (like `both`, the default).
     #[clap(long)]
     output_dir: Option<PathBuf>,
 
@@ -88,8 +208,9 @@ struct CliOptions {
     output_dir_layout: OutputDirLayout,
 
     /// Determines which file-types are created by the program.
-    #[clap(long, default_value_t = OutputFormat::default())]
-    output_format: OutputFormat,
+    /// Several can be given, separated by commas (e.g. `--output-format=epub,markdown`).
+    #[clap(long, value_delimiter = ',', default_values_t = [OutputFormat::default()])]
+    output_format: Vec<OutputFormat>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
@@ -101,6 +222,226 @@ enum FlattenDetails {
     All,
     /// Only <details> tags in epubs will be flattened.
     Mixed,
+    /// <details> tags in epubs become links to footnotes holding their content,
+    /// which readers with pop-up footnotes only show when tapped. Html files keep them.
+    Footnotes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ImageFormat {
+    /// WebP images are converted to png, which all e-readers support, and AVIF images are not embedded.
+    Compatible,
+    /// Images are kept in their original format.
+    Original,
+    /// Bmp and png images are converted to lossless WebP (jpegs would get bigger).
+    Webp,
+    /// Images are converted to AVIF (except for gifs and svgs).
+    Avif,
+}
+impl From<ImageFormat> for glowpub::gen::ImageFormat {
+    fn from(value: ImageFormat) -> Self {
+        match value {
+            ImageFormat::Compatible => Self::Compatible,
+            ImageFormat::Original => Self::Original,
+            ImageFormat::Webp => Self::WebP,
+            ImageFormat::Avif => Self::Avif,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+enum HtmlImages {
+    /// The default option. Images are loaded from their original hosts.
+    #[default]
+    Remote,
+    /// Images are embedded in the html file, so it works offline.
+    Inline,
+    /// Images are saved in an `assets` folder next to the html file.
+    Assets,
+}
+impl Display for HtmlImages {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Remote => write!(f, "remote"),
+            Self::Inline => write!(f, "inline"),
+            Self::Assets => write!(f, "assets"),
+        }
+    }
+}
+impl From<HtmlImages> for glowpub::gen::HtmlImages {
+    fn from(value: HtmlImages) -> Self {
+        match value {
+            HtmlImages::Remote => Self::Remote,
+            HtmlImages::Inline => Self::Inline,
+            HtmlImages::Assets => Self::Assets,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+enum PageSplit {
+    /// The default option. Pages have the same number of replies.
+    #[default]
+    Replies,
+    /// Pages have at most a number of bytes, which keeps the files small enough for all readers.
+    Bytes,
+    /// Pages have at most a number of words.
+    Words,
+}
+impl Display for PageSplit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Replies => write!(f, "replies"),
+            Self::Bytes => write!(f, "bytes"),
+            Self::Words => write!(f, "words"),
+        }
+    }
+}
+impl PageSplit {
+    fn with_limit(self, limit: Option<usize>) -> glowpub::gen::PageSplit {
+        match self {
+            Self::Replies => glowpub::gen::PageSplit::Replies(limit.unwrap_or(30)),
+            Self::Bytes => glowpub::gen::PageSplit::Bytes(limit.unwrap_or(250_000)),
+            Self::Words => glowpub::gen::PageSplit::Words(limit.unwrap_or(10_000)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum VolumeSplit {
+    /// A volume per section, and one for the sectionless threads.
+    Sections,
+    /// Volumes have the same number of threads.
+    Threads,
+    /// Volumes have at most a number of replies.
+    Replies,
+    /// Volumes have at most a number of bytes, not counting images.
+    Bytes,
+}
+impl VolumeSplit {
+    fn with_limit(self, limit: Option<usize>) -> epub::VolumeSplit {
+        match self {
+            Self::Sections => epub::VolumeSplit::Sections,
+            Self::Threads => epub::VolumeSplit::Threads(limit.unwrap_or(25)),
+            Self::Replies => epub::VolumeSplit::Replies(limit.unwrap_or(5_000)),
+            Self::Bytes => epub::VolumeSplit::Bytes(limit.unwrap_or(20_000_000)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+enum PartLabels {
+    /// The default option. `Part 0`, `Part 1`, and so on.
+    #[default]
+    Numbered,
+    /// The numbers of the first and last replies, like `Replies 31–60`.
+    Replies,
+    /// When the first and last replies were written.
+    Dates,
+    /// The characters speaking.
+    Characters,
+}
+impl Display for PartLabels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Numbered => write!(f, "numbered"),
+            Self::Replies => write!(f, "replies"),
+            Self::Dates => write!(f, "dates"),
+            Self::Characters => write!(f, "characters"),
+        }
+    }
+}
+impl From<PartLabels> for glowpub::gen::PartLabels {
+    fn from(value: PartLabels) -> Self {
+        match value {
+            PartLabels::Numbered => Self::Numbered,
+            PartLabels::Replies => Self::Replies,
+            PartLabels::Dates => Self::Dates,
+            PartLabels::Characters => Self::Characters,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+enum EpubVersion {
+    /// EPUB 2, for older readers.
+    #[value(name = "2")]
+    Epub2,
+    /// The default option. EPUB 3, with landmarks, a page-list, and semantic markup.
+    #[default]
+    #[value(name = "3")]
+    Epub3,
+}
+impl Display for EpubVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Epub2 => write!(f, "2"),
+            Self::Epub3 => write!(f, "3"),
+        }
+    }
+}
+impl From<EpubVersion> for glowpub::gen::EpubVersion {
+    fn from(value: EpubVersion) -> Self {
+        match value {
+            EpubVersion::Epub2 => Self::Epub2,
+            EpubVersion::Epub3 => Self::Epub3,
+        }
+    }
+}
+
+/// Where the epub of `--since` starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Since {
+    Reply(u64),
+    Time(DateTime<Utc>),
+    /// Since the last epub of the post.
+    Last,
+}
+impl FromStr for Since {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == "last" {
+            Ok(Self::Last)
+        } else if let Ok(reply_id) = value.parse() {
+            Ok(Self::Reply(reply_id))
+        } else if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+            Ok(Self::Time(time.to_utc()))
+        } else {
+            Err(format!(
+                "expected a reply id, an rfc 3339 date, or `last`, found {value:?}"
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+enum TtsDetailsOption {
+    /// The default option. Both the summary and the content are read.
+    #[default]
+    Read,
+    /// Only the summary is read.
+    Summary,
+    /// The whole tag is skipped.
+    Skip,
+}
+impl Display for TtsDetailsOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Read => write!(f, "read"),
+            Self::Summary => write!(f, "summary"),
+            Self::Skip => write!(f, "skip"),
+        }
+    }
+}
+impl From<TtsDetailsOption> for TtsDetails {
+    fn from(value: TtsDetailsOption) -> Self {
+        match value {
+            TtsDetailsOption::Read => Self::Read,
+            TtsDetailsOption::Summary => Self::SummaryOnly,
+            TtsDetailsOption::Skip => Self::Skip,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
@@ -130,30 +471,127 @@ enum OutputFormat {
     Epub,
     /// Only HTML files will be created.
     Html,
+    /// Plain text files, wrapped to 80 columns.
+    Text,
+    /// CommonMark files.
+    Markdown,
+    /// Paginated PDF files, with the images embedded.
+    Pdf,
+    /// MOBI files for Kindles, with the images embedded.
+    Mobi,
+    /// Epub files for Kobo readers, named `.kepub.epub`.
+    Kepub,
+    /// FictionBook 2 files, with the images embedded.
+    Fb2,
+    /// Word documents, with the images embedded.
+    Docx,
+    /// Json files of the processed threads (or a single one for the whole board with `--single-file`),
+    /// for analysis tools.
+    Json,
+    /// Like `json`, but one thread per line, in a single file per board even without `--single-file`.
+    Ndjson,
+    /// SSML files for text-to-speech engines, one per post even with `--single-file`.
+    Ssml,
+    /// Json files listing what each speaker says, for text-to-speech engines without SSML support.
+    /// One per post even with `--single-file`.
+    TtsScript,
     /// No output files will be created.
     None,
 }
-impl OutputFormat {
-    fn epub(self) -> bool {
-        matches!(self, Self::Epub | Self::Both)
-    }
-    fn html(self) -> bool {
-        matches!(self, Self::Html | Self::Both)
-    }
-}
 impl Display for OutputFormat {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Both => write!(f, "both"),
             Self::Epub => write!(f, "epub"),
             Self::Html => write!(f, "html"),
+            Self::Text => write!(f, "text"),
+            Self::Markdown => write!(f, "markdown"),
+            Self::Pdf => write!(f, "pdf"),
+            Self::Mobi => write!(f, "mobi"),
+            Self::Kepub => write!(f, "kepub"),
+            Self::Fb2 => write!(f, "fb2"),
+            Self::Docx => write!(f, "docx"),
+            Self::Json => write!(f, "json"),
+            Self::Ndjson => write!(f, "ndjson"),
+            Self::Ssml => write!(f, "ssml"),
+            Self::TtsScript => write!(f, "tts-script"),
             Self::None => write!(f, "none"),
         }
     }
 }
 
+/// All the selected [OutputFormat]s.
+#[derive(Debug, Clone)]
+struct OutputFormats(Vec<OutputFormat>);
+impl OutputFormats {
+    fn epub(&self) -> bool {
+        self.any(|f| matches!(f, OutputFormat::Epub | OutputFormat::Both))
+    }
+    fn html(&self) -> bool {
+        self.any(|f| matches!(f, OutputFormat::Html | OutputFormat::Both))
+    }
+    fn text(&self) -> bool {
+        self.any(|f| f == OutputFormat::Text)
+    }
+    fn markdown(&self) -> bool {
+        self.any(|f| f == OutputFormat::Markdown)
+    }
+    fn pdf(&self) -> bool {
+        self.any(|f| f == OutputFormat::Pdf)
+    }
+    fn mobi(&self) -> bool {
+        self.any(|f| f == OutputFormat::Mobi)
+    }
+    fn kepub(&self) -> bool {
+        self.any(|f| f == OutputFormat::Kepub)
+    }
+    fn fb2(&self) -> bool {
+        self.any(|f| f == OutputFormat::Fb2)
+    }
+    fn docx(&self) -> bool {
+        self.any(|f| f == OutputFormat::Docx)
+    }
+    fn json(&self) -> bool {
+        self.any(|f| f == OutputFormat::Json)
+    }
+    fn ndjson(&self) -> bool {
+        self.any(|f| f == OutputFormat::Ndjson)
+    }
+    fn ssml(&self) -> bool {
+        self.any(|f| f == OutputFormat::Ssml)
+    }
+    fn tts_script(&self) -> bool {
+        self.any(|f| f == OutputFormat::TtsScript)
+    }
+    /// How many kinds of files will be created.
+    fn count(&self) -> usize {
+        [
+            self.epub(),
+            self.html(),
+            self.text(),
+            self.markdown(),
+            self.pdf(),
+            self.mobi(),
+            self.kepub(),
+            self.fb2(),
+            self.docx(),
+            self.json(),
+            self.ndjson(),
+            self.ssml(),
+            self.tts_script(),
+        ]
+        .into_iter()
+        .filter(|selected| *selected)
+        .count()
+    }
+
+    fn any(&self, f: impl Fn(OutputFormat) -> bool) -> bool {
+        self.0.iter().copied().any(f)
+    }
+}
+
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<(), Box<dyn Error>> {
     simple_logger::init_with_level(log::Level::Info).unwrap();
 
     let command = Command::parse();
@@ -163,47 +601,125 @@ async fn main() {
         text_to_speech,
         flatten_details,
         jpeg,
+        image_format,
         resize_icons,
+        perceptual_dedup,
+        image_workers,
+        placeholder_icons,
+        html_images,
+        interactive_html,
+        page_split,
+        page_split_limit,
+        part_labels,
+        nested_toc,
+        link_footnotes,
+        epub_version,
+        language,
+        uuid_namespace,
+        tts_voices,
+        tts_pause,
+        tts_details,
+        tts_announce_speakers,
         output_dir,
         output_dir_layout,
         output_format,
     } = command.options();
 
     let resize_icons = resize_icons.map(|r| r.unwrap_or(100));
+    let perceptual_dedup = perceptual_dedup.map(|d| d.unwrap_or(4));
 
-    let mut html_output_dir = output_dir
-        .clone()
-        .unwrap_or_else(|| PathBuf::from(DEFAULT_OUTPUT_DIR));
-    let mut epub_output_dir = output_dir
-        .clone()
-        .unwrap_or_else(|| PathBuf::from(DEFAULT_OUTPUT_DIR));
-
-    if output_format == OutputFormat::Both || output_dir.is_none() {
-        html_output_dir = html_output_dir.join("html/");
-        epub_output_dir = epub_output_dir.join("epub/");
-    }
+    let output_format = OutputFormats(output_format);
+    let format_output_dir = |subdir: &str| {
+        let dir = output_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_OUTPUT_DIR));
+        if output_format.count() > 1 || output_dir.is_none() {
+            dir.join(subdir)
+        } else {
+            dir
+        }
+    };
+    let html_output_dir = format_output_dir("html/");
+    let epub_output_dir = format_output_dir("epub/");
+    let text_output_dir = format_output_dir("text/");
+    let markdown_output_dir = format_output_dir("markdown/");
+    let pdf_output_dir = format_output_dir("pdf/");
+    let mobi_output_dir = format_output_dir("mobi/");
+    let kepub_output_dir = format_output_dir("kepub/");
+    let fb2_output_dir = format_output_dir("fb2/");
+    let docx_output_dir = format_output_dir("docx/");
+    let json_output_dir = format_output_dir("json/");
+    let page_split = page_split.with_limit(page_split_limit);
+    let tts_output_dirs = TtsOutputDirs {
+        ssml: format_output_dir("ssml/"),
+        script: format_output_dir("tts/"),
+    };
 
     let epub_options = Options {
         text_to_speech,
-        flatten_details: match flatten_details.unwrap_or_default() {
-            FlattenDetails::All | FlattenDetails::Mixed => true,
-            FlattenDetails::None => false,
+        details: match flatten_details.unwrap_or_default() {
+            FlattenDetails::All | FlattenDetails::Mixed => DetailsMode::Flatten,
+            FlattenDetails::Footnotes => DetailsMode::Footnotes,
+            FlattenDetails::None => DetailsMode::Keep,
         },
         jpeg,
         resize_icons,
+        perceptual_dedup,
+        image_workers,
+        placeholder_icons,
+        image_format: image_format.unwrap_or(ImageFormat::Compatible).into(),
+        html_images: html_images.into(),
+        interactive_html,
+        kepub: false,
+        page_split,
+        part_labels: part_labels.into(),
+        nested_toc,
+        link_footnotes,
+        epub_version: epub_version.into(),
+        language,
+        uuid_namespace,
     };
     let html_options = Options {
         text_to_speech,
-        flatten_details: match flatten_details.unwrap_or_default() {
-            FlattenDetails::All => true,
-            FlattenDetails::None | FlattenDetails::Mixed => false,
+        details: match flatten_details.unwrap_or_default() {
+            FlattenDetails::All => DetailsMode::Flatten,
+            FlattenDetails::None | FlattenDetails::Mixed | FlattenDetails::Footnotes => {
+                DetailsMode::Keep
+            }
         },
         jpeg,
         resize_icons,
+        perceptual_dedup,
+        image_workers,
+        // Placeholders only exist locally.
+        placeholder_icons: placeholder_icons && html_images != HtmlImages::Remote,
+        image_format: image_format.unwrap_or(ImageFormat::Original).into(),
+        html_images: html_images.into(),
+        interactive_html,
+        kepub: false,
+        page_split,
+        part_labels: part_labels.into(),
+        nested_toc,
+        link_footnotes,
+        epub_version: epub_version.into(),
+        language,
+        uuid_namespace,
+    };
+
+    let tts_options = TtsOptions {
+        voices: tts_voices
+            .map(|path| {
+                let json = std::fs::read_to_string(path).unwrap();
+                serde_json::from_str::<VoiceMap>(&json).unwrap()
+            })
+            .unwrap_or_default(),
+        pause_ms: tts_pause,
+        details: tts_details.into(),
+        announce_speakers: tts_announce_speakers,
     };
 
     match command {
-        Command::Post { post_id, .. } => {
+        Command::Post { post_id, since, .. } => {
             log::info!("Downloading post {post_id}");
             let thread = Thread::get_cached(post_id, !use_cache)
                 .await
@@ -236,18 +752,114 @@ async fn main() {
             if output_format.html() {
                 log::info!("Generating html document {name}...");
                 let path = html_output_dir.join(format!("{name}.html"));
-                write(path, thread.to_single_html_page(html_options));
+                write_html_page(path, thread.to_html(html_options).await.unwrap());
             }
 
+            let since = match since {
+                Some(Since::Reply(reply_id)) => Some(epub::Since::Reply(reply_id)),
+                Some(Since::Time(time)) => Some(epub::Since::Time(time)),
+                Some(Since::Last) => {
+                    let since = Thread::since_last_generation(post_id);
+                    if since.is_none() {
+                        log::info!(
+                            "No epub of post {post_id} was written yet, including all of it"
+                        );
+                    }
+                    since
+                }
+                None => None,
+            };
+
             if output_format.epub() {
-                log::info!("Generating epub document {name}...");
-                let path = epub_output_dir.join(format!("{name}.epub"));
-                write(path, thread.to_epub(epub_options).await.unwrap());
+                match since {
+                    None => {
+                        log::info!("Generating epub document {name}...");
+                        let path = epub_output_dir.join(format!("{name}.epub"));
+                        write(path, thread.to_epub(epub_options).await.unwrap());
+                        thread.record_generation()?;
+                    }
+                    Some(since) => {
+                        log::info!("Generating epub document {name} of the new replies...");
+                        match thread.to_delta_epub(epub_options, since).await.unwrap() {
+                            Some(epub) => {
+                                let path =
+                                    epub_output_dir.join(format!("{name} [new replies].epub"));
+                                write(path, epub);
+                                thread.record_generation()?;
+                            }
+                            None => log::info!("No new replies in post {post_id}"),
+                        }
+                    }
+                }
             }
+
+            if output_format.kepub() {
+                log::info!("Generating kepub document {name}...");
+                let path = kepub_output_dir.join(format!("{name}.kepub.epub"));
+                write(path, thread.to_kepub(epub_options).await.unwrap());
+            }
+
+            if output_format.fb2() {
+                log::info!("Generating fb2 document {name}...");
+                let path = fb2_output_dir.join(format!("{name}.fb2"));
+                write(path, thread.to_fb2(epub_options).await.unwrap());
+            }
+
+            if output_format.docx() {
+                log::info!("Generating docx document {name}...");
+                let path = docx_output_dir.join(format!("{name}.docx"));
+                write(path, thread.to_docx(epub_options).await.unwrap());
+            }
+
+            if output_format.json() {
+                log::info!("Generating json document {name}...");
+                let path = json_output_dir.join(format!("{name}.json"));
+                write_json_export(path, thread.to_json(html_options).await.unwrap());
+            }
+
+            if output_format.ndjson() {
+                log::info!("Generating ndjson document {name}...");
+                let path = json_output_dir.join(format!("{name}.ndjson"));
+                write_json_export(path, thread.to_ndjson(html_options).await.unwrap());
+            }
+
+            if output_format.text() {
+                log::info!("Generating text document {name}...");
+                let path = text_output_dir.join(format!("{name}.txt"));
+                write(path, thread.to_text(html_options));
+            }
+
+            if output_format.markdown() {
+                log::info!("Generating markdown document {name}...");
+                let path = markdown_output_dir.join(format!("{name}.md"));
+                write(path, thread.to_markdown(html_options));
+            }
+
+            if output_format.pdf() {
+                log::info!("Generating pdf document {name}...");
+                let path = pdf_output_dir.join(format!("{name}.pdf"));
+                write(path, thread.to_pdf(epub_options).await.unwrap());
+            }
+
+            if output_format.mobi() {
+                log::info!("Generating mobi document {name}...");
+                let path = mobi_output_dir.join(format!("{name}.mobi"));
+                write(path, thread.to_mobi(epub_options).await.unwrap());
+            }
+
+            write_tts(
+                &thread,
+                &name,
+                &output_format,
+                &tts_output_dirs,
+                html_options,
+                &tts_options,
+            );
         }
         Command::Board {
             board_id,
             single_file: false,
+            html_site,
             ..
         } => {
             log::info!("Downloading board/continuity {board_id}...");
@@ -263,6 +875,20 @@ async fn main() {
             log::info!("Caching all the icons...");
             continuity.cache_all_icons(false).await;
 
+            if output_format.html() && html_site {
+                let name = board_filename(&continuity.board);
+                log::info!("Generating html site {name}...");
+                let dir = html_output_dir.join(&name);
+                write_html_site(dir, continuity.to_html_site(html_options).await.unwrap());
+            }
+
+            if output_format.ndjson() {
+                let name = board_filename(&continuity.board);
+                log::info!("Generating ndjson document {name}...");
+                let path = json_output_dir.join(format!("{name}.ndjson"));
+                write_json_export(path, continuity.to_ndjson(html_options).await.unwrap());
+            }
+
             for thread in &continuity.threads {
                 let name = thread_filename(
                     thread,
@@ -271,10 +897,10 @@ async fn main() {
                     output_dir_layout,
                 );
 
-                if output_format.html() {
+                if output_format.html() && !html_site {
                     log::info!("Generating html document {name}...");
                     let path = html_output_dir.join(format!("{name}.html"));
-                    write(path, thread.to_single_html_page(html_options));
+                    write_html_page(path, thread.to_html(html_options).await.unwrap());
                 }
 
                 if output_format.epub() {
@@ -282,20 +908,72 @@ async fn main() {
                     let path = epub_output_dir.join(format!("{name}.epub"));
                     write(path, thread.to_epub(epub_options).await.unwrap());
                 }
+
+                if output_format.kepub() {
+                    log::info!("Generating kepub document {name}...");
+                    let path = kepub_output_dir.join(format!("{name}.kepub.epub"));
+                    write(path, thread.to_kepub(epub_options).await.unwrap());
+                }
+
+                if output_format.fb2() {
+                    log::info!("Generating fb2 document {name}...");
+                    let path = fb2_output_dir.join(format!("{name}.fb2"));
+                    write(path, thread.to_fb2(epub_options).await.unwrap());
+                }
+
+                if output_format.docx() {
+                    log::info!("Generating docx document {name}...");
+                    let path = docx_output_dir.join(format!("{name}.docx"));
+                    write(path, thread.to_docx(epub_options).await.unwrap());
+                }
+
+                if output_format.json() {
+                    log::info!("Generating json document {name}...");
+                    let path = json_output_dir.join(format!("{name}.json"));
+                    write_json_export(path, thread.to_json(html_options).await.unwrap());
+                }
+
+                if output_format.text() {
+                    log::info!("Generating text document {name}...");
+                    let path = text_output_dir.join(format!("{name}.txt"));
+                    write(path, thread.to_text(html_options));
+                }
+
+                if output_format.markdown() {
+                    log::info!("Generating markdown document {name}...");
+                    let path = markdown_output_dir.join(format!("{name}.md"));
+                    write(path, thread.to_markdown(html_options));
+                }
+
+                if output_format.pdf() {
+                    log::info!("Generating pdf document {name}...");
+                    let path = pdf_output_dir.join(format!("{name}.pdf"));
+                    write(path, thread.to_pdf(epub_options).await.unwrap());
+                }
+
+                if output_format.mobi() {
+                    log::info!("Generating mobi document {name}...");
+                    let path = mobi_output_dir.join(format!("{name}.mobi"));
+                    write(path, thread.to_mobi(epub_options).await.unwrap());
+                }
+
+                write_tts(
+                    thread,
+                    &name,
+                    &output_format,
+                    &tts_output_dirs,
+                    html_options,
+                    &tts_options,
+                );
             }
         }
         Command::Board {
             board_id,
             single_file: true,
+            volume_split,
+            volume_split_limit,
             ..
         } => {
-            if output_format.html() {
-                log::warn!("HTML output is not supported in single-file mode.");
-                if output_format == OutputFormat::Html {
-                    return;
-                }
-            }
-
             log::info!("Downloading board/continuity {board_id}...");
             let continuity = Continuity::get_cached(board_id, !use_cache)
                 .await
@@ -309,19 +987,130 @@ async fn main() {
             log::info!("Caching all the icons...");
             continuity.cache_all_icons(false).await;
 
-            let name = {
-                let board_id = continuity.board.id;
-                let name = slug::slugify(&continuity.board.name);
-                format!("[{board_id}] {name}")
-            };
+            let name = board_filename(&continuity.board);
+
+            if output_format.html() {
+                log::info!("Generating html document {name}...");
+                let path = html_output_dir.join(format!("{name}.html"));
+                write_html_page(path, continuity.to_html(html_options).await.unwrap());
+            }
+
+            let volume_split =
+                volume_split.map(|volume_split| volume_split.with_limit(volume_split_limit));
+
+            if output_format.epub() {
+                log::info!("Generating epub document {name}...");
+                match volume_split {
+                    None => {
+                        let path = epub_output_dir.join(format!("{name}.epub"));
+                        write(path, continuity.to_epub(epub_options).await.unwrap());
+                    }
+                    Some(volume_split) => write_volumes(
+                        &epub_output_dir,
+                        &name,
+                        "epub",
+                        continuity
+                            .to_epub_volumes(epub_options, volume_split)
+                            .await
+                            .unwrap(),
+                    ),
+                }
+            }
+
+            if output_format.kepub() {
+                log::info!("Generating kepub document {name}...");
+                match volume_split {
+                    None => {
+                        let path = kepub_output_dir.join(format!("{name}.kepub.epub"));
+                        write(path, continuity.to_kepub(epub_options).await.unwrap());
+                    }
+                    Some(volume_split) => write_volumes(
+                        &kepub_output_dir,
+                        &name,
+                        "kepub.epub",
+                        continuity
+                            .to_kepub_volumes(epub_options, volume_split)
+                            .await
+                            .unwrap(),
+                    ),
+                }
+            }
+
+            if output_format.fb2() {
+                log::info!("Generating fb2 document {name}...");
+                let path = fb2_output_dir.join(format!("{name}.fb2"));
+                write(path, continuity.to_fb2(epub_options).await.unwrap());
+            }
 
-            log::info!("Generating epub document {name}...");
-            let path = epub_output_dir.join(format!("{name}.epub"));
-            write(path, continuity.to_epub(epub_options).await.unwrap());
+            if output_format.docx() {
+                log::info!("Generating docx document {name}...");
+                let path = docx_output_dir.join(format!("{name}.docx"));
+                write(path, continuity.to_docx(epub_options).await.unwrap());
+            }
+
+            if output_format.json() {
+                log::info!("Generating json document {name}...");
+                let path = json_output_dir.join(format!("{name}.json"));
+                write_json_export(path, continuity.to_json(html_options).await.unwrap());
+            }
+
+            if output_format.ndjson() {
+                log::info!("Generating ndjson document {name}...");
+                let path = json_output_dir.join(format!("{name}.ndjson"));
+                write_json_export(path, continuity.to_ndjson(html_options).await.unwrap());
+            }
+
+            if output_format.text() {
+                log::info!("Generating text document {name}...");
+                let path = text_output_dir.join(format!("{name}.txt"));
+                write(path, continuity.to_text(html_options));
+            }
+
+            if output_format.markdown() {
+                log::info!("Generating markdown document {name}...");
+                let path = markdown_output_dir.join(format!("{name}.md"));
+                write(path, continuity.to_markdown(html_options));
+            }
+
+            if output_format.pdf() {
+                log::info!("Generating pdf document {name}...");
+                let path = pdf_output_dir.join(format!("{name}.pdf"));
+                write(path, continuity.to_pdf(epub_options).await.unwrap());
+            }
+
+            if output_format.mobi() {
+                log::info!("Generating mobi document {name}...");
+                let path = mobi_output_dir.join(format!("{name}.mobi"));
+                write(path, continuity.to_mobi(epub_options).await.unwrap());
+            }
+
+            for thread in &continuity.threads {
+                let name = thread_filename(
+                    thread,
+                    &continuity.board,
+                    continuity.threads.iter().map(|t| t.post.section.clone()),
+                    output_dir_layout,
+                );
+                write_tts(
+                    thread,
+                    &name,
+                    &output_format,
+                    &tts_output_dirs,
+                    html_options,
+                    &tts_options,
+                );
+            }
         }
     }
 
     log::info!("Done");
+    Ok(())
+}
+
+fn board_filename(board: &Board) -> String {
+    let board_id = board.id;
+    let name = slug::slugify(&board.name);
+    format!("[{board_id}] {name}")
 }
 
 fn thread_filename(
@@ -370,6 +1159,79 @@ fn thread_filename(
     )
 }
 
+struct TtsOutputDirs {
+    ssml: PathBuf,
+    script: PathBuf,
+}
+
+/// Text-to-speech files are always written per thread, as each is meant to become an audio file.
+fn write_tts(
+    thread: &Thread,
+    name: &str,
+    output_format: &OutputFormats,
+    dirs: &TtsOutputDirs,
+    options: Options,
+    tts_options: &TtsOptions,
+) {
+    if output_format.ssml() {
+        log::info!("Generating ssml document {name}...");
+        let path = dirs.ssml.join(format!("{name}.ssml"));
+        write(path, thread.to_ssml(options, tts_options));
+    }
+
+    if output_format.tts_script() {
+        log::info!("Generating text-to-speech script {name}...");
+        let path = dirs.script.join(format!("{name}.json"));
+        write(path, thread.to_tts_script(options, tts_options));
+    }
+}
+
+/// Writes the page, and its assets relative to it.
+fn write_html_page(path: impl AsRef<Path>, page: HtmlPage) {
+    let HtmlPage { html, assets } = page;
+    for (asset_path, data) in assets {
+        write(path.as_ref().parent().unwrap().join(asset_path), data);
+    }
+    write(path, html);
+}
+
+/// Writes the json and its assets next to it.
+fn write_json_export(path: impl AsRef<Path>, export: JsonExport) {
+    let JsonExport { json, assets } = export;
+    for (asset_path, data) in assets {
+        write(path.as_ref().parent().unwrap().join(asset_path), data);
+    }
+    write(path, json);
+}
+
+/// Writes the pages and their assets in the directory.
+fn write_html_site(dir: impl AsRef<Path>, site: HtmlSite) {
+    let HtmlSite { pages, assets } = site;
+    for (path, data) in assets {
+        write(dir.as_ref().join(path), data);
+    }
+    for (path, html) in pages {
+        write(dir.as_ref().join(path), html);
+    }
+}
+
+/// Writes the numbered volumes of a book in the directory.
+fn write_volumes(dir: impl AsRef<Path>, name: &str, extension: &str, volumes: Vec<Vec<u8>>) {
+    if let [book] = &volumes[..] {
+        write(dir.as_ref().join(format!("{name}.{extension}")), book);
+        return;
+    }
+
+    let width = Ord::max(volumes.len().to_string().len(), 2);
+    for (i, volume) in volumes.into_iter().enumerate() {
+        let number = i + 1;
+        let path = dir
+            .as_ref()
+            .join(format!("{name} - Volume {number:0width$}.{extension}"));
+        write(path, volume);
+    }
+}
+
 pub fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) {
     std::fs::create_dir_all(path.as_ref().parent().unwrap()).unwrap();
     write_if_changed(path, contents).unwrap();
@@ -42,3 +42,32 @@ impl serde::de::Visitor<'_> for Rfc3339Visitor {
         self.visit_str(std::str::from_utf8(v).map_err(E::custom)?)
     }
 }
+
+/// The same, for optional timestamps (`#[serde(default, with = "crate::rfc3339::option")]`).
+pub mod option {
+    use chrono::{DateTime, Utc};
+    use serde::Deserialize;
+
+    pub fn serialize<S>(dt: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        match dt {
+            Some(dt) => super::serialize(dt, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> Result<Option<DateTime<Utc>>, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        Option::<String>::deserialize(d)?
+            .map(|v| {
+                DateTime::parse_from_rfc3339(&v)
+                    .map(From::from)
+                    .map_err(serde::de::Error::custom)
+            })
+            .transpose()
+    }
+}
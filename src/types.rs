@@ -155,6 +155,15 @@ mod helpers {
 
             (sections, sectionless_threads)
         }
+        /// All threads in reading order: by section, then the sectionless ones.
+        pub fn threads_in_order(&self) -> Vec<&Thread> {
+            let (sections, sectionless_threads) = self.sections();
+            sections
+                .into_iter()
+                .flat_map(|(_, threads)| threads)
+                .chain(sectionless_threads)
+                .collect()
+        }
     }
     impl Thread {
         pub fn image_urls(&self) -> HashSet<String> {
@@ -4,7 +4,7 @@ use image::ImageReader;
 use mime::Mime;
 use sha2::{Digest, Sha256};
 
-use crate::types::{Icon, Thread};
+use crate::types::{Character, Icon, Thread};
 
 const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
@@ -14,6 +14,14 @@ impl Thread {
             .flatten()
             .chain(self.replies.iter().flat_map(|r| r.icon.as_ref()))
     }
+    /// The character and icon of the post and of every reply.
+    pub fn avatars(&self) -> impl Iterator<Item = (Option<&Character>, Option<&Icon>)> {
+        std::iter::once((self.post.character.as_ref(), self.post.icon.as_ref())).chain(
+            self.replies
+                .iter()
+                .map(|r| (r.character.as_ref(), r.icon.as_ref())),
+        )
+    }
 }
 
 pub fn mime_to_image_extension(mime: &Mime) -> Option<String> {
@@ -24,6 +32,7 @@ pub fn mime_to_image_extension(mime: &Mime) -> Option<String> {
         (mime::IMAGE, mime::PNG) => Some("png"),
         (mime::IMAGE, mime::SVG) => Some("svg"),
         (mime::IMAGE, subtype) if subtype.as_str() == "webp" => Some("webp"),
+        (mime::IMAGE, subtype) if subtype.as_str() == "avif" => Some("avif"),
         _ => None,
     }
     .map(str::to_string)
@@ -48,6 +57,7 @@ pub fn extension_to_image_mime(extension: &str) -> Option<Mime> {
         "png" => Some(mime::IMAGE_PNG),
         "svg" => Some(mime::IMAGE_SVG),
         "webp" => Some(Mime::from_str("image/webp").unwrap()),
+        "avif" => Some(Mime::from_str("image/avif").unwrap()),
         _ => None,
     }
 }